@@ -1,53 +1,144 @@
 //! Contains the [`Acct`] struct representing an account.
 
 use std::error::Error;
+use std::fmt;
 
-#[derive(Debug, Default, PartialEq)]
+use serde::Serialize;
+
+use crate::amount::Amount;
+use crate::ledger::{Entry, Ledger, LedgerAcct};
+
+/// The client an [`Acct`] stands for in the ledger its own methods post to.
+const CLIENT: u16 = 0;
+
+/// The reasons an [`Acct`] can refuse to move funds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AcctError {
+    /// Deposits and withdrawals must be for a positive amount.
+    NonPositiveAmount,
+    /// There are not enough available funds for a withdrawal.
+    InsufficientFunds,
+    /// The resulting balance would be too large to represent.
+    Overflow,
+}
+
+impl fmt::Display for AcctError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AcctError::NonPositiveAmount => write!(f, "amount must be positive"),
+            AcctError::InsufficientFunds => write!(f, "funds not available for withdrawal"),
+            AcctError::Overflow => write!(f, "balance overflow"),
+        }
+    }
+}
+
+impl Error for AcctError {}
+
+/// Why an account is locked.
+///
+/// The reasons are ordered by severity, and a more severe lock replaces a less
+/// severe one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LockReason {
+    /// An administrator froze the account.
+    Frozen,
+    /// A chargeback was processed for the account.
+    Chargeback,
+    /// An administrator closed the account (which can't be undone).
+    Closed,
+}
+
+impl fmt::Display for LockReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            LockReason::Frozen => "frozen",
+            LockReason::Chargeback => "chargeback",
+            LockReason::Closed => "closed",
+        })
+    }
+}
+
+/// The state of a client's account.
+#[derive(Debug, Default, Clone, PartialEq)]
 pub struct Acct {
-    pub available: f64,
-    pub held: f64,
-    pub total: f64,
-    pub locked: bool,
+    /// The funds available for withdrawal.
+    pub available: Amount,
+    /// The funds held by disputes.
+    pub held: Amount,
+    /// The available and held funds together (always their sum).
+    pub total: Amount,
+    /// Why the account is locked, if it is.
+    pub lock: Option<LockReason>,
 }
 
 impl Acct {
-    pub fn deposit(&mut self, amt: f64) -> Result<(), Box<dyn Error>> {
-        if amt > 0.0 {
-            self.total += amt;
-            self.available += amt;
-            Ok(())
-        } else {
-            Err("cannot deposit negative funds".into())
-        }
+    /// Returns whether the account is locked (for any reason).
+    pub fn locked(&self) -> bool {
+        self.lock.is_some()
+    }
+
+    /// Returns how much credit the account is using, i.e. how far its available
+    /// funds are below zero.
+    pub fn credit_used(&self) -> Amount {
+        // the smallest balance has no positive counterpart, so it saturates instead
+        let used = self.available.checked_neg().unwrap_or(Amount::from_raw(i64::MAX));
+        used.max(Amount::ZERO)
     }
 
-    pub fn withdrawal(&mut self, amt: f64) -> Result<(), Box<dyn Error>> {
-        if self.available < amt {
-            return Err("funds not available for withdrawal".into());
+    /// Adds a positive amount to the available funds.
+    pub fn deposit(&mut self, amt: Amount) -> Result<(), AcctError> {
+        if !amt.is_positive() {
+            return Err(AcctError::NonPositiveAmount);
         }
-        if amt > 0.0 {
-            self.total -= amt;
-            self.available -= amt;
-            Ok(())
-        } else {
-            Err("cannot withdrawal negative funds".into())
+        self.post(Entry::new(LedgerAcct::External, LedgerAcct::Available(CLIENT), amt), None)
+    }
+
+    /// Removes a positive amount from the available funds, if there's enough.
+    pub fn withdrawal(&mut self, amt: Amount) -> Result<(), AcctError> {
+        if !amt.is_positive() {
+            return Err(AcctError::NonPositiveAmount);
         }
+        self.post(Entry::new(LedgerAcct::Available(CLIENT), LedgerAcct::External, amt), Some(Amount::ZERO))
+    }
+
+    /// Moves an amount from the available funds to the held funds.
+    pub fn dispute(&mut self, amt: Amount) -> Result<(), AcctError> {
+        self.post(Entry::new(LedgerAcct::Available(CLIENT), LedgerAcct::Held(CLIENT), amt), None)
+    }
+
+    /// Moves an amount from the held funds back to the available funds.
+    pub fn resolve(&mut self, amt: Amount) -> Result<(), AcctError> {
+        self.post(Entry::new(LedgerAcct::Held(CLIENT), LedgerAcct::Available(CLIENT), amt), None)
     }
 
-    pub fn dispute(&mut self, amt: f64) {
-        self.available -= amt;
-        self.held += amt;
+    /// Removes an amount from the held funds and locks the account (unless it's
+    /// already locked for a more severe reason).
+    pub fn chargeback(&mut self, amt: Amount) -> Result<(), AcctError> {
+        self.post(Entry::new(LedgerAcct::Held(CLIENT), LedgerAcct::Settlement, amt), None)?;
+        self.lock = self.lock.max(Some(LockReason::Chargeback));
+        Ok(())
     }
 
-    pub fn resolve(&mut self, amt: f64) {
-        self.available += amt;
-        self.held -= amt;
+    /// Posts an entry to a ledger holding only this account, taking on the
+    /// balances it leaves the account with (see [`Ledger::acct`]).
+    fn post(&mut self, entry: Option<Entry>, floor: Option<Amount>) -> Result<(), AcctError> {
+        let entry = entry.ok_or(AcctError::Overflow)?;
+        let mut ledger = Ledger::default();
+        ledger.set(LedgerAcct::Available(CLIENT), self.available);
+        ledger.set(LedgerAcct::Held(CLIENT), self.held);
+        let posting = ledger.stage(&[entry], |acct| acct.client() == Some(CLIENT))?;
+        *self = ledger.acct(&posting, CLIENT, self.lock, floor)?;
+        Ok(())
     }
 
-    pub fn chargeback(&mut self, amt: f64) {
-        self.held -= amt;
-        self.total -= amt;
-        self.locked = true;
+    /// Sets the available and held funds, deriving the total from them. An overflow
+    /// leaves the account untouched.
+    pub(crate) fn set(&mut self, available: Amount, held: Amount) -> Result<(), AcctError> {
+        self.total = available.checked_add(held).ok_or(AcctError::Overflow)?;
+        self.available = available;
+        self.held = held;
+        Ok(())
     }
 }
 
@@ -61,60 +152,89 @@ impl Acct {
 mod test {
     use super::*;
 
+    fn amt(s: &str) -> Amount {
+        s.parse().unwrap()
+    }
+
     #[test]
     fn deposit() {
         let mut acct = Acct::default();
 
-        assert!(acct.deposit(1.0).is_ok());
-        assert!(acct.deposit(0.0).is_err());
-        assert!(acct.deposit(-1.0).is_err());
+        assert_eq!(Ok(()), acct.deposit(amt("1.0")));
+        assert_eq!(Err(AcctError::NonPositiveAmount), acct.deposit(amt("0.0")));
+        assert_eq!(Err(AcctError::NonPositiveAmount), acct.deposit(amt("-1.0")));
 
-        assert_eq!(Acct{ available: 1.0, held: 0.0, total: 1.0, locked: false }, acct);
+        assert_eq!(Acct{ available: amt("1.0"), held: amt("0.0"), total: amt("1.0"), lock: None }, acct);
     }
 
     #[test]
     fn withdrawal() {
         let mut acct = Acct::default();
-        _ = acct.deposit(1.0);
+        _ = acct.deposit(amt("1.0"));
 
-        assert!(acct.withdrawal(0.5).is_ok());
-        assert!(acct.withdrawal(1.0).is_err());
-        assert!(acct.withdrawal(0.0).is_err());
-        assert!(acct.withdrawal(-1.0).is_err());
+        assert_eq!(Ok(()), acct.withdrawal(amt("0.5")));
+        assert_eq!(Err(AcctError::InsufficientFunds), acct.withdrawal(amt("1.0")));
+        assert_eq!(Err(AcctError::NonPositiveAmount), acct.withdrawal(amt("0.0")));
+        assert_eq!(Err(AcctError::NonPositiveAmount), acct.withdrawal(amt("-1.0")));
 
-        assert_eq!(Acct{ available: 0.5, held: 0.0, total: 0.5, locked: false }, acct);
+        assert_eq!(Acct{ available: amt("0.5"), held: amt("0.0"), total: amt("0.5"), lock: None }, acct);
     }
 
     #[test]
     fn dispute_deposit() {
         let mut acct = Acct::default();
-        _ = acct.deposit(1.0);
+        _ = acct.deposit(amt("1.0"));
 
-        acct.dispute(1.0);
-        assert_eq!(Acct{ available: 0.0, held: 1.0, total: 1.0, locked: false }, acct);
+        assert!(acct.dispute(amt("1.0")).is_ok());
+        assert_eq!(Acct{ available: amt("0.0"), held: amt("1.0"), total: amt("1.0"), lock: None }, acct);
 
-        acct.resolve(1.0);
-        assert_eq!(Acct{ available: 1.0, held: 0.0, total: 1.0, locked: false }, acct);
+        assert!(acct.resolve(amt("1.0")).is_ok());
+        assert_eq!(Acct{ available: amt("1.0"), held: amt("0.0"), total: amt("1.0"), lock: None }, acct);
 
-        acct.dispute(1.0);
-        acct.chargeback(1.0);
-        assert_eq!(Acct{ available: 0.0, held: 0.0, total: 0.0, locked: true }, acct);
+        assert!(acct.dispute(amt("1.0")).is_ok());
+        assert!(acct.chargeback(amt("1.0")).is_ok());
+        assert_eq!(Acct{ available: amt("0.0"), held: amt("0.0"), total: amt("0.0"), lock: Some(LockReason::Chargeback) }, acct);
     }
 
     #[test]
     fn dispute_withdraw() {
         let mut acct = Acct::default();
-        _ = acct.deposit(1.0);
-        _ = acct.withdrawal(0.5);
+        _ = acct.deposit(amt("1.0"));
+        _ = acct.withdrawal(amt("0.5"));
+
+        assert!(acct.dispute(amt("-0.5")).is_ok());
+        assert_eq!(Acct{ available: amt("1.0"), held: amt("-0.5"), total: amt("0.5"), lock: None }, acct);
+
+        assert!(acct.resolve(amt("-0.5")).is_ok());
+        assert_eq!(Acct{ available: amt("0.5"), held: amt("0.0"), total: amt("0.5"), lock: None }, acct);
+
+        assert!(acct.dispute(amt("-0.5")).is_ok());
+        assert!(acct.chargeback(amt("-0.5")).is_ok());
+        assert_eq!(Acct{ available: amt("1.0"), held: amt("0.0"), total: amt("1.0"), lock: Some(LockReason::Chargeback) }, acct);
+    }
+
+    #[test]
+    fn credit_used() {
+        let mut acct = Acct::default();
+        assert_eq!(Ok(()), acct.set(amt("1.0"), Amount::ZERO));
+        assert_eq!(amt("0.0"), acct.credit_used());
 
-        acct.dispute(-0.5);
-        assert_eq!(Acct{ available: 1.0, held: -0.5, total: 0.5, locked: false }, acct);
+        assert_eq!(Ok(()), acct.set(amt("-0.5"), Amount::ZERO));
+        assert_eq!(amt("0.5"), acct.credit_used());
 
-        acct.resolve(-0.5);
-        assert_eq!(Acct{ available: 0.5, held: 0.0, total: 0.5, locked: false }, acct);
+        acct.available = Amount::from_raw(i64::MIN);
+        assert_eq!(Amount::from_raw(i64::MAX), acct.credit_used());
+    }
+
+    #[test]
+    fn overflow() {
+        let mut acct = Acct::default();
+        assert_eq!(Ok(()), acct.set(amt("922337203685477.5807"), Amount::ZERO));
 
-        acct.dispute(-0.5);
-        acct.chargeback(-0.5);
-        assert_eq!(Acct{ available: 1.0, held: 0.0, total: 1.0, locked: true }, acct);
+        assert_eq!(Err(AcctError::Overflow), acct.set(amt("922337203685477.5807"), amt("0.0001")));
+        assert_eq!(Err(AcctError::Overflow), acct.deposit(amt("0.0001")));
+        assert_eq!(Err(AcctError::Overflow), acct.resolve(amt("0.0001")));
+        assert_eq!(Err(AcctError::Overflow), acct.dispute(Amount::from_raw(i64::MIN)));
+        assert_eq!(Acct{ available: amt("922337203685477.5807"), held: amt("0.0"), total: amt("922337203685477.5807"), lock: None }, acct);
     }
 }
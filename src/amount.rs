@@ -0,0 +1,213 @@
+//! Contains the [`Amount`] type representing a fixed-point amount of funds.
+
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+use serde::de::{self, Deserialize, Deserializer, Visitor};
+use serde::{Serialize, Serializer};
+
+/// The number of decimal places an [`Amount`] is precise to.
+pub const DECIMALS: usize = 4;
+
+/// The number of ten-thousandths in a single unit.
+const SCALE: i64 = 10_000;
+
+/// An amount of funds stored as a whole number of ten-thousandths.
+///
+/// Unlike `f64`, adding and subtracting amounts never drifts, and all of the
+/// arithmetic is checked so an overflow is reported instead of wrapping.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Amount(i64);
+
+impl Amount {
+    pub const ZERO: Amount = Amount(0);
+
+    /// Creates an amount from a number of ten-thousandths.
+    pub const fn from_raw(raw: i64) -> Self {
+        Self(raw)
+    }
+
+    /// Returns the number of ten-thousandths in this amount.
+    pub const fn raw(self) -> i64 {
+        self.0
+    }
+
+    pub fn is_positive(self) -> bool {
+        self > Amount::ZERO
+    }
+
+    pub fn is_negative(self) -> bool {
+        self < Amount::ZERO
+    }
+
+    pub fn checked_add(self, rhs: Amount) -> Option<Amount> {
+        self.0.checked_add(rhs.0).map(Amount)
+    }
+
+    pub fn checked_sub(self, rhs: Amount) -> Option<Amount> {
+        self.0.checked_sub(rhs.0).map(Amount)
+    }
+
+    /// Returns the amount with its sign flipped, or `None` for the smallest amount
+    /// (which has no positive counterpart).
+    pub fn checked_neg(self) -> Option<Amount> {
+        self.0.checked_neg().map(Amount)
+    }
+}
+
+/// The reasons a string can fail to parse as an [`Amount`].
+#[derive(Debug, PartialEq)]
+pub enum ParseAmountError {
+    /// The string is not a decimal number.
+    Invalid,
+    /// The string has more than [`DECIMALS`] fractional digits.
+    TooPrecise,
+    /// The number is too large to be represented.
+    Overflow,
+}
+
+impl fmt::Display for ParseAmountError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseAmountError::Invalid => write!(f, "invalid amount"),
+            ParseAmountError::TooPrecise => write!(f, "amount has more than {DECIMALS} decimal places"),
+            ParseAmountError::Overflow => write!(f, "amount is too large"),
+        }
+    }
+}
+
+impl Error for ParseAmountError {}
+
+impl FromStr for Amount {
+    type Err = ParseAmountError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (negative, digits) = match s.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, s.strip_prefix('+').unwrap_or(s)),
+        };
+        let (whole, frac) = digits.split_once('.').unwrap_or((digits, ""));
+
+        // at least one digit is required, and only digits are allowed on either side of the point
+        if whole.is_empty() && frac.is_empty() {
+            return Err(ParseAmountError::Invalid);
+        }
+        if !whole.bytes().chain(frac.bytes()).all(|b| b.is_ascii_digit()) {
+            return Err(ParseAmountError::Invalid);
+        }
+        if frac.len() > DECIMALS {
+            return Err(ParseAmountError::TooPrecise);
+        }
+
+        let mut raw: i64 = 0;
+        let padding = std::iter::repeat_n(b'0', DECIMALS - frac.len());
+        for b in whole.bytes().chain(frac.bytes()).chain(padding) {
+            raw = raw
+                .checked_mul(10)
+                .and_then(|r| r.checked_add(i64::from(b - b'0')))
+                .ok_or(ParseAmountError::Overflow)?;
+        }
+        Ok(Amount(if negative { -raw } else { raw }))
+    }
+}
+
+impl fmt::Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.is_negative() { "-" } else { "" };
+        let abs = self.0.unsigned_abs();
+        let scale = SCALE as u64;
+        write!(f, "{sign}{}.{:0width$}", abs / scale, abs % scale, width = DECIMALS)
+    }
+}
+
+impl Serialize for Amount {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Amount {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where D: Deserializer<'de>
+    {
+        deserializer.deserialize_str(AmountVisitor)
+    }
+}
+
+struct AmountVisitor;
+
+impl<'de> Visitor<'de> for AmountVisitor {
+    type Value = Amount;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a decimal amount with at most {DECIMALS} decimal places")
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+        where E: de::Error
+    {
+        v.parse().map_err(E::custom)
+    }
+}
+
+//------------------------------------------------------------------------------
+//------------------------------------------------------------------------------
+// TESTS
+//------------------------------------------------------------------------------
+//------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!(Ok(Amount(10_000)), "1".parse());
+        assert_eq!(Ok(Amount(10_000)), "1.0".parse());
+        assert_eq!(Ok(Amount(12_345)), "1.2345".parse());
+        assert_eq!(Ok(Amount(5_000)), ".5".parse());
+        assert_eq!(Ok(Amount(20_000)), "2.".parse());
+        assert_eq!(Ok(Amount(-5_000)), "-0.5".parse());
+        assert_eq!(Ok(Amount(5_000)), "+0.5".parse());
+
+        assert_eq!(Err(ParseAmountError::TooPrecise), "1.23456".parse::<Amount>());
+        assert_eq!(Err(ParseAmountError::Invalid), "".parse::<Amount>());
+        assert_eq!(Err(ParseAmountError::Invalid), ".".parse::<Amount>());
+        assert_eq!(Err(ParseAmountError::Invalid), "1.2.3".parse::<Amount>());
+        assert_eq!(Err(ParseAmountError::Invalid), "1e5".parse::<Amount>());
+        assert_eq!(Err(ParseAmountError::Invalid), "--1".parse::<Amount>());
+        assert_eq!(Err(ParseAmountError::Overflow), "99999999999999999999".parse::<Amount>());
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!("1.0000", Amount(10_000).to_string());
+        assert_eq!("0.0001", Amount(1).to_string());
+        assert_eq!("-0.5000", Amount(-5_000).to_string());
+        assert_eq!("0.0000", Amount::ZERO.to_string());
+        assert_eq!("-922337203685477.5808", Amount(i64::MIN).to_string());
+    }
+
+    #[test]
+    fn no_drift() {
+        let tenth: Amount = "0.1".parse().unwrap();
+        let mut sum = Amount::ZERO;
+        for _ in 0..10_000 {
+            sum = sum.checked_add(tenth).unwrap();
+        }
+        assert_eq!(Ok(sum), "1000".parse());
+    }
+
+    #[test]
+    fn checked_arithmetic() {
+        let max = Amount(i64::MAX);
+        assert_eq!(None, max.checked_add(Amount(1)));
+        assert_eq!(None, Amount(-i64::MAX).checked_sub(Amount(2)));
+        assert_eq!(Some(Amount(1)), Amount(3).checked_sub(Amount(2)));
+        assert_eq!(Some(Amount(-i64::MAX)), max.checked_neg());
+        assert_eq!(None, Amount(i64::MIN).checked_neg());
+    }
+}
@@ -0,0 +1,70 @@
+//! Contains the CRC-32 checksum used by the on-disk formats.
+
+/// The lookup table for the reflected IEEE polynomial.
+const TABLE: [u32; 256] = table();
+
+const fn table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// Returns the CRC-32 (IEEE) checksum of the data.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(data);
+    crc.finish()
+}
+
+/// Computes a CRC-32 (IEEE) checksum of data that arrives in pieces.
+pub struct Crc32(u32);
+
+impl Crc32 {
+    pub fn new() -> Self {
+        Crc32(!0)
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.0 = data.iter().fold(self.0, |crc, b| TABLE[((crc ^ u32::from(*b)) & 0xFF) as usize] ^ (crc >> 8));
+    }
+
+    pub fn finish(&self) -> u32 {
+        !self.0
+    }
+}
+
+//------------------------------------------------------------------------------
+//------------------------------------------------------------------------------
+// TESTS
+//------------------------------------------------------------------------------
+//------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn known_values() {
+        assert_eq!(0, crc32(b""));
+        assert_eq!(0xCBF4_3926, crc32(b"123456789"));
+        assert_eq!(0x414F_A339, crc32(b"The quick brown fox jumps over the lazy dog"));
+    }
+
+    #[test]
+    fn pieces() {
+        let mut crc = Crc32::new();
+        crc.update(b"The quick brown fox ");
+        crc.update(b"jumps over the lazy dog");
+        assert_eq!(0x414F_A339, crc.finish());
+    }
+}
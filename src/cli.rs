@@ -0,0 +1,714 @@
+//! Contains the command line argument handling.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::error::Error;
+use std::ffi::OsString;
+
+use toy_payments_engine::input::STDIN;
+use toy_payments_engine::{AsOf, DeadlineAction, DisputePolicy, Format, LockPolicy, LockReason, TxType};
+
+const USAGE: &str = "\
+usage: toy_payments_engine [<command>] [<options>] [<transactions.csv>...]
+
+commands:
+    process     process transactions and write the resulting accounts (the default)
+    validate    check that every row can be parsed, without processing anything
+    stats       process transactions and write a summary of what happened
+    statement   process transactions and write the statement of a single client
+    replay      rebuild the accounts from a snapshot and/or write-ahead log
+
+Inputs are processed in order, and `-` (or no inputs at all) reads from stdin.
+Files ending in `.jsonl` or `.ndjson` are read and written as JSON Lines, and
+everything else as CSV, unless --input-format or --output-format says otherwise.
+Run `toy_payments_engine <command> --help` for the options of a command.";
+
+// NOTE: the usage texts are built with `concat!` so that the options of the
+// engine (shared by every command that processes transactions) are only written
+// once.
+macro_rules! engine_options {
+    () => {"
+    --house-account <id>    the client account fees are paid into and interest is paid out of
+    --allow-fee-overdraft   let fees take a client's available funds below zero
+    --client-config <path>  read each client's credit limit from a CSV with client and
+                            credit_limit columns
+    --limits <path>         read the withdrawal and velocity limits from a JSON file
+    --no-redispute          don't let a resolved transaction be disputed again
+    --max-disputes <count>  the most times a single transaction can be disputed
+    --no-withdrawal-disputes
+                            don't let withdrawals be disputed
+    --no-negative-available don't let a dispute take the available funds below zero (or
+                            the client's credit limit)
+    --dispute-window <time> the longest a transaction can be disputed after its timestamp
+    --resolution-deadline <time>
+                            the longest a dispute can be left open before it's settled
+    --on-deadline <action>  settle disputes left open past the deadline with a resolve
+                            (the default) or a chargeback
+    --frozen-allows <types> the comma-separated transaction types still allowed on frozen accounts
+    --chargeback-allows <types>
+                            the types still allowed on accounts locked by a chargeback
+    --closed-allows <types> the types still allowed on closed accounts"
+    };
+}
+
+const PROCESS_USAGE: &str = concat!("\
+usage: toy_payments_engine process [<options>] [<transactions.csv>...]
+
+Processes transactions and writes the resulting accounts to stdout.
+
+options:
+    --input-format <format> read the inputs as csv or jsonl, whatever their extension
+    --output-format <format>
+                            write the accounts and reports as csv or jsonl
+    --rejected <path>       write a report of every rejected row
+    --wal <path>            recover from and log accepted transactions to a write-ahead log
+    --snapshot-in <path>    start from a snapshot
+    --snapshot-out <path>   write a snapshot of the final state
+    --audit <path>          write the audit log of account freezes, unfreezes, and closes
+    --transactions <path>   write every recorded transaction and its dispute state
+    --journal <path>        write every change to every account as JSON lines
+    --as-of <point>         write the accounts as they were right after a point
+                            (seq:<n> or time:<timestamp>) rather than at the end
+    --strict                check the invariants after every transaction, stopping at
+                            the first row that breaks one
+    --threads <count>       process clients in parallel across this many threads
+                            (can't be combined with --wal, --journal, --as-of,
+                            --strict, or --resolution-deadline)", engine_options!());
+
+const VALIDATE_USAGE: &str = "\
+usage: toy_payments_engine validate [<options>] [<transactions.csv>...]
+
+Checks that every row can be parsed, writing a report of the malformed rows to
+stdout. Exits with a non-zero code if any row is malformed.
+
+options:
+    --input-format <format> read the inputs as csv or jsonl, whatever their extension";
+
+const STATS_USAGE: &str = concat!("\
+usage: toy_payments_engine stats [<options>] [<transactions.csv>...]
+
+Processes transactions and writes the counts per transaction type, the rejections
+per error code, and the activity per client to stdout.
+
+options:
+    --input-format <format> read the inputs as csv or jsonl, whatever their extension", engine_options!());
+
+const STATEMENT_USAGE: &str = concat!("\
+usage: toy_payments_engine statement --client <id> [<options>] [<transactions.csv>...]
+
+Processes transactions and writes the statement of a client's account to stdout:
+every transaction that touched it (accepted or rejected) in the order they were
+processed, with the balances after each.
+
+options:
+    --client <id>           the client to write the statement of
+    --text                  write the statement as plain text
+    --input-format <format> read the inputs as csv or jsonl, whatever their extension
+    --output-format <format>
+                            write the statement as csv or jsonl
+    --snapshot-in <path>    start from a snapshot (the statement opens with its balances)", engine_options!());
+
+const REPLAY_USAGE: &str = concat!("\
+usage: toy_payments_engine replay [<options>]
+
+Rebuilds the engine's state and writes the resulting accounts to stdout. The
+write-ahead log is read as-is, without truncating a torn trailing record, and
+the engine options must match the ones the log was written with.
+
+options:
+    --output-format <format>
+                            write the accounts and audit log as csv or jsonl
+    --snapshot-in <path>    start from a snapshot
+    --wal <path>            replay the transactions in a write-ahead log
+    --snapshot-out <path>   write a snapshot of the rebuilt state
+    --audit <path>          write the audit log of account freezes, unfreezes, and closes
+    --transactions <path>   write every recorded transaction and its dispute state
+    --journal <path>        write every change to every account as JSON lines
+    --as-of <point>         write the accounts as they were right after a point
+                            (seq:<n> or time:<timestamp>) rather than at the end
+    --strict                check the invariants after every transaction, stopping at
+                            the first transaction that breaks one", engine_options!());
+
+/// The value options of the engine, shared by every command that processes transactions.
+const ENGINE_VALUES: [&str; 10] = [
+    "--house-account",
+    "--client-config",
+    "--limits",
+    "--max-disputes",
+    "--dispute-window",
+    "--resolution-deadline",
+    "--on-deadline",
+    "--frozen-allows",
+    "--chargeback-allows",
+    "--closed-allows",
+];
+/// The flags of the engine, shared by every command that processes transactions.
+const ENGINE_FLAGS: [&str; 4] = ["--allow-fee-overdraft", "--no-redispute", "--no-withdrawal-disputes", "--no-negative-available"];
+
+/// What this program was asked to do.
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Process(ProcessArgs),
+    Validate(ValidateArgs),
+    Stats(StatsArgs),
+    Statement(StatementArgs),
+    Replay(ReplayArgs),
+    /// Print the usage text.
+    Help(&'static str),
+}
+
+/// The options of the `process` command.
+#[derive(Debug, Default, PartialEq)]
+pub struct ProcessArgs {
+    /// The files of transactions to process, in order (`-` is stdin).
+    pub inputs: Vec<OsString>,
+    /// The format to read every input in, rather than going by extension.
+    pub input_format: Option<Format>,
+    /// The format to write every output in, rather than going by extension.
+    pub output_format: Option<Format>,
+    /// Where to write the report of rejected rows, if anywhere.
+    pub rejected: Option<OsString>,
+    /// The write-ahead log to recover from and log accepted transactions to, if any.
+    pub wal: Option<OsString>,
+    /// The snapshot to start from, if any.
+    pub snapshot_in: Option<OsString>,
+    /// Where to write a snapshot of the final state, if anywhere.
+    pub snapshot_out: Option<OsString>,
+    /// Where to write the audit log, if anywhere.
+    pub audit: Option<OsString>,
+    /// Where to write the recorded transactions, if anywhere.
+    pub transactions: Option<OsString>,
+    /// Where to write the event journal, if anywhere.
+    pub journal: Option<OsString>,
+    /// The point in time to write the accounts as of, rather than the end.
+    pub as_of: Option<AsOf>,
+    /// Whether to check the invariants after every transaction.
+    pub strict: bool,
+    /// The number of threads to process clients across, if more than one.
+    pub threads: Option<usize>,
+    pub engine: EngineArgs,
+}
+
+/// The options of the `validate` command.
+#[derive(Debug, Default, PartialEq)]
+pub struct ValidateArgs {
+    /// The files of transactions to check (`-` is stdin).
+    pub inputs: Vec<OsString>,
+    /// The format to read every input in, rather than going by extension.
+    pub input_format: Option<Format>,
+}
+
+/// The options of the `stats` command.
+#[derive(Debug, Default, PartialEq)]
+pub struct StatsArgs {
+    /// The files of transactions to process, in order (`-` is stdin).
+    pub inputs: Vec<OsString>,
+    /// The format to read every input in, rather than going by extension.
+    pub input_format: Option<Format>,
+    pub engine: EngineArgs,
+}
+
+/// The options of the `statement` command.
+#[derive(Debug, Default, PartialEq)]
+pub struct StatementArgs {
+    /// The client to write the statement of.
+    pub client_id: u16,
+    /// Whether to write the statement as plain text rather than records.
+    pub text: bool,
+    /// The files of transactions to process, in order (`-` is stdin).
+    pub inputs: Vec<OsString>,
+    /// The format to read every input in, rather than going by extension.
+    pub input_format: Option<Format>,
+    /// The format to write the statement in (CSV by default).
+    pub output_format: Option<Format>,
+    /// The snapshot to start from, if any.
+    pub snapshot_in: Option<OsString>,
+    pub engine: EngineArgs,
+}
+
+/// The options of the `replay` command.
+#[derive(Debug, Default, PartialEq)]
+pub struct ReplayArgs {
+    /// The format to write every output in, rather than going by extension.
+    pub output_format: Option<Format>,
+    /// The snapshot to start from, if any.
+    pub snapshot_in: Option<OsString>,
+    /// The write-ahead log to replay, if any.
+    pub wal: Option<OsString>,
+    /// Where to write a snapshot of the rebuilt state, if anywhere.
+    pub snapshot_out: Option<OsString>,
+    /// Where to write the audit log, if anywhere.
+    pub audit: Option<OsString>,
+    /// Where to write the recorded transactions, if anywhere.
+    pub transactions: Option<OsString>,
+    /// Where to write the event journal, if anywhere.
+    pub journal: Option<OsString>,
+    /// The point in time to write the accounts as of, rather than the end.
+    pub as_of: Option<AsOf>,
+    /// Whether to check the invariants after every transaction.
+    pub strict: bool,
+    pub engine: EngineArgs,
+}
+
+/// The options that configure how the engine processes transactions.
+#[derive(Debug, Default, PartialEq)]
+pub struct EngineArgs {
+    /// The client account fees are paid into and interest is paid out of, if any.
+    pub house_account: Option<u16>,
+    /// Whether fees may take a client's available funds below zero.
+    pub allow_fee_overdraft: bool,
+    /// Where the clients' credit limits are read from, if anywhere.
+    pub client_config: Option<OsString>,
+    /// Where the limits are read from, if anywhere.
+    pub limits: Option<OsString>,
+    pub dispute_policy: DisputePolicy,
+    pub lock_policy: LockPolicy,
+}
+
+impl EngineArgs {
+    fn parse(opts: &mut Options, usage: &str) -> Result<Self, Box<dyn Error>> {
+        let house_account = match opts.value("--house-account") {
+            None => None,
+            Some(value) => match value.to_str().and_then(|v| v.parse().ok()) {
+                Some(client_id) => Some(client_id),
+                None => return Err(format!("--house-account expects a client ID\n\n{usage}").into()),
+            },
+        };
+        let max_disputes = match opts.value("--max-disputes") {
+            None => None,
+            Some(value) => match value.to_str().and_then(|v| v.parse().ok()) {
+                Some(max) => Some(max),
+                None => return Err(format!("--max-disputes expects a number\n\n{usage}").into()),
+            },
+        };
+        let on_deadline = match opts.value("--on-deadline").as_ref().and_then(|v| v.to_str()) {
+            None => DeadlineAction::Resolve,
+            Some("resolve") => DeadlineAction::Resolve,
+            Some("chargeback") => DeadlineAction::Chargeback,
+            Some(_) => return Err(format!("--on-deadline expects resolve or chargeback\n\n{usage}").into()),
+        };
+        let mut lock_policy = LockPolicy::default();
+        for (opt, reason) in [
+            ("--frozen-allows", LockReason::Frozen),
+            ("--chargeback-allows", LockReason::Chargeback),
+            ("--closed-allows", LockReason::Closed),
+        ] {
+            let Some(value) = opts.value(opt) else { continue };
+            for name in value.to_string_lossy().split(',') {
+                match name.trim().parse::<TxType>() {
+                    Ok(tx_type) => lock_policy = lock_policy.allow(reason, tx_type),
+                    Err(e) => return Err(format!("{opt} expects transaction types: {e}\n\n{usage}").into()),
+                }
+            }
+        }
+        Ok(EngineArgs {
+            house_account,
+            allow_fee_overdraft: opts.flag("--allow-fee-overdraft"),
+            client_config: opts.value("--client-config"),
+            limits: opts.value("--limits"),
+            dispute_policy: DisputePolicy {
+                allow_redispute: !opts.flag("--no-redispute"),
+                max_disputes,
+                withdrawals_disputable: !opts.flag("--no-withdrawal-disputes"),
+                allow_negative_available: !opts.flag("--no-negative-available"),
+                dispute_window: opts.time("--dispute-window", usage)?,
+                resolution_deadline: opts.time("--resolution-deadline", usage)?,
+                on_deadline,
+            },
+            lock_policy,
+        })
+    }
+}
+
+impl Command {
+    /// Parses the arguments sent to this process.
+    pub fn parse() -> Result<Self, Box<dyn Error>> {
+        Self::parse_from(env::args_os().skip(1))
+    }
+
+    fn parse_from<I>(args: I) -> Result<Self, Box<dyn Error>>
+        where I: IntoIterator<Item = OsString>
+    {
+        let mut args: Vec<OsString> = args.into_iter().collect();
+
+        // the command can be left off, in which case the transactions are processed
+        let command = match args.first().and_then(|a| a.to_str()) {
+            Some(c @ ("process" | "validate" | "stats" | "statement" | "replay" | "help")) => {
+                let c = c.to_owned();
+                args.remove(0);
+                c
+            }
+            Some("--help" | "-h") => return Ok(Command::Help(USAGE)),
+            _ => "process".to_owned(),
+        };
+
+        match command.as_str() {
+            "process" => {
+                let values = [&["--input-format", "--output-format", "--rejected", "--wal", "--snapshot-in", "--snapshot-out", "--audit", "--transactions", "--journal", "--as-of", "--threads"][..], &ENGINE_VALUES].concat();
+                let flags = [&["--strict"][..], &ENGINE_FLAGS].concat();
+                let Some(mut opts) = Options::parse(args, &values, &flags, PROCESS_USAGE)? else {
+                    return Ok(Command::Help(PROCESS_USAGE));
+                };
+                let threads = match opts.value("--threads") {
+                    None => None,
+                    Some(value) => match value.to_str().and_then(|v| v.parse::<usize>().ok()) {
+                        Some(0) | None => return Err(format!("--threads expects a positive number\n\n{PROCESS_USAGE}").into()),
+                        Some(1) => None,
+                        Some(n) => Some(n),
+                    },
+                };
+                let args = ProcessArgs {
+                    input_format: opts.format("--input-format", PROCESS_USAGE)?,
+                    output_format: opts.format("--output-format", PROCESS_USAGE)?,
+                    rejected: opts.value("--rejected"),
+                    wal: opts.value("--wal"),
+                    snapshot_in: opts.value("--snapshot-in"),
+                    snapshot_out: opts.value("--snapshot-out"),
+                    audit: opts.value("--audit"),
+                    transactions: opts.value("--transactions"),
+                    journal: opts.value("--journal"),
+                    as_of: opts.as_of(PROCESS_USAGE)?,
+                    strict: opts.flag("--strict"),
+                    threads,
+                    engine: EngineArgs::parse(&mut opts, PROCESS_USAGE)?,
+                    inputs: opts.inputs(),
+                };
+                if args.threads.is_some() && args.wal.is_some() {
+                    return Err(format!("--threads can't be combined with --wal\n\n{PROCESS_USAGE}").into());
+                }
+                if args.threads.is_some() && args.journal.is_some() {
+                    return Err(format!("--threads can't be combined with --journal\n\n{PROCESS_USAGE}").into());
+                }
+                if args.threads.is_some() && args.as_of.is_some() {
+                    return Err(format!("--threads can't be combined with --as-of\n\n{PROCESS_USAGE}").into());
+                }
+                if args.threads.is_some() && args.strict {
+                    return Err(format!("--threads can't be combined with --strict\n\n{PROCESS_USAGE}").into());
+                }
+                if args.threads.is_some() && args.engine.dispute_policy.resolution_deadline.is_some() {
+                    return Err(format!("--threads can't be combined with --resolution-deadline\n\n{PROCESS_USAGE}").into());
+                }
+                Ok(Command::Process(args))
+            }
+            "validate" => {
+                let Some(mut opts) = Options::parse(args, &["--input-format"], &[], VALIDATE_USAGE)? else {
+                    return Ok(Command::Help(VALIDATE_USAGE));
+                };
+                Ok(Command::Validate(ValidateArgs {
+                    input_format: opts.format("--input-format", VALIDATE_USAGE)?,
+                    inputs: opts.inputs(),
+                }))
+            }
+            "stats" => {
+                let values = [&["--input-format"][..], &ENGINE_VALUES].concat();
+                let Some(mut opts) = Options::parse(args, &values, &ENGINE_FLAGS, STATS_USAGE)? else {
+                    return Ok(Command::Help(STATS_USAGE));
+                };
+                Ok(Command::Stats(StatsArgs {
+                    input_format: opts.format("--input-format", STATS_USAGE)?,
+                    engine: EngineArgs::parse(&mut opts, STATS_USAGE)?,
+                    inputs: opts.inputs(),
+                }))
+            }
+            "statement" => {
+                let values = [&["--client", "--input-format", "--output-format", "--snapshot-in"][..], &ENGINE_VALUES].concat();
+                let flags = [&["--text"][..], &ENGINE_FLAGS].concat();
+                let Some(mut opts) = Options::parse(args, &values, &flags, STATEMENT_USAGE)? else {
+                    return Ok(Command::Help(STATEMENT_USAGE));
+                };
+                let client_id = match opts.value("--client") {
+                    None => return Err(format!("statement needs a --client\n\n{STATEMENT_USAGE}").into()),
+                    Some(value) => match value.to_str().and_then(|v| v.parse().ok()) {
+                        Some(client_id) => client_id,
+                        None => return Err(format!("--client expects a client ID\n\n{STATEMENT_USAGE}").into()),
+                    },
+                };
+                let args = StatementArgs {
+                    client_id,
+                    text: opts.flag("--text"),
+                    input_format: opts.format("--input-format", STATEMENT_USAGE)?,
+                    output_format: opts.format("--output-format", STATEMENT_USAGE)?,
+                    snapshot_in: opts.value("--snapshot-in"),
+                    engine: EngineArgs::parse(&mut opts, STATEMENT_USAGE)?,
+                    inputs: opts.inputs(),
+                };
+                if args.text && args.output_format.is_some() {
+                    return Err(format!("--text can't be combined with --output-format\n\n{STATEMENT_USAGE}").into());
+                }
+                Ok(Command::Statement(args))
+            }
+            "replay" => {
+                let values = [&["--output-format", "--snapshot-in", "--wal", "--snapshot-out", "--audit", "--transactions", "--journal", "--as-of"][..], &ENGINE_VALUES].concat();
+                let flags = [&["--strict"][..], &ENGINE_FLAGS].concat();
+                let Some(mut opts) = Options::parse(args, &values, &flags, REPLAY_USAGE)? else {
+                    return Ok(Command::Help(REPLAY_USAGE));
+                };
+                if !opts.inputs.is_empty() {
+                    return Err(format!("replay doesn't take any inputs\n\n{REPLAY_USAGE}").into());
+                }
+                let args = ReplayArgs {
+                    output_format: opts.format("--output-format", REPLAY_USAGE)?,
+                    snapshot_in: opts.value("--snapshot-in"),
+                    wal: opts.value("--wal"),
+                    snapshot_out: opts.value("--snapshot-out"),
+                    audit: opts.value("--audit"),
+                    transactions: opts.value("--transactions"),
+                    journal: opts.value("--journal"),
+                    as_of: opts.as_of(REPLAY_USAGE)?,
+                    strict: opts.flag("--strict"),
+                    engine: EngineArgs::parse(&mut opts, REPLAY_USAGE)?,
+                };
+                if args.snapshot_in.is_none() && args.wal.is_none() {
+                    return Err(format!("replay needs a snapshot or a write-ahead log\n\n{REPLAY_USAGE}").into());
+                }
+                Ok(Command::Replay(args))
+            }
+            _ => Ok(Command::Help(USAGE)),
+        }
+    }
+}
+
+/// The options and inputs given to a command.
+struct Options {
+    values: BTreeMap<&'static str, OsString>,
+    flags: BTreeSet<&'static str>,
+    inputs: Vec<OsString>,
+}
+
+impl Options {
+    /// Splits the arguments into the options that take a value, the flags, and the
+    /// inputs, returning `None` if help was asked for.
+    fn parse(args: Vec<OsString>, known: &[&'static str], known_flags: &[&'static str], usage: &str) -> Result<Option<Self>, Box<dyn Error>> {
+        let mut values = BTreeMap::new();
+        let mut flags = BTreeSet::new();
+        let mut inputs = Vec::new();
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let name = arg.to_string_lossy();
+            if name == "--help" || name == "-h" {
+                return Ok(None);
+            } else if let Some(opt) = known.iter().find(|o| **o == name) {
+                values.insert(*opt, args.next().ok_or(format!("{opt} expects a value\n\n{usage}"))?);
+            } else if let Some(flag) = known_flags.iter().find(|f| **f == name) {
+                flags.insert(*flag);
+            } else if name.starts_with("--") {
+                return Err(format!("unknown option {name}\n\n{usage}").into());
+            } else {
+                inputs.push(arg);
+            }
+        }
+        Ok(Some(Options { values, flags, inputs }))
+    }
+
+    fn value(&mut self, name: &str) -> Option<OsString> {
+        self.values.remove(name)
+    }
+
+    /// Parses the value of a format option, if given.
+    fn format(&mut self, name: &str, usage: &str) -> Result<Option<Format>, Box<dyn Error>> {
+        match self.value(name) {
+            None => Ok(None),
+            Some(value) => match value.to_str().and_then(|v| v.parse().ok()) {
+                Some(format) => Ok(Some(format)),
+                None => Err(format!("{name} expects csv or jsonl\n\n{usage}").into()),
+            },
+        }
+    }
+
+    /// Parses the value of a length of time option (in the units of the
+    /// transactions' timestamps), if given.
+    fn time(&mut self, name: &str, usage: &str) -> Result<Option<u64>, Box<dyn Error>> {
+        match self.value(name) {
+            None => Ok(None),
+            Some(value) => match value.to_str().and_then(|v| v.parse().ok()) {
+                Some(time) => Ok(Some(time)),
+                None => Err(format!("{name} expects a length of time\n\n{usage}").into()),
+            },
+        }
+    }
+
+    /// Parses the value of `--as-of`, if given.
+    fn as_of(&mut self, usage: &str) -> Result<Option<AsOf>, Box<dyn Error>> {
+        match self.value("--as-of") {
+            None => Ok(None),
+            Some(value) => match value.to_str().and_then(|v| v.parse().ok()) {
+                Some(as_of) => Ok(Some(as_of)),
+                None => Err(format!("--as-of expects seq:<n> or time:<timestamp>\n\n{usage}").into()),
+            },
+        }
+    }
+
+    fn flag(&self, name: &str) -> bool {
+        self.flags.contains(name)
+    }
+
+    /// Returns the inputs, defaulting to stdin when there are none.
+    fn inputs(self) -> Vec<OsString> {
+        if self.inputs.is_empty() {
+            vec![STDIN.into()]
+        } else {
+            self.inputs
+        }
+    }
+}
+
+//------------------------------------------------------------------------------
+//------------------------------------------------------------------------------
+// TESTS
+//------------------------------------------------------------------------------
+//------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Command, Box<dyn Error>> {
+        Command::parse_from(args.iter().map(OsString::from))
+    }
+
+    #[test]
+    fn process() {
+        let expected = Command::Process(ProcessArgs{ inputs: vec!["tx.csv".into()], ..Default::default() });
+        assert_eq!(expected, parse(&["tx.csv"]).unwrap());
+        assert_eq!(expected, parse(&["process", "tx.csv"]).unwrap());
+
+        assert_eq!(
+            Command::Process(ProcessArgs{ inputs: vec!["-".into()], ..Default::default() }),
+            parse(&[]).unwrap()
+        );
+        assert_eq!(
+            Command::Process(ProcessArgs{
+                inputs: vec!["mon.csv".into(), "-".into(), "tue.csv".into()],
+                input_format: Some(Format::Csv),
+                output_format: Some(Format::JsonLines),
+                rejected: Some("bad.csv".into()),
+                wal: Some("tx.wal".into()),
+                snapshot_in: Some("monday.snap".into()),
+                snapshot_out: Some("tuesday.snap".into()),
+                audit: Some("audit.csv".into()),
+                transactions: Some("tx-out.jsonl".into()),
+                journal: Some("events.jsonl".into()),
+                as_of: Some(AsOf::Time(1_700_000_000)),
+                strict: true,
+                threads: None,
+                engine: EngineArgs{ house_account: Some(0), allow_fee_overdraft: true, ..Default::default() },
+            }),
+            parse(&[
+                "--rejected", "bad.csv", "mon.csv", "-", "--wal", "tx.wal", "--house-account", "0",
+                "--snapshot-in", "monday.snap", "tue.csv", "--snapshot-out", "tuesday.snap", "--allow-fee-overdraft",
+                "--audit", "audit.csv", "--transactions", "tx-out.jsonl", "--journal", "events.jsonl", "--strict", "--input-format", "csv", "--output-format", "jsonl",
+                "--as-of", "time:1700000000",
+            ]).unwrap()
+        );
+        assert!(parse(&["--as-of", "1042", "tx.csv"]).is_err());
+        assert!(parse(&["--threads", "4", "--as-of", "seq:1042", "tx.csv"]).is_err());
+
+        let lock_policy = LockPolicy::default()
+            .allow(LockReason::Frozen, TxType::Resolve)
+            .allow(LockReason::Frozen, TxType::Chargeback)
+            .allow(LockReason::Chargeback, TxType::Resolve);
+        assert_eq!(
+            Command::Process(ProcessArgs{ inputs: vec!["tx.csv".into()], engine: EngineArgs{ lock_policy, ..Default::default() }, ..Default::default() }),
+            parse(&["--frozen-allows", "resolve, chargeback", "--chargeback-allows", "resolve", "tx.csv"]).unwrap()
+        );
+        assert!(parse(&["--closed-allows", "refund", "tx.csv"]).is_err());
+
+        assert_eq!(
+            Command::Process(ProcessArgs{ inputs: vec!["tx.csv".into()], threads: Some(4), ..Default::default() }),
+            parse(&["--threads", "4", "tx.csv"]).unwrap()
+        );
+        assert_eq!(expected, parse(&["--threads", "1", "tx.csv"]).unwrap());
+
+        assert!(parse(&["tx.csv", "--rejected"]).is_err());
+        assert!(parse(&["--threads", "0", "tx.csv"]).is_err());
+        assert!(parse(&["--threads", "four", "tx.csv"]).is_err());
+        assert!(parse(&["--threads", "4", "--wal", "tx.wal", "tx.csv"]).is_err());
+        assert!(parse(&["--threads", "4", "--journal", "events.jsonl", "tx.csv"]).is_err());
+        assert!(parse(&["--threads", "4", "--strict", "tx.csv"]).is_err());
+        assert!(parse(&["--house-account", "house", "tx.csv"]).is_err());
+        assert!(parse(&["--verbose", "tx.csv"]).is_err());
+    }
+
+    #[test]
+    fn other_commands() {
+        assert_eq!(
+            Command::Validate(ValidateArgs{ inputs: vec!["a.csv".into(), "b.csv".into()], input_format: None }),
+            parse(&["validate", "a.csv", "b.csv"]).unwrap()
+        );
+        assert_eq!(
+            Command::Validate(ValidateArgs{ inputs: vec!["-".into()], input_format: Some(Format::JsonLines) }),
+            parse(&["validate", "--input-format", "jsonl"]).unwrap()
+        );
+        assert_eq!(Command::Stats(StatsArgs{ inputs: vec!["-".into()], ..Default::default() }), parse(&["stats"]).unwrap());
+        assert_eq!(
+            Command::Stats(StatsArgs{
+                inputs: vec!["-".into()],
+                engine: EngineArgs{
+                    house_account: Some(9),
+                    client_config: Some("clients.csv".into()),
+                    limits: Some("limits.json".into()),
+                    ..Default::default()
+                },
+                ..Default::default()
+            }),
+            parse(&["stats", "--house-account", "9", "--client-config", "clients.csv", "--limits", "limits.json"]).unwrap()
+        );
+        assert_eq!(
+            Command::Stats(StatsArgs{
+                inputs: vec!["-".into()],
+                engine: EngineArgs{
+                    dispute_policy: DisputePolicy{
+                        allow_redispute: false,
+                        max_disputes: Some(3),
+                        withdrawals_disputable: false,
+                        allow_negative_available: false,
+                        dispute_window: Some(60),
+                        resolution_deadline: Some(3600),
+                        on_deadline: DeadlineAction::Chargeback,
+                    },
+                    ..Default::default()
+                },
+                ..Default::default()
+            }),
+            parse(&[
+                "stats", "--no-redispute", "--max-disputes", "3", "--no-withdrawal-disputes", "--no-negative-available",
+                "--dispute-window", "60", "--resolution-deadline", "3600", "--on-deadline", "chargeback",
+            ]).unwrap()
+        );
+        assert!(parse(&["stats", "--max-disputes", "-1"]).is_err());
+        assert!(parse(&["stats", "--dispute-window", "1h"]).is_err());
+        assert!(parse(&["stats", "--on-deadline", "ignore"]).is_err());
+        assert!(parse(&["--threads", "4", "--resolution-deadline", "60", "tx.csv"]).is_err());
+        assert_eq!(
+            Command::Replay(ReplayArgs{ wal: Some("tx.wal".into()), journal: Some("events.jsonl".into()), strict: true, ..Default::default() }),
+            parse(&["replay", "--wal", "tx.wal", "--journal", "events.jsonl", "--strict"]).unwrap()
+        );
+
+        assert_eq!(
+            Command::Statement(StatementArgs{ client_id: 7, text: true, inputs: vec!["tx.csv".into()], ..Default::default() }),
+            parse(&["statement", "--client", "7", "--text", "tx.csv"]).unwrap()
+        );
+        assert!(parse(&["statement", "tx.csv"]).is_err());
+        assert!(parse(&["statement", "--client", "seven"]).is_err());
+        assert!(parse(&["statement", "--client", "7", "--text", "--output-format", "jsonl"]).is_err());
+
+        assert!(parse(&["replay"]).is_err());
+        assert!(parse(&["replay", "--wal", "tx.wal", "tx.csv"]).is_err());
+        assert!(parse(&["validate", "--wal", "tx.wal"]).is_err());
+        assert!(parse(&["validate", "--allow-fee-overdraft"]).is_err());
+        assert!(parse(&["stats", "--strict"]).is_err());
+        assert!(parse(&["stats", "--input-format", "xml"]).is_err());
+        assert!(parse(&["replay", "--wal", "tx.wal", "--input-format", "csv"]).is_err());
+    }
+
+    #[test]
+    fn help() {
+        assert_eq!(Command::Help(USAGE), parse(&["--help"]).unwrap());
+        assert_eq!(Command::Help(USAGE), parse(&["help"]).unwrap());
+        assert_eq!(Command::Help(PROCESS_USAGE), parse(&["process", "-h"]).unwrap());
+        assert_eq!(Command::Help(PROCESS_USAGE), parse(&["tx.csv", "--help"]).unwrap());
+        assert_eq!(Command::Help(REPLAY_USAGE), parse(&["replay", "--help"]).unwrap());
+        assert_eq!(Command::Help(STATEMENT_USAGE), parse(&["statement", "--help"]).unwrap());
+    }
+}
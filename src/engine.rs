@@ -1,106 +1,900 @@
-use std::collections::BTreeMap;
-use std::error::Error;
+//! Contains the [`Engine`] that processes transactions.
 
-use crate::account::Acct;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::io;
+
+use serde::Serialize;
+
+use crate::account::{Acct, LockReason};
+use crate::amount::Amount;
+use crate::error::ProcessError;
+use crate::history::{AsOf, History};
+use crate::invariants::{Checker, Violation};
+use crate::journal::{Event, Subscriber};
+use crate::ledger::{Entry, Ledger, LedgerAcct};
+use crate::limits::{Limits, Usage};
+use crate::policy::{DisputePolicy, LockPolicy};
+use crate::store::{AccountStore, TxStore};
 use crate::transaction::{Tx, TxType};
+use crate::wal::Wal;
 
-/// Represents the current state (in terms of disputes) of a recorded transaction.
-#[derive(Debug, PartialEq)]
+/// Represents the current state (in terms of disputes) of a recorded transaction
+/// (see [`RecTx::state`]).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum TxState {
     /// The transaction is okay.
     Undisputed,
-    /// The transaction is currently being disputed,
+    /// Some of the transaction is currently being disputed,
     Disputed,
-    /// The transaction was successfully disputed. (keeping the ridiculous name because I like it)
+    /// Some of the transaction was successfully disputed, and none of it is being
+    /// disputed. (keeping the ridiculous name because I like it)
     Chargebacked,
 }
 
+impl fmt::Display for TxState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            TxState::Undisputed => "undisputed",
+            TxState::Disputed => "disputed",
+            TxState::Chargebacked => "chargebacked",
+        })
+    }
+}
+
 /// A recorded transaction is different from `Tx` in that these only represent
-/// transactions with amounts (i.e., deposits and withdraws).
-#[derive(Debug, PartialEq)]
+/// transactions with amounts (i.e., deposits, withdraws, transfers, fees, and
+/// interest).
+#[derive(Debug, Clone, PartialEq)]
 pub struct RecTx {
     pub client_id: u16,
-    pub amount: f64,
-    pub state: TxState,
+    /// The type of the transaction that was recorded.
+    pub kind: TxType,
+    /// The amount from the client's side (negative for funds leaving the account).
+    pub amount: Amount,
+    /// The portion of the amount currently held by disputes (from the client's side).
+    pub disputed: Amount,
+    /// The portion of the amount that has been charged back (from the client's side).
+    pub charged_back: Amount,
+    /// The number of times the transaction has been disputed.
+    pub disputes: u32,
+    /// The client funds were moved to, for transfers (whose amount is recorded from
+    /// the sending client's side, like a withdrawal).
+    pub dest: Option<u16>,
+    /// When the transaction was made, if its row had a timestamp.
+    pub timestamp: Option<u64>,
+    /// When the disputed portion was first disputed, if that dispute had a
+    /// timestamp (and until none of it is disputed anymore).
+    pub disputed_at: Option<u64>,
 }
 
-impl From<Tx> for RecTx {
-    fn from(tx: Tx) -> Self {
+impl From<&Tx> for RecTx {
+    fn from(tx: &Tx) -> Self {
         Self {
             client_id: tx.client_id,
+            kind: tx.tx_type,
             amount: match tx.tx_type {
-                TxType::Deposit => tx.amount.unwrap(),
-                TxType::Withdrawal => -tx.amount.unwrap(),
+                TxType::Deposit | TxType::Interest => tx.amount.unwrap(),
+                TxType::Withdrawal | TxType::Transfer | TxType::Fee => tx.amount.and_then(Amount::checked_neg).expect("recorded amounts are positive"),
                 _ => unreachable!(),
             },
-            state: TxState::Undisputed,
+            disputed: Amount::ZERO,
+            charged_back: Amount::ZERO,
+            disputes: 0,
+            dest: match tx.tx_type {
+                TxType::Transfer => tx.dest,
+                _ => None,
+            },
+            timestamp: tx.timestamp,
+            disputed_at: None,
         }
     }
 }
 
+impl RecTx {
+    /// Returns the state of the transaction, derived from its disputed and charged
+    /// back portions.
+    pub fn state(&self) -> TxState {
+        if self.disputed != Amount::ZERO {
+            TxState::Disputed
+        } else if self.charged_back != Amount::ZERO {
+            TxState::Chargebacked
+        } else {
+            TxState::Undisputed
+        }
+    }
+
+    /// Returns the portion of the amount that's neither disputed nor charged back
+    /// (from the client's side), if it fits.
+    pub fn undisputed(&self) -> Option<Amount> {
+        self.amount.checked_sub(self.disputed)?.checked_sub(self.charged_back)
+    }
+}
+
 /// The map of transactions - needed so that past transactions can be disputed
-type TxMap = BTreeMap<u32, RecTx>;
+pub type TxMap = BTreeMap<u32, RecTx>;
 /// The map of accounts - this is the output of the program
-type AcctMap = BTreeMap<u16, Acct>;
+pub type AcctMap = BTreeMap<u16, Acct>;
+
+/// The settings that change how an [`Engine`] processes transactions.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub(crate) struct Config {
+    /// The account fees are paid into and interest is paid out of
+    pub house_account: Option<u16>,
+    /// Whether a fee may take a client's available funds below zero
+    pub allow_fee_overdraft: bool,
+    pub dispute_policy: DisputePolicy,
+    pub lock_policy: LockPolicy,
+    /// How far each client's available funds may go below zero, if at all
+    pub credit_limits: BTreeMap<u16, Amount>,
+    pub limits: Limits,
+}
+
+impl Config {
+    /// Returns how far a client's available funds may go below zero (a negative
+    /// limit counts as zero).
+    pub fn credit_limit(&self, client_id: u16) -> Amount {
+        self.credit_limits.get(&client_id).copied().unwrap_or_default().max(Amount::ZERO)
+    }
+}
+
+/// A record of an administrative transaction changing an account's lock.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditEntry {
+    pub client_id: u16,
+    pub tx_id: u32,
+    /// The administrative transaction's type.
+    pub action: TxType,
+    /// Why the action was taken.
+    pub reason: String,
+    /// The account's lock before the action.
+    pub from: Option<LockReason>,
+    /// The account's lock after the action.
+    pub to: Option<LockReason>,
+}
 
+/// Processes transactions and keeps track of the resulting state of every account.
+///
+/// The engine is generic over where it stores its transactions and accounts,
+/// using the in-memory [`TxMap`] and [`AcctMap`] by default.
 #[derive(Default)]
-pub struct Engine {
+pub struct Engine<T = TxMap, A = AcctMap> {
     /// Keeps track of all transactions processed by the engine
-    pub tx_map: TxMap,
+    pub tx_map: T,
     /// Keeps track of all client accounts
-    pub acct_map: AcctMap,
+    pub acct_map: A,
+    /// The number of transactions submitted to the engine so far
+    seq: u64,
+    /// Where accepted transactions are logged to, if anywhere
+    wal: Option<Wal>,
+    config: Config,
+    /// Every administrative transaction that was accepted, in order
+    audit: Vec<AuditEntry>,
+    /// Every event journaled so far, if the journal is being kept in memory
+    journal: Option<Vec<Event>>,
+    /// Where journaled events are sent to as transactions are accepted
+    subscribers: Vec<Box<dyn Subscriber>>,
+    /// The balance of every account in the ledger
+    ledger: Ledger,
+    /// Checks the invariants after every accepted transaction, in strict mode
+    checker: Option<Checker>,
+    /// Every account's balances after every change, if the history is being kept
+    history: Option<History>,
+    /// The latest timestamp of each client's accepted transactions
+    clocks: BTreeMap<u16, u64>,
+    /// How much of the limits each client has used up
+    usage: BTreeMap<u16, Usage>,
+    /// The client, opening time, and ID of every open dispute with a timestamp
+    /// (built from the recorded transactions when first needed)
+    open_disputes: Option<BTreeSet<(u16, u64, u32)>>,
 }
 
 impl Engine {
-    pub fn process_tx(&mut self, tx: Tx) -> Result<(), Box<dyn Error>> {
+    /// Creates an engine with the default in-memory stores.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<T, A> Engine<T, A>
+    where T: TxStore, A: AccountStore
+{
+    /// Creates an engine backed by the given stores.
+    ///
+    /// The accounts already in the store are opened in the ledger, as if their
+    /// funds had been deposited.
+    ///
+    /// # Panics
+    ///
+    /// If the funds of the accounts together are too large for the external account
+    /// to have provided.
+    pub fn with_stores(tx_map: T, acct_map: A) -> Self {
+        let mut ledger = Ledger::default();
+        for (client_id, acct) in acct_map.iter() {
+            ledger.open(client_id, &acct).expect("opening balances overflowed the external account");
+        }
+        Self::from_parts(tx_map, acct_map, ledger)
+    }
+
+    /// Creates an engine backed by the given stores, whose accounts already have
+    /// their balances in the ledger.
+    pub(crate) fn from_parts(tx_map: T, acct_map: A, ledger: Ledger) -> Self {
+        Self {
+            tx_map,
+            acct_map,
+            seq: 0,
+            wal: None,
+            config: Config::default(),
+            audit: Vec::new(),
+            journal: None,
+            subscribers: Vec::new(),
+            ledger,
+            checker: None,
+            history: None,
+            clocks: BTreeMap::new(),
+            usage: BTreeMap::new(),
+            open_disputes: None,
+        }
+    }
+
+    /// Logs every transaction accepted from now on to the write-ahead log.
+    pub fn set_wal(&mut self, wal: Wal) {
+        self.wal = Some(wal);
+    }
+
+    /// Empties the write-ahead log (if there is one), for once a snapshot has been
+    /// saved that covers every transaction in it.
+    pub fn truncate_wal(&mut self) -> io::Result<()> {
+        match self.wal.as_mut() {
+            Some(wal) => wal.truncate(),
+            None => Ok(()),
+        }
+    }
+
+    /// Sets the client account that fees are paid into and interest is paid out of.
+    ///
+    /// Fees and interest are rejected until there is one. Like a transfer's
+    /// destination, the house account only gets created once a fee or interest is
+    /// posted to it.
+    pub fn set_house_account(&mut self, client_id: u16) {
+        self.config.house_account = Some(client_id);
+    }
+
+    /// Sets whether a fee may take a client's available funds below zero, rather
+    /// than being rejected for insufficient funds (the default).
+    pub fn set_fee_overdraft(&mut self, allowed: bool) {
+        self.config.allow_fee_overdraft = allowed;
+    }
+
+    /// Sets how far a client's available funds may go below zero (their approved
+    /// overdraft), which is zero by default.
+    ///
+    /// Withdrawals, transfers, and fees can take the available funds down to the
+    /// negative of the limit, and so can disputes unless they're allowed to take
+    /// them any lower (see [`DisputePolicy::allow_negative_available`]). A negative
+    /// limit counts as zero.
+    pub fn set_credit_limit(&mut self, client_id: u16, limit: Amount) {
+        self.config.credit_limits.insert(client_id, limit);
+    }
+
+    /// Returns how far a client's available funds may go below zero (see
+    /// [`Engine::set_credit_limit`]).
+    pub fn credit_limit(&self, client_id: u16) -> Amount {
+        self.config.credit_limit(client_id)
+    }
+
+    /// Sets how much and how often clients can move funds (see [`crate::limits`]).
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.config.limits = limits;
+    }
+
+    /// Sets which disputes are allowed.
+    pub fn set_dispute_policy(&mut self, policy: DisputePolicy) {
+        self.config.dispute_policy = policy;
+    }
+
+    /// Sets which transactions are still allowed on locked accounts.
+    pub fn set_lock_policy(&mut self, policy: LockPolicy) {
+        self.config.lock_policy = policy;
+    }
+
+    /// Returns every administrative transaction the engine has accepted, in order.
+    pub fn audit_log(&self) -> &[AuditEntry] {
+        &self.audit
+    }
+
+    /// Keeps every journaled event in memory from now on (see [`Engine::journal`]).
+    pub fn keep_journal(&mut self) {
+        self.journal.get_or_insert_with(Vec::new);
+    }
+
+    /// Iterates over the events journaled since [`Engine::keep_journal`] was called.
+    pub fn journal(&self) -> impl Iterator<Item = &Event> {
+        self.journal.iter().flatten()
+    }
+
+    /// Sends every event journaled from now on to the subscriber.
+    pub fn subscribe<S>(&mut self, subscriber: S)
+        where S: Subscriber + 'static
+    {
+        self.subscribers.push(Box::new(subscriber));
+    }
+
+    /// Flushes every subscriber (see [`Subscriber::flush`]).
+    pub fn flush_journal(&mut self) -> std::io::Result<()> {
+        self.subscribers.iter_mut().try_for_each(|s| s.flush())
+    }
+
+    /// Sets whether to check the invariants (see [`crate::invariants`]) after every
+    /// accepted transaction, stopping at the first transaction that breaks one with
+    /// a fatal [`ProcessError::InvariantViolated`].
+    pub fn set_strict(&mut self, strict: bool) {
+        self.checker = strict.then(|| Checker::new(&self.tx_map));
+    }
+
+    /// Keeps a checkpoint of every account after every change from now on,
+    /// starting from the accounts' current balances (see [`Engine::balance_at`]).
+    pub fn keep_history(&mut self) {
+        if self.history.is_none() {
+            self.history = Some(History::new(self.acct_map.iter()));
+        }
+    }
+
+    /// Returns a client's account as it was at a point in time, if the client had
+    /// an account by then.
+    ///
+    /// Only the history kept since [`Engine::keep_history`] was called is known, so
+    /// a point before then gives the account as it was when the history started.
+    /// A client whose every transaction was rejected never has an account in the
+    /// history.
+    pub fn balance_at(&self, client_id: u16, as_of: AsOf) -> Option<&Acct> {
+        self.history.as_ref()?.at(client_id, as_of)
+    }
+
+    /// Iterates over every account as it was at a point in time, ordered by client
+    /// ID (see [`Engine::balance_at`]).
+    pub fn balances_at(&self, as_of: AsOf) -> impl Iterator<Item = (u16, Acct)> + '_ {
+        self.history.iter()
+            .flat_map(move |history| history.clients().filter_map(move |client_id| Some((client_id, history.at(client_id, as_of)?.clone()))))
+    }
+
+    /// Returns the ledger every movement of funds is posted to (see
+    /// [`crate::ledger`]).
+    pub fn ledger(&self) -> &Ledger {
+        &self.ledger
+    }
+
+    pub(crate) fn ledger_mut(&mut self) -> &mut Ledger {
+        &mut self.ledger
+    }
+
+    /// Returns the latest timestamp of each client's accepted transactions.
+    pub(crate) fn clocks(&self) -> &BTreeMap<u16, u64> {
+        &self.clocks
+    }
+
+    pub(crate) fn clocks_mut(&mut self) -> &mut BTreeMap<u16, u64> {
+        &mut self.clocks
+    }
+
+    /// Returns how much of the limits each client has used up.
+    pub(crate) fn usage(&self) -> &BTreeMap<u16, Usage> {
+        &self.usage
+    }
+
+    pub(crate) fn usage_mut(&mut self) -> &mut BTreeMap<u16, Usage> {
+        &mut self.usage
+    }
+
+    /// Forgets the open disputes, for when the recorded transactions were changed
+    /// behind the engine's back (e.g., by loading a snapshot).
+    pub(crate) fn reset_open_disputes(&mut self) {
+        self.open_disputes = None;
+    }
+
+    /// Returns the first feature in use that needs every transaction processed in
+    /// a single order, if any.
+    pub(crate) fn serial_feature(&self) -> Option<&'static str> {
+        if self.wal.is_some() {
+            Some("a write-ahead log")
+        } else if self.checker.is_some() {
+            Some("strict mode")
+        } else if self.history.is_some() {
+            Some("a history")
+        } else if self.journal.is_some() || !self.subscribers.is_empty() {
+            Some("a journal")
+        } else {
+            None
+        }
+    }
+
+    pub(crate) fn audit_mut(&mut self) -> &mut Vec<AuditEntry> {
+        &mut self.audit
+    }
+
+    pub(crate) fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub(crate) fn set_config(&mut self, config: Config) {
+        self.config = config;
+    }
+
+    /// Returns the number of transactions submitted to the engine so far (or, after
+    /// recovering from a write-ahead log, up to the last one that was accepted).
+    pub fn processed(&self) -> u64 {
+        self.seq
+    }
+
+    /// Processes a single transaction, updating the state of the client's account.
+    ///
+    /// A rejected transaction leaves the engine unchanged, except that the client's
+    /// account is created if this is the first time the client has been seen.
+    ///
+    /// If there is a write-ahead log, an accepted transaction is only acknowledged
+    /// once it has been logged. Failing to log it is a fatal error (see
+    /// [`ProcessError::is_fatal`]) since the engine then holds state the log
+    /// doesn't. The same goes for the journal's subscribers, which only get the
+    /// transaction's events once it has been logged (and get rejected transactions
+    /// too, see [`Subscriber::rejected`]). In strict mode, a transaction that breaks
+    /// an invariant is neither logged nor journaled.
+    ///
+    /// Before a timestamped transaction is processed, the client's disputes left
+    /// open past the resolution deadline by then are settled (see
+    /// [`Engine::settle_overdue`]), even if the transaction itself is rejected.
+    pub fn process_tx(&mut self, tx: Tx) -> Result<(), ProcessError> {
+        let seq = self.seq;
+        self.seq += 1;
+        self.settle_overdue(seq, &tx)?;
+        let events = match self.apply_observed(seq, &tx) {
+            Ok(events) => events,
+            Err(e) if e.is_fatal() => return Err(e),
+            Err(e) => {
+                self.publish_rejection(seq, &tx, &e)?;
+                return Err(e);
+            }
+        };
+        self.log(seq, &tx)?;
+        self.publish(&tx, events)
+    }
+
+    /// Settles every dispute of a transaction's client that was left open past the
+    /// resolution deadline by the time of the transaction, with a resolve (or
+    /// chargeback, see [`DisputePolicy::on_deadline`]) for the whole disputed
+    /// portion made at that time.
+    ///
+    /// Settlements are logged and journaled like any other accepted transaction
+    /// (under the sequence number of the transaction that triggered them). One that
+    /// is rejected (e.g., by the lock policy) leaves the dispute open, to be tried
+    /// again with the client's next transaction.
+    fn settle_overdue(&mut self, seq: u64, tx: &Tx) -> Result<(), ProcessError> {
+        let policy = self.config.dispute_policy;
+        let (Some(deadline), Some(now)) = (policy.resolution_deadline, tx.timestamp) else {
+            return Ok(());
+        };
+        // a transaction out of order doesn't move the clock (and gets rejected)
+        if self.clocks.get(&tx.client_id).is_some_and(|latest| now < *latest) {
+            return Ok(());
+        }
+        let Some(cutoff) = now.checked_sub(deadline) else {
+            return Ok(());
+        };
+
+        let tx_map = &self.tx_map;
+        let open = self.open_disputes.get_or_insert_with(|| {
+            tx_map.iter()
+                .filter_map(|(tx_id, t)| t.disputed_at.map(|at| (t.client_id, at, tx_id)))
+                .collect()
+        });
+        let overdue: Vec<u32> = open.range((tx.client_id, 0, 0)..(tx.client_id, cutoff, 0))
+            .map(|(_, _, tx_id)| *tx_id)
+            .collect();
+
+        for tx_id in overdue {
+            let settlement = Tx {
+                tx_type: policy.on_deadline.tx_type(),
+                client_id: tx.client_id,
+                tx_id,
+                amount: None,
+                dest: None,
+                reason: None,
+                timestamp: Some(now),
+            };
+            match self.apply_observed(seq, &settlement) {
+                Ok(events) => {
+                    self.log(seq, &settlement)?;
+                    self.publish(&settlement, events)?;
+                }
+                Err(e) if e.is_fatal() => return Err(e),
+                Err(_) => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Logs an accepted transaction to the write-ahead log, if there is one.
+    fn log(&mut self, seq: u64, tx: &Tx) -> Result<(), ProcessError> {
+        if let Some(wal) = self.wal.as_mut() {
+            wal.append(seq, tx).map_err(|e| ProcessError::WalWrite {
+                client_id: tx.client_id,
+                tx_id: tx.tx_id,
+                message: e.to_string(),
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Re-applies a transaction read back from a write-ahead log.
+    pub(crate) fn replay(&mut self, seq: u64, tx: Tx) -> Result<(), ProcessError> {
+        self.seq = seq + 1;
+        let events = self.apply_observed(seq, &tx)?;
+        self.publish(&tx, events)
+    }
+
+    /// Applies a transaction, returning its events if anything is journaling them,
+    /// checking the invariants in strict mode, and keeping a checkpoint of the
+    /// accounts it changed if the history is being kept.
+    fn apply_observed(&mut self, seq: u64, tx: &Tx) -> Result<Vec<Event>, ProcessError> {
+        let journaling = self.journal.is_some() || !self.subscribers.is_empty();
+        if !journaling && self.checker.is_none() && self.history.is_none() {
+            return self.apply(tx).map(|_| Vec::new());
+        }
+
+        let mut touched = vec![tx.client_id];
+        touched.extend(match tx.tx_type {
+            TxType::Transfer | TxType::Fee | TxType::Interest => self.other_account(tx.tx_type, tx.client_id, tx.tx_id, tx.dest).ok(),
+            TxType::Dispute | TxType::Resolve | TxType::Chargeback => self.tx_map.get(tx.tx_id).and_then(|t| t.dest),
+            _ => None,
+        });
+        let before: Vec<(u16, Acct)> = touched.into_iter()
+            .map(|client_id| (client_id, self.acct_map.get(client_id).unwrap_or_default()))
+            .collect();
+        let tx_before = self.checker.as_ref().and_then(|_| self.tx_map.get(tx.tx_id));
+
+        self.apply(tx)?;
+        let after: Vec<(u16, Acct)> = before.iter()
+            .map(|(client_id, _)| (*client_id, self.acct_map.get(*client_id).expect("a touched account exists")))
+            .collect();
+        self.check(tx, &before, &after, tx_before)?;
+        if let Some(history) = self.history.as_mut() {
+            history.record(seq, tx.timestamp, &after);
+        }
+
+        if !journaling {
+            return Ok(Vec::new());
+        }
+        Ok(before.iter().zip(&after)
+            .map(|((client_id, before), (_, after))| Event::new(seq, tx, *client_id, before, after))
+            .collect())
+    }
+
+    /// Checks the invariants after an accepted transaction, if in strict mode.
+    fn check(&mut self, tx: &Tx, before: &[(u16, Acct)], after: &[(u16, Acct)], tx_before: Option<RecTx>) -> Result<(), ProcessError> {
+        let Some(checker) = self.checker.as_mut() else {
+            return Ok(());
+        };
+        let tx_after = self.tx_map.get(tx.tx_id);
+        if tx.tx_type.is_recorded() {
+            checker.record(tx.tx_id, tx_after.as_ref().expect("an accepted transaction is recorded"));
+        }
+
+        checker.check(tx, &self.config, &self.tx_map, before, after).map_err(|(invariant, client_id)| ProcessError::InvariantViolated {
+            client_id: tx.client_id,
+            tx_id: tx.tx_id,
+            violation: Box::new(Violation {
+                invariant,
+                client_id,
+                before: before.to_vec(),
+                after: after.to_vec(),
+                tx_before,
+                tx_after,
+            }),
+        })
+    }
+
+    /// Hands the events of an accepted transaction to the journal and its subscribers.
+    fn publish(&mut self, tx: &Tx, events: Vec<Event>) -> Result<(), ProcessError> {
+        for event in &events {
+            for subscriber in &mut self.subscribers {
+                subscriber.event(event).map_err(|e| ProcessError::JournalWrite {
+                    client_id: tx.client_id,
+                    tx_id: tx.tx_id,
+                    message: e.to_string(),
+                })?;
+            }
+        }
+        if let Some(journal) = self.journal.as_mut() {
+            journal.extend(events);
+        }
+        Ok(())
+    }
+
+    /// Hands a rejected transaction to the journal's subscribers.
+    fn publish_rejection(&mut self, seq: u64, tx: &Tx, error: &ProcessError) -> Result<(), ProcessError> {
+        for subscriber in &mut self.subscribers {
+            subscriber.rejected(seq, tx, error).map_err(|e| ProcessError::JournalWrite {
+                client_id: tx.client_id,
+                tx_id: tx.tx_id,
+                message: e.to_string(),
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Processes a transaction whose other account (the destination of a transfer,
+    /// or the house account) lives in another engine (see [`crate::sharded`]).
+    pub(crate) fn process_with(&mut self, tx: &Tx, other_books: &mut Self) -> Result<(), ProcessError> {
+        self.seq += 1;
+        self.apply_with(tx, Some(other_books))
+    }
+
+    fn apply(&mut self, tx: &Tx) -> Result<(), ProcessError> {
+        self.apply_with(tx, None)
+    }
+
+    /// Applies a transaction, looking up its other account (see
+    /// [`Engine::other_account`]) in `other_books` if given, and in this engine
+    /// otherwise.
+    ///
+    /// A timestamped transaction can't be earlier than the client's latest
+    /// accepted one, and moves the client's clock forward once it's accepted.
+    fn apply_with(&mut self, tx: &Tx, other_books: Option<&mut Self>) -> Result<(), ProcessError> {
+        let (client_id, tx_id) = (tx.client_id, tx.tx_id);
+        if let Some(timestamp) = tx.timestamp {
+            if let Some(&latest) = self.clocks.get(&client_id).filter(|latest| timestamp < **latest) {
+                // the client still gets an account, like any other rejected transaction
+                self.acct_map.update(client_id, |_| ());
+                return Err(ProcessError::TimestampOutOfOrder { client_id, tx_id, timestamp, latest });
+            }
+        }
+        self.apply_unclocked(tx, other_books)?;
+        if let Some(timestamp) = tx.timestamp {
+            self.clocks.insert(client_id, timestamp);
+        }
+        Ok(())
+    }
+
+    fn apply_unclocked(&mut self, tx: &Tx, other_books: Option<&mut Self>) -> Result<(), ProcessError> {
+        let (client_id, tx_id) = (tx.client_id, tx.tx_id);
+
         // 1. Get the account associated with this transaction
         // NOTE: even if all transactions for an account are invalid we create a default account
-        let acct = self.acct_map.entry(tx.client_id).or_insert_with(Acct::default);
+        let mut acct = match self.acct_map.get(client_id) {
+            Some(acct) => acct,
+            None => {
+                self.acct_map.put(client_id, Acct::default());
+                Acct::default()
+            }
+        };
+
+        // 2. Locked accounts only allow what the lock policy allows
+        if let Some(lock) = acct.lock {
+            if !self.config.lock_policy.allows(lock, tx.tx_type) {
+                return Err(ProcessError::AccountLocked { client_id, tx_id });
+            }
+        }
 
-        // 2. Locked accounts are locked forever - no transactions can be processed for them
-        if acct.locked {
-            return Err("unable to process transaction - account locked".into());
+        // 3. Process administrative transactions (i.e. freezes, unfreezes, and closes)
+        if tx.tx_type.is_admin() {
+            let reason = match tx.reason.as_deref() {
+                Some(reason) if !reason.is_empty() => reason,
+                _ => return Err(ProcessError::MissingReason { client_id, tx_id }),
+            };
+            let to = match (&tx.tx_type, acct.lock) {
+                (TxType::Freeze, None) => Some(LockReason::Frozen),
+                (TxType::Unfreeze, Some(LockReason::Frozen | LockReason::Chargeback)) => None,
+                (TxType::Close, lock) if lock != Some(LockReason::Closed) => Some(LockReason::Closed),
+                (_, lock) => return Err(ProcessError::InvalidLockChange { client_id, tx_id, lock, requested: tx.tx_type }),
+            };
+            self.audit.push(AuditEntry {
+                client_id,
+                tx_id,
+                action: tx.tx_type,
+                reason: reason.to_owned(),
+                from: acct.lock,
+                to,
+            });
+            acct.lock = to;
+            self.acct_map.put(client_id, acct);
+            return Ok(());
         }
 
-        // 3a. Process "recorded" transactions (i.e. deposits and withdraws)
-        if let TxType::Deposit | TxType::Withdrawal = tx.tx_type {
-            if self.tx_map.contains_key(&tx.tx_id) {
-                return Err(format!("transaction id {} already exists", tx.tx_id).into());
+        // 4a. Process "recorded" transactions (i.e. deposits, withdraws, transfers, fees, and interest)
+        if tx.tx_type.is_recorded() {
+            if self.tx_map.contains(tx_id) {
+                return Err(ProcessError::DuplicateTxId { client_id, tx_id });
             }
-            match tx.amount {
-                Some(amt) => {
-                    match &tx.tx_type {
-                        TxType::Deposit => acct.deposit(amt)?,
-                        TxType::Withdrawal => acct.withdrawal(amt)?,
-                        _ => unreachable!(),
-                    }
-                    self.tx_map.insert(tx.tx_id, tx.into());
+            let Some(amt) = tx.amount else {
+                return Err(ProcessError::MissingAmount { client_id, tx_id });
+            };
+            let client = LedgerAcct::Available(client_id);
+            let (debit, credit, other) = match &tx.tx_type {
+                TxType::Deposit => (LedgerAcct::External, client, None),
+                TxType::Withdrawal => (client, LedgerAcct::External, None),
+                TxType::Transfer | TxType::Fee => {
+                    let other = self.other_account(tx.tx_type, client_id, tx_id, tx.dest)?;
+                    (client, LedgerAcct::Available(other), Some(other))
                 }
-                None => return Err(format!("transaction {} missing amount", tx.tx_id).into()),
+                TxType::Interest => {
+                    let house = self.other_account(tx.tx_type, client_id, tx_id, tx.dest)?;
+                    (LedgerAcct::Available(house), client, Some(house))
+                }
+                _ => unreachable!(),
+            };
+            if !amt.is_positive() {
+                return Err(ProcessError::NonPositiveAmount { client_id, tx_id });
             }
+            let limits = self.config.limits;
+            limits.check(tx, amt, self.usage.get(&client_id)).map_err(|rule| ProcessError::LimitExceeded { client_id, tx_id, rule })?;
+            // only fees (when allowed to) and interest (for the house) can overdraw an
+            // account past its credit limit, and interest only takes from the house
+            let guarded = match tx.tx_type {
+                TxType::Fee => !self.config.allow_fee_overdraft,
+                TxType::Interest => false,
+                _ => true,
+            };
+            let entry = Entry::new(debit, credit, amt).expect("amount is positive");
+            self.post(client_id, tx_id, other, other_books, &[entry], guarded)?;
+            self.tx_map.put(tx_id, RecTx::from(tx));
+            limits.record(tx, amt, &mut self.usage);
         }
-        // 3b. Process "non-recorded" transaction (i.e. dispute-related)
+        // 4b. Process "non-recorded" transaction (i.e. dispute-related)
         // NOTE: all dispute-related transactions only make sense if their transaction ID exists
-        else if let Some(mut t) = self.tx_map.get_mut(&tx.tx_id) {
-            if t.client_id != tx.client_id {
-                return Err(format!("no transaction {} for client {}", tx.tx_id, t.client_id).into());
+        else {
+            let mut t = self.tx_map.get(tx_id).ok_or(ProcessError::UnknownTx { client_id, tx_id })?;
+            if t.client_id != client_id {
+                return Err(ProcessError::ClientMismatch { client_id, tx_id });
             }
-            match &tx.tx_type {
-                TxType::Deposit | TxType::Withdrawal => unreachable!(),
-                TxType::Dispute if TxState::Undisputed == t.state => {
-                    t.state = TxState::Disputed;
-                    acct.dispute(t.amount);
+            let policy = self.config.dispute_policy;
+            let disputable = match t.kind {
+                TxType::Fee | TxType::Interest => false,
+                TxType::Withdrawal => policy.withdrawals_disputable,
+                _ => true,
+            };
+            if !disputable {
+                return Err(ProcessError::NotDisputable { client_id, tx_id });
+            }
+            // disputes take from the undisputed portion, and resolves and chargebacks
+            // from the disputed one (all of it unless the row gives an amount)
+            let overflow = || ProcessError::Overflow { client_id, tx_id };
+            let portion = match tx.tx_type {
+                TxType::Dispute => t.undisputed().ok_or_else(overflow)?,
+                _ => t.disputed,
+            };
+            if portion == Amount::ZERO {
+                return Err(ProcessError::InvalidStateTransition { client_id, tx_id, from: t.state(), requested: tx.tx_type });
+            }
+            if tx.tx_type == TxType::Dispute && !policy.allows_dispute(t.disputes) {
+                return Err(ProcessError::DisputeLimitReached { client_id, tx_id });
+            }
+            if let (TxType::Dispute, Some(window), Some(made), Some(now)) = (tx.tx_type, policy.dispute_window, t.timestamp, tx.timestamp) {
+                if now.saturating_sub(made) > window {
+                    return Err(ProcessError::DisputeWindowClosed { client_id, tx_id });
                 }
-                TxType::Resolve if TxState::Disputed == t.state => {
-                    t.state = TxState::Undisputed;
-                    acct.resolve(t.amount);
+            }
+            // amounts on rows are positive, while portions are from the client's side
+            let side = |amount: Amount| if t.amount.is_negative() { amount.checked_neg().ok_or_else(overflow) } else { Ok(amount) };
+            let amount = match tx.amount {
+                None => portion,
+                Some(amount) if !amount.is_positive() => return Err(ProcessError::NonPositiveAmount { client_id, tx_id }),
+                Some(amount) if amount > side(portion)? => {
+                    return Err(ProcessError::DisputeAmountExceeded { client_id, tx_id, requested: tx.tx_type, amount, max: side(portion)? });
                 }
-                TxType::Chargeback if TxState::Disputed == t.state => {
-                    t.state = TxState::Chargebacked;
-                    acct.chargeback(t.amount);
+                Some(amount) => side(amount)?,
+            };
+            // the new portions are worked out before anything is posted, so that an
+            // overflow leaves everything untouched
+            let (disputed, charged_back) = match tx.tx_type {
+                TxType::Dispute => (t.disputed.checked_add(amount), Some(t.charged_back)),
+                TxType::Resolve => (t.disputed.checked_sub(amount), Some(t.charged_back)),
+                _ => (t.disputed.checked_sub(amount), t.charged_back.checked_add(amount)),
+            };
+            let (disputed, charged_back) = (disputed.ok_or_else(overflow)?, charged_back.ok_or_else(overflow)?);
+            let dest = t.dest;
+
+            // the sending side of a transfer is disputed like a withdrawal, and the
+            // receiving side like a deposit (that's clawed back without locking the
+            // receiving account on a chargeback)
+            let entry = |client_id, amount| match &tx.tx_type {
+                TxType::Dispute => Entry::new(LedgerAcct::Available(client_id), LedgerAcct::Held(client_id), amount),
+                TxType::Resolve => Entry::new(LedgerAcct::Held(client_id), LedgerAcct::Available(client_id), amount),
+                TxType::Chargeback => Entry::new(LedgerAcct::Held(client_id), LedgerAcct::Settlement, amount),
+                _ => unreachable!(),
+            }.ok_or_else(overflow);
+            let guarded = tx.tx_type == TxType::Dispute && !policy.allow_negative_available;
+            match dest {
+                None => self.post(client_id, tx_id, None, other_books, &[entry(client_id, amount)?], guarded)?,
+                Some(dest) => {
+                    let entries = [entry(client_id, amount)?, entry(dest, amount.checked_neg().ok_or_else(overflow)?)?];
+                    self.post(client_id, tx_id, Some(dest), other_books, &entries, guarded)?
                 }
-                _ => return Err(format!("invalid tx {:?} for state {:?}", tx.tx_type, t.state).into()),
             }
+            if tx.tx_type == TxType::Chargeback {
+                self.acct_map.update(client_id, |acct| acct.lock = acct.lock.max(Some(LockReason::Chargeback)));
+            }
+
+            let was_open = t.disputed_at;
+            if tx.tx_type == TxType::Dispute {
+                if t.disputed == Amount::ZERO {
+                    t.disputed_at = tx.timestamp;
+                }
+                t.disputes += 1;
+            }
+            t.disputed = disputed;
+            t.charged_back = charged_back;
+            if t.disputed == Amount::ZERO {
+                t.disputed_at = None;
+            }
+
+            // keeps the open disputes up to date, if they've been built yet
+            if let Some(open) = self.open_disputes.as_mut() {
+                if let Some(at) = was_open {
+                    open.remove(&(client_id, at, tx_id));
+                }
+                if let Some(at) = t.disputed_at {
+                    open.insert((client_id, at, tx_id));
+                }
+            }
+            self.tx_map.put(tx_id, t);
+        }
+        Ok(())
+    }
+
+    /// Returns the other account a transaction moves funds to or from: the
+    /// destination of a transfer, or the house account for fees and interest.
+    fn other_account(&self, tx_type: TxType, client_id: u16, tx_id: u32, dest: Option<u16>) -> Result<u16, ProcessError> {
+        let other = match tx_type {
+            TxType::Transfer => dest.ok_or(ProcessError::InvalidDestination { client_id, tx_id })?,
+            _ => self.config.house_account.ok_or(ProcessError::NoHouseAccount { client_id, tx_id })?,
+        };
+        if other == client_id {
+            return Err(ProcessError::InvalidDestination { client_id, tx_id });
+        }
+        Ok(other)
+    }
+
+    /// Posts entries to the ledger, changing the client's account, another client's
+    /// account (kept by `other_books` if given), and the accounts outside of any
+    /// client together. Either account refusing the change (or the other account
+    /// being locked) leaves everything untouched, and the other account isn't
+    /// created unless the entries are posted.
+    ///
+    /// If `guarded`, the entries may not take either client's available funds below
+    /// their credit limit.
+    fn post(&mut self, client_id: u16, tx_id: u32, other: Option<u16>, other_books: Option<&mut Self>, entries: &[Entry], guarded: bool) -> Result<(), ProcessError> {
+        let acct_err = |e| ProcessError::from_acct(e, client_id, tx_id);
+        // credit limits are never negative, so they can always be flipped into floors
+        let floor = |client_id| guarded.then(|| self.config.credit_limit(client_id).checked_neg().expect("credit limit is positive"));
+        let (floor, other_floor) = (floor(client_id), other.and_then(floor));
+        let owned_by = |client_id| move |acct: LedgerAcct| acct.client() == Some(client_id);
+
+        let lock = self.acct_map.get(client_id).unwrap_or_default().lock;
+        let posting = self.ledger.stage(entries, owned_by(client_id)).map_err(acct_err)?;
+        let acct = self.ledger.acct(&posting, client_id, lock, floor).map_err(acct_err)?;
+        let outside = self.ledger.stage(entries, LedgerAcct::is_outside).map_err(acct_err)?;
+
+        // the other account is only created once the entries are posted
+        let mut other_posting = None;
+        if let Some(other) = other {
+            let books = other_books.as_deref().unwrap_or(self);
+            let lock = books.acct_map.get(other).unwrap_or_default().lock;
+            if lock.is_some() {
+                return Err(ProcessError::DestinationLocked { client_id, tx_id, dest: other });
+            }
+            let refused = |reason| ProcessError::DestinationRefused { client_id, tx_id, dest: other, reason };
+            let posting = books.ledger.stage(entries, owned_by(other)).map_err(refused)?;
+            let acct = books.ledger.acct(&posting, other, lock, other_floor).map_err(refused)?;
+            other_posting = Some((other, posting, acct));
+        }
+
+        self.ledger.commit(posting);
+        self.ledger.commit(outside);
+        self.acct_map.put(client_id, acct);
+        if let Some((other, posting, acct)) = other_posting {
+            let books = other_books.unwrap_or(self);
+            books.ledger.commit(posting);
+            books.acct_map.put(other, acct);
         }
         Ok(())
     }
@@ -116,18 +910,35 @@ impl Engine {
 mod test {
     use super::*;
     use csv::{ReaderBuilder, Trim};
+    use crate::account::AcctError;
+    use crate::ledger::Imbalance;
+    use crate::limits::{LimitRule, Velocity};
+    use crate::policy::DeadlineAction;
+
+    fn amt(s: &str) -> Amount {
+        s.parse().unwrap()
+    }
+
+    /// A recorded transaction that has never been disputed.
+    fn rec(client_id: u16, kind: TxType, amount: &str) -> RecTx {
+        RecTx{ client_id, kind, amount: amt(amount), disputed: Amount::ZERO, charged_back: Amount::ZERO, disputes: 0, dest: None, timestamp: None, disputed_at: None }
+    }
 
     struct TestDef {
         input_data: &'static str,
         expected_transactions: Vec<(u32, RecTx)>,
         expected_accounts: Vec<(u16, Acct)>,
-        errors: Vec<String>,
+        errors: Vec<ProcessError>,
     }
 
     impl TestDef {
         fn run(&mut self) {
-            // engine to do the processing
-            let mut engine = Engine::default();
+            self.run_with(Engine::new());
+        }
+
+        fn run_with(&mut self, mut engine: Engine) {
+            // every test also checks the invariants hold after every transaction
+            engine.set_strict(true);
 
             // build a reader for the csv data
             let mut reader = ReaderBuilder::new()
@@ -139,21 +950,22 @@ mod test {
             for res in reader.deserialize() {
                 let tx: Tx = res.expect("unable to deserialize row");
                 if let Err(e) = engine.process_tx(tx) {
-                    self.errors.push(e.to_string());
+                    assert!(!e.is_fatal(), "{e}");
+                    self.errors.push(e);
                 }
             }
 
             // verify transactions
             assert_eq!(self.expected_transactions.len(), engine.tx_map.len());
             for (id, tx) in &self.expected_transactions {
-                let t = engine.tx_map.get(&id).expect("expected transaction {id}");
+                let t = engine.tx_map.get(id).expect("expected transaction {id}");
                 assert_eq!(*tx, *t);
             }
 
             // verify accounts
             assert_eq!(self.expected_accounts.len(), engine.acct_map.len());
             for (id, acct) in &self.expected_accounts {
-                let a = engine.acct_map.get(&id).expect("expected account for client {id}");
+                let a = engine.acct_map.get(id).expect("expected account for client {id}");
                 assert_eq!(*acct, *a);
             }
         }
@@ -167,13 +979,13 @@ mod test {
                 deposit,    2,  2,  2.0
                 deposit,    1,  3,  2.0",
             expected_transactions: vec![
-                (1, RecTx{ client_id: 1, amount: 1.0, state: TxState::Undisputed }),
-                (2, RecTx{ client_id: 2, amount: 2.0, state: TxState::Undisputed }),
-                (3, RecTx{ client_id: 1, amount: 2.0, state: TxState::Undisputed }),
+                (1, rec(1, TxType::Deposit, "1.0")),
+                (2, rec(2, TxType::Deposit, "2.0")),
+                (3, rec(1, TxType::Deposit, "2.0")),
             ],
             expected_accounts: vec![
-                (1, Acct{ available: 3.0, held: 0.0, total: 3.0, locked: false }),
-                (2, Acct{ available: 2.0, held: 0.0, total: 2.0, locked: false }),
+                (1, Acct{ available: amt("3.0"), held: amt("0.0"), total: amt("3.0"), lock: None }),
+                (2, Acct{ available: amt("2.0"), held: amt("0.0"), total: amt("2.0"), lock: None }),
             ],
             errors: vec![],
         };
@@ -189,13 +1001,13 @@ mod test {
                 deposit,    2,  2,  2.0
                 withdrawal, 1,  3,  0.5",
             expected_transactions: vec![
-                (1, RecTx{ client_id: 1, amount: 1.0, state: TxState::Undisputed }),
-                (2, RecTx{ client_id: 2, amount: 2.0, state: TxState::Undisputed }),
-                (3, RecTx{ client_id: 1, amount: -0.5, state: TxState::Undisputed }),
+                (1, rec(1, TxType::Deposit, "1.0")),
+                (2, rec(2, TxType::Deposit, "2.0")),
+                (3, rec(1, TxType::Withdrawal, "-0.5")),
             ],
             expected_accounts: vec![
-                (1, Acct{ available: 0.5, held: 0.0, total: 0.5, locked: false }),
-                (2, Acct{ available: 2.0, held: 0.0, total: 2.0, locked: false }),
+                (1, Acct{ available: amt("0.5"), held: amt("0.0"), total: amt("0.5"), lock: None }),
+                (2, Acct{ available: amt("2.0"), held: amt("0.0"), total: amt("2.0"), lock: None }),
             ],
             errors: vec![],
         };
@@ -211,17 +1023,17 @@ mod test {
                 deposit,    2,  2,  2.0
                 withdrawal, 1,  3,  1.1",
             expected_transactions: vec![
-                (1, RecTx{ client_id: 1, amount: 1.0, state: TxState::Undisputed }),
-                (2, RecTx{ client_id: 2, amount: 2.0, state: TxState::Undisputed }),
+                (1, rec(1, TxType::Deposit, "1.0")),
+                (2, rec(2, TxType::Deposit, "2.0")),
             ],
             expected_accounts: vec![
-                (1, Acct{ available: 1.0, held: 0.0, total: 1.0, locked: false }),
-                (2, Acct{ available: 2.0, held: 0.0, total: 2.0, locked: false }),
+                (1, Acct{ available: amt("1.0"), held: amt("0.0"), total: amt("1.0"), lock: None }),
+                (2, Acct{ available: amt("2.0"), held: amt("0.0"), total: amt("2.0"), lock: None }),
             ],
             errors: vec![],
         };
         test.run();
-        assert!(!test.errors.is_empty());
+        assert_eq!(vec![ProcessError::InsufficientFunds { client_id: 1, tx_id: 3 }], test.errors);
     }
 
     #[test]
@@ -232,12 +1044,12 @@ mod test {
                 deposit,    2,  2,  2.0
                 dispute,    1,  1,  ",      // NOTE - we can't end the CSV data with a newline when the last line has a blank optional value
             expected_transactions: vec![
-                (1, RecTx{ client_id: 1, amount: 1.0, state: TxState::Disputed }),
-                (2, RecTx{ client_id: 2, amount: 2.0, state: TxState::Undisputed }),
+                (1, RecTx{ disputed: amt("1.0"), disputes: 1, ..rec(1, TxType::Deposit, "1.0") }),
+                (2, rec(2, TxType::Deposit, "2.0")),
             ],
             expected_accounts: vec![
-                (1, Acct{ available: 0.0, held: 1.0, total: 1.0, locked: false }),
-                (2, Acct{ available: 2.0, held: 0.0, total: 2.0, locked: false }),
+                (1, Acct{ available: amt("0.0"), held: amt("1.0"), total: amt("1.0"), lock: None }),
+                (2, Acct{ available: amt("2.0"), held: amt("0.0"), total: amt("2.0"), lock: None }),
             ],
             errors: vec![],
         };
@@ -253,11 +1065,11 @@ mod test {
                 withdrawal, 1,  2,  0.5
                 dispute,    1,  2,  ",      // NOTE - we can't end the CSV data with a newline when the last line has a blank optional value
             expected_transactions: vec![
-                (1, RecTx{ client_id: 1, amount: 1.0, state: TxState::Undisputed }),
-                (2, RecTx{ client_id: 1, amount: -0.5, state: TxState::Disputed }),
+                (1, rec(1, TxType::Deposit, "1.0")),
+                (2, RecTx{ disputed: amt("-0.5"), disputes: 1, ..rec(1, TxType::Withdrawal, "-0.5") }),
             ],
             expected_accounts: vec![
-                (1, Acct{ available: 1.0, held: -0.5, total: 0.5, locked: false }),
+                (1, Acct{ available: amt("1.0"), held: amt("-0.5"), total: amt("0.5"), lock: None }),
             ],
             errors: vec![],
         };
@@ -274,12 +1086,12 @@ mod test {
                 dispute,    1,  1,
                 resolve,    1,  1,  ",      // NOTE - we can't end the CSV data with a newline when the last line has a blank optional value
             expected_transactions: vec![
-                (1, RecTx{ client_id: 1, amount: 1.0, state: TxState::Undisputed }),
-                (2, RecTx{ client_id: 2, amount: 2.0, state: TxState::Undisputed }),
+                (1, RecTx{ disputes: 1, ..rec(1, TxType::Deposit, "1.0") }),
+                (2, rec(2, TxType::Deposit, "2.0")),
             ],
             expected_accounts: vec![
-                (1, Acct{ available: 1.0, held: 0.0, total: 1.0, locked: false }),
-                (2, Acct{ available: 2.0, held: 0.0, total: 2.0, locked: false }),
+                (1, Acct{ available: amt("1.0"), held: amt("0.0"), total: amt("1.0"), lock: None }),
+                (2, Acct{ available: amt("2.0"), held: amt("0.0"), total: amt("2.0"), lock: None }),
             ],
             errors: vec![],
         };
@@ -296,11 +1108,11 @@ mod test {
                 dispute,    1,  2,
                 resolve,    1,  2,  ",      // NOTE - we can't end the CSV data with a newline when the last line has a blank optional value
             expected_transactions: vec![
-                (1, RecTx{ client_id: 1, amount: 1.0, state: TxState::Undisputed }),
-                (2, RecTx{ client_id: 1, amount: -0.5, state: TxState::Undisputed }),
+                (1, rec(1, TxType::Deposit, "1.0")),
+                (2, RecTx{ disputes: 1, ..rec(1, TxType::Withdrawal, "-0.5") }),
             ],
             expected_accounts: vec![
-                (1, Acct{ available: 0.5, held: 0.0, total: 0.5, locked: false }),
+                (1, Acct{ available: amt("0.5"), held: amt("0.0"), total: amt("0.5"), lock: None }),
             ],
             errors: vec![],
         };
@@ -317,12 +1129,12 @@ mod test {
                 dispute,    1,  1,
                 chargeback, 1,  1,  ",      // NOTE - we can't end the CSV data with a newline when the last line has a blank optional value
             expected_transactions: vec![
-                (1, RecTx{ client_id: 1, amount: 1.0, state: TxState::Chargebacked }),
-                (2, RecTx{ client_id: 2, amount: 2.0, state: TxState::Undisputed }),
+                (1, RecTx{ charged_back: amt("1.0"), disputes: 1, ..rec(1, TxType::Deposit, "1.0") }),
+                (2, rec(2, TxType::Deposit, "2.0")),
             ],
             expected_accounts: vec![
-                (1, Acct{ available: 0.0, held: 0.0, total: 0.0, locked: true }),
-                (2, Acct{ available: 2.0, held: 0.0, total: 2.0, locked: false }),
+                (1, Acct{ available: amt("0.0"), held: amt("0.0"), total: amt("0.0"), lock: Some(LockReason::Chargeback) }),
+                (2, Acct{ available: amt("2.0"), held: amt("0.0"), total: amt("2.0"), lock: None }),
             ],
             errors: vec![],
         };
@@ -339,11 +1151,11 @@ mod test {
                 dispute,    1,  2,
                 chargeback, 1,  2,  ",      // NOTE - we can't end the CSV data with a newline when the last line has a blank optional value
             expected_transactions: vec![
-                (1, RecTx{ client_id: 1, amount: 1.0, state: TxState::Undisputed }),
-                (2, RecTx{ client_id: 1, amount: -0.5, state: TxState::Chargebacked }),
+                (1, rec(1, TxType::Deposit, "1.0")),
+                (2, RecTx{ charged_back: amt("-0.5"), disputes: 1, ..rec(1, TxType::Withdrawal, "-0.5") }),
             ],
             expected_accounts: vec![
-                (1, Acct{ available: 1.0, held: 0.0, total: 1.0, locked: true }),
+                (1, Acct{ available: amt("1.0"), held: amt("0.0"), total: amt("1.0"), lock: Some(LockReason::Chargeback) }),
             ],
             errors: vec![],
         };
@@ -359,16 +1171,792 @@ mod test {
                 dispute,    2,  1,
                 chargeback, 3,  1,  ",      // NOTE - we can't end the CSV data with a newline when the last line has a blank optional value
             expected_transactions: vec![
-                (1, RecTx{ client_id: 1, amount: 1.0, state: TxState::Undisputed }),
+                (1, rec(1, TxType::Deposit, "1.0")),
+            ],
+            expected_accounts: vec![
+                (1, Acct{ available: amt("1.0"), held: amt("0.0"), total: amt("1.0"), lock: None }),
+                (2, Acct{ available: amt("0.0"), held: amt("0.0"), total: amt("0.0"), lock: None }),
+                (3, Acct{ available: amt("0.0"), held: amt("0.0"), total: amt("0.0"), lock: None }),
+            ],
+            errors: vec![],
+        };
+        test.run();
+        assert_eq!(vec![
+            ProcessError::ClientMismatch { client_id: 2, tx_id: 1 },
+            ProcessError::ClientMismatch { client_id: 3, tx_id: 1 },
+        ], test.errors);
+    }
+
+    #[test]
+    fn duplicate_and_missing() {
+        let mut test = TestDef{
+            input_data: "type, client, tx, amount
+                deposit,    1,  1,  1.0
+                deposit,    1,  1,  2.0
+                withdrawal, 2,  1,  0.5
+                deposit,    1,  2,
+                deposit,    1,  3,  0.0",
+            expected_transactions: vec![
+                (1, rec(1, TxType::Deposit, "1.0")),
             ],
             expected_accounts: vec![
-                (1, Acct{ available: 1.0, held: 0.0, total: 1.0, locked: false }),
-                (2, Acct{ available: 0.0, held: 0.0, total: 0.0, locked: false }),
-                (3, Acct{ available: 0.0, held: 0.0, total: 0.0, locked: false }),
+                (1, Acct{ available: amt("1.0"), held: amt("0.0"), total: amt("1.0"), lock: None }),
+                (2, Acct{ available: amt("0.0"), held: amt("0.0"), total: amt("0.0"), lock: None }),
             ],
             errors: vec![],
         };
         test.run();
-        assert!(!test.errors.is_empty());
+        assert_eq!(vec![
+            ProcessError::DuplicateTxId { client_id: 1, tx_id: 1 },
+            ProcessError::DuplicateTxId { client_id: 2, tx_id: 1 },
+            ProcessError::MissingAmount { client_id: 1, tx_id: 2 },
+            ProcessError::NonPositiveAmount { client_id: 1, tx_id: 3 },
+        ], test.errors);
+    }
+
+    #[test]
+    fn invalid_dispute_transitions() {
+        let mut test = TestDef{
+            input_data: "type, client, tx, amount
+                deposit,    1,  1,  1.0
+                resolve,    1,  1,
+                chargeback, 1,  1,
+                dispute,    1,  2,
+                dispute,    1,  1,
+                dispute,    1,  1,
+                chargeback, 1,  1,
+                deposit,    1,  3,  1.0",
+            expected_transactions: vec![
+                (1, RecTx{ charged_back: amt("1.0"), disputes: 1, ..rec(1, TxType::Deposit, "1.0") }),
+            ],
+            expected_accounts: vec![
+                (1, Acct{ available: amt("0.0"), held: amt("0.0"), total: amt("0.0"), lock: Some(LockReason::Chargeback) }),
+            ],
+            errors: vec![],
+        };
+        test.run();
+        assert_eq!(vec![
+            ProcessError::InvalidStateTransition { client_id: 1, tx_id: 1, from: TxState::Undisputed, requested: TxType::Resolve },
+            ProcessError::InvalidStateTransition { client_id: 1, tx_id: 1, from: TxState::Undisputed, requested: TxType::Chargeback },
+            ProcessError::UnknownTx { client_id: 1, tx_id: 2 },
+            ProcessError::InvalidStateTransition { client_id: 1, tx_id: 1, from: TxState::Disputed, requested: TxType::Dispute },
+            ProcessError::AccountLocked { client_id: 1, tx_id: 3 },
+        ], test.errors);
+        assert_eq!("account_locked", test.errors[4].code());
+    }
+
+    #[test]
+    fn transfers() {
+        let mut test = TestDef{
+            input_data: "type, client, tx, amount, dest
+                deposit,    1,  1,  2.0,
+                deposit,    3,  2,  1.0,
+                dispute,    3,  2,  ,
+                chargeback, 3,  2,  ,
+                transfer,   1,  3,  0.5,    2
+                transfer,   1,  4,  5.0,    2
+                transfer,   1,  5,  0.5,    3
+                transfer,   1,  6,  0.5,    1
+                transfer,   1,  7,  0.5,
+                transfer,   1,  3,  0.5,    2",
+            expected_transactions: vec![
+                (1, rec(1, TxType::Deposit, "2.0")),
+                (2, RecTx{ charged_back: amt("1.0"), disputes: 1, ..rec(3, TxType::Deposit, "1.0") }),
+                (3, RecTx{ dest: Some(2), ..rec(1, TxType::Transfer, "-0.5") }),
+            ],
+            expected_accounts: vec![
+                (1, Acct{ available: amt("1.5"), held: amt("0.0"), total: amt("1.5"), lock: None }),
+                (2, Acct{ available: amt("0.5"), held: amt("0.0"), total: amt("0.5"), lock: None }),
+                (3, Acct{ available: amt("0.0"), held: amt("0.0"), total: amt("0.0"), lock: Some(LockReason::Chargeback) }),
+            ],
+            errors: vec![],
+        };
+        test.run();
+        assert_eq!(vec![
+            ProcessError::InsufficientFunds { client_id: 1, tx_id: 4 },
+            ProcessError::DestinationLocked { client_id: 1, tx_id: 5, dest: 3 },
+            ProcessError::InvalidDestination { client_id: 1, tx_id: 6 },
+            ProcessError::InvalidDestination { client_id: 1, tx_id: 7 },
+            ProcessError::DuplicateTxId { client_id: 1, tx_id: 3 },
+        ], test.errors);
+    }
+
+    #[test]
+    fn transfer_refused() {
+        let mut test = TestDef{
+            input_data: "type, client, tx, amount, dest
+                deposit,    1,  1,  2.0,
+                transfer,   1,  2,  5.0,    4
+                transfer,   1,  3,  1.0,    2
+                withdrawal, 2,  4,  1.0,
+                dispute,    1,  3,  ,
+                transfer,   1,  5,  0.0001, 3",
+            expected_transactions: vec![
+                (1, rec(1, TxType::Deposit, "2.0")),
+                (3, RecTx{ dest: Some(2), ..rec(1, TxType::Transfer, "-1.0") }),
+                (4, rec(2, TxType::Withdrawal, "-1.0")),
+            ],
+            // the destination of a rejected transfer doesn't get an account
+            expected_accounts: vec![
+                (1, Acct{ available: amt("1.0"), held: amt("0.0"), total: amt("1.0"), lock: None }),
+                (2, Acct{ available: amt("0.0"), held: amt("0.0"), total: amt("0.0"), lock: None }),
+                (3, Acct{ available: amt("922337203685477.5807"), held: amt("0.0"), total: amt("922337203685477.5807"), lock: None }),
+            ],
+            errors: vec![],
+        };
+        let full = Acct{ available: amt("922337203685477.5807"), total: amt("922337203685477.5807"), ..Default::default() };
+        let mut engine = Engine::with_stores(TxMap::new(), AcctMap::from([(3, full)]));
+        engine.set_dispute_policy(DisputePolicy{ allow_negative_available: false, ..Default::default() });
+        // the full account's funds came from charged back withdrawals, leaving the
+        // external account room for deposits
+        engine.ledger_mut().set(LedgerAcct::External, Amount::ZERO);
+        engine.ledger_mut().set(LedgerAcct::Settlement, Amount::from_raw(-i64::MAX));
+        test.run_with(engine);
+        // errors on the receiving side name the receiving account
+        assert_eq!(vec![
+            ProcessError::InsufficientFunds { client_id: 1, tx_id: 2 },
+            ProcessError::DestinationRefused { client_id: 1, tx_id: 3, dest: 2, reason: AcctError::InsufficientFunds },
+            ProcessError::DestinationRefused { client_id: 1, tx_id: 5, dest: 3, reason: AcctError::Overflow },
+        ], test.errors);
+    }
+
+    #[test]
+    fn dispute_transfer() {
+        let mut test = TestDef{
+            input_data: "type, client, tx, amount, dest
+                deposit,    1,  1,  2.0,
+                transfer,   1,  2,  0.5,    2
+                dispute,    1,  2,  ,
+                resolve,    1,  2,  ,
+                dispute,    1,  2,  ,
+                chargeback, 1,  2,  ,
+                deposit,    2,  3,  1.0,",
+            expected_transactions: vec![
+                (1, rec(1, TxType::Deposit, "2.0")),
+                (2, RecTx{ charged_back: amt("-0.5"), disputes: 2, dest: Some(2), ..rec(1, TxType::Transfer, "-0.5") }),
+                (3, rec(2, TxType::Deposit, "1.0")),
+            ],
+            expected_accounts: vec![
+                // the sender gets the funds back and is locked, and the receiver loses them
+                (1, Acct{ available: amt("2.0"), held: amt("0.0"), total: amt("2.0"), lock: Some(LockReason::Chargeback) }),
+                (2, Acct{ available: amt("1.0"), held: amt("0.0"), total: amt("1.0"), lock: None }),
+            ],
+            errors: vec![],
+        };
+        test.run();
+        assert!(test.errors.is_empty());
+    }
+
+    #[test]
+    fn dispute_overflow() {
+        // only reachable with a store changed behind the engine's back, but still
+        // rejected rather than wrapping around
+        let mut engine = Engine::new();
+        let broken = RecTx{ disputed: Amount::from_raw(-1), ..rec(1, TxType::Deposit, "922337203685477.5807") };
+        engine.tx_map.insert(1, broken.clone());
+        let dispute = Tx{ tx_type: TxType::Dispute, client_id: 1, tx_id: 1, amount: None, dest: None, reason: None, timestamp: None };
+        assert_eq!(Err(ProcessError::Overflow { client_id: 1, tx_id: 1 }), engine.process_tx(dispute));
+        assert_eq!(broken, engine.tx_map[&1]);
+    }
+
+    #[test]
+    fn partial_disputes() {
+        let mut test = TestDef{
+            input_data: "type, client, tx, amount
+                deposit,    1,  1,  10.0
+                withdrawal, 1,  2,  2.0
+                dispute,    1,  1,  3.0
+                dispute,    1,  1,  8.0
+                dispute,    1,  1,  0.0
+                resolve,    1,  1,  4.0
+                resolve,    1,  1,  1.0
+                deposit,    2,  3,  5.0
+                withdrawal, 2,  4,  2.0
+                dispute,    2,  4,  0.5
+                dispute,    2,  4,
+                dispute,    2,  4,  0.1
+                resolve,    2,  4,  2.0
+                chargeback, 1,  1,  0.5",
+            expected_transactions: vec![
+                (1, RecTx{ disputed: amt("1.5"), charged_back: amt("0.5"), disputes: 1, ..rec(1, TxType::Deposit, "10.0") }),
+                (2, rec(1, TxType::Withdrawal, "-2.0")),
+                (3, rec(2, TxType::Deposit, "5.0")),
+                (4, RecTx{ disputes: 2, ..rec(2, TxType::Withdrawal, "-2.0") }),
+            ],
+            expected_accounts: vec![
+                (1, Acct{ available: amt("6.0"), held: amt("1.5"), total: amt("7.5"), lock: Some(LockReason::Chargeback) }),
+                (2, Acct{ available: amt("3.0"), held: amt("0.0"), total: amt("3.0"), lock: None }),
+            ],
+            errors: vec![],
+        };
+        test.run();
+        assert_eq!(test.errors, vec![
+            ProcessError::DisputeAmountExceeded { client_id: 1, tx_id: 1, requested: TxType::Dispute, amount: amt("8.0"), max: amt("7.0") },
+            ProcessError::NonPositiveAmount { client_id: 1, tx_id: 1 },
+            ProcessError::DisputeAmountExceeded { client_id: 1, tx_id: 1, requested: TxType::Resolve, amount: amt("4.0"), max: amt("3.0") },
+            ProcessError::InvalidStateTransition { client_id: 2, tx_id: 4, from: TxState::Disputed, requested: TxType::Dispute },
+        ]);
+    }
+
+    #[test]
+    fn fees_and_interest() {
+        let mut test = TestDef{
+            input_data: "type, client, tx, amount
+                deposit,    1,  1,  1.0
+                fee,        1,  2,  0.25
+                interest,   1,  3,  0.1
+                fee,        1,  4,  2.0
+                fee,        9,  5,  0.1
+                dispute,    1,  2,  ",
+            expected_transactions: vec![
+                (1, rec(1, TxType::Deposit, "1.0")),
+                (2, rec(1, TxType::Fee, "-0.25")),
+                (3, rec(1, TxType::Interest, "0.1")),
+            ],
+            expected_accounts: vec![
+                (1, Acct{ available: amt("0.85"), held: amt("0.0"), total: amt("0.85"), lock: None }),
+                (9, Acct{ available: amt("0.15"), held: amt("0.0"), total: amt("0.15"), lock: None }),
+            ],
+            errors: vec![],
+        };
+        let mut engine = Engine::new();
+        engine.set_house_account(9);
+        test.run_with(engine);
+        assert_eq!(vec![
+            ProcessError::InsufficientFunds { client_id: 1, tx_id: 4 },
+            ProcessError::InvalidDestination { client_id: 9, tx_id: 5 },
+            ProcessError::NotDisputable { client_id: 1, tx_id: 2 },
+        ], test.errors);
+    }
+
+    #[test]
+    fn rejected_fees_leave_house_alone() {
+        let mut test = TestDef{
+            input_data: "type, client, tx, amount
+                deposit,    1,  1,  1.0
+                fee,        1,  2,  2.0
+                interest,   2,  3,  0.0001",
+            expected_transactions: vec![
+                (1, rec(1, TxType::Deposit, "1.0")),
+            ],
+            // the house account only appears once something is posted to it
+            expected_accounts: vec![
+                (1, Acct{ available: amt("1.0"), held: amt("0.0"), total: amt("1.0"), lock: None }),
+                (2, Acct{ available: amt("922337203685477.5807"), held: amt("0.0"), total: amt("922337203685477.5807"), lock: None }),
+            ],
+            errors: vec![],
+        };
+        let full = Acct{ available: amt("922337203685477.5807"), total: amt("922337203685477.5807"), ..Default::default() };
+        let mut engine = Engine::with_stores(TxMap::new(), AcctMap::from([(2, full)]));
+        engine.set_house_account(9);
+        engine.ledger_mut().set(LedgerAcct::External, Amount::ZERO);
+        engine.ledger_mut().set(LedgerAcct::Settlement, Amount::from_raw(-i64::MAX));
+        test.run_with(engine);
+        assert_eq!(vec![
+            ProcessError::InsufficientFunds { client_id: 1, tx_id: 2 },
+            ProcessError::Overflow { client_id: 2, tx_id: 3 },
+        ], test.errors);
+    }
+
+    #[test]
+    fn interest_overdraws_house() {
+        let mut test = TestDef{
+            input_data: "type, client, tx, amount
+                deposit,    1,  1,  10.0
+                interest,   1,  2,  0.5",
+            expected_transactions: vec![
+                (1, rec(1, TxType::Deposit, "10.0")),
+                (2, rec(1, TxType::Interest, "0.5")),
+            ],
+            expected_accounts: vec![
+                (0, Acct{ available: amt("-0.5"), held: amt("0.0"), total: amt("-0.5"), lock: None }),
+                (1, Acct{ available: amt("10.5"), held: amt("0.0"), total: amt("10.5"), lock: None }),
+            ],
+            errors: vec![],
+        };
+        let mut engine = Engine::new();
+        engine.set_house_account(0);
+        test.run_with(engine);
+        assert!(test.errors.is_empty());
+    }
+
+    #[test]
+    fn fee_overdraft() {
+        let mut test = TestDef{
+            input_data: "type, client, tx, amount
+                deposit,    1,  1,  1.0
+                fee,        1,  2,  1.5
+                fee,        2,  3,  0.5",
+            expected_transactions: vec![
+                (1, rec(1, TxType::Deposit, "1.0")),
+                (2, rec(1, TxType::Fee, "-1.5")),
+                (3, rec(2, TxType::Fee, "-0.5")),
+            ],
+            expected_accounts: vec![
+                (0, Acct{ available: amt("2.0"), held: amt("0.0"), total: amt("2.0"), lock: None }),
+                (1, Acct{ available: amt("-0.5"), held: amt("0.0"), total: amt("-0.5"), lock: None }),
+                (2, Acct{ available: amt("-0.5"), held: amt("0.0"), total: amt("-0.5"), lock: None }),
+            ],
+            errors: vec![],
+        };
+        let mut engine = Engine::new();
+        engine.set_house_account(0);
+        engine.set_fee_overdraft(true);
+        test.run_with(engine);
+        assert!(test.errors.is_empty());
+
+        // without a house account there's nowhere to post them
+        let mut engine = Engine::new();
+        let fee = Tx{ tx_type: TxType::Fee, client_id: 1, tx_id: 1, amount: Some(amt("1.0")), dest: None, reason: None, timestamp: None };
+        assert_eq!(Err(ProcessError::NoHouseAccount { client_id: 1, tx_id: 1 }), engine.process_tx(fee));
+    }
+
+    #[test]
+    fn credit_limits() {
+        let mut test = TestDef{
+            input_data: "type, client, tx, amount, dest
+                deposit,    1,  1,  1.0,
+                withdrawal, 1,  2,  1.5,
+                withdrawal, 1,  3,  0.6,
+                transfer,   1,  4,  0.5,    2
+                withdrawal, 2,  5,  0.6,
+                deposit,    3,  6,  1.0,
+                deposit,    3,  7,  3.0,
+                withdrawal, 3,  8,  3.5,
+                dispute,    3,  7,  ,
+                dispute,    3,  6,  ,",
+            expected_transactions: vec![
+                (1, rec(1, TxType::Deposit, "1.0")),
+                (2, rec(1, TxType::Withdrawal, "-1.5")),
+                (4, RecTx{ dest: Some(2), ..rec(1, TxType::Transfer, "-0.5") }),
+                (6, RecTx{ disputed: amt("1.0"), disputes: 1, ..rec(3, TxType::Deposit, "1.0") }),
+                (7, rec(3, TxType::Deposit, "3.0")),
+                (8, rec(3, TxType::Withdrawal, "-3.5")),
+            ],
+            expected_accounts: vec![
+                (1, Acct{ available: amt("-1.0"), held: amt("0.0"), total: amt("-1.0"), lock: None }),
+                (2, Acct{ available: amt("0.5"), held: amt("0.0"), total: amt("0.5"), lock: None }),
+                (3, Acct{ available: amt("-0.5"), held: amt("1.0"), total: amt("0.5"), lock: None }),
+            ],
+            errors: vec![],
+        };
+        // disputes are held to the same limit when they can't take the funds below it
+        let mut engine = with_policy(DisputePolicy{ allow_negative_available: false, ..Default::default() });
+        engine.set_credit_limit(1, amt("1.0"));
+        engine.set_credit_limit(3, amt("2.0"));
+        test.run_with(engine);
+        assert_eq!(vec![
+            ProcessError::InsufficientFunds { client_id: 1, tx_id: 3 },
+            ProcessError::InsufficientFunds { client_id: 2, tx_id: 5 },
+            ProcessError::InsufficientFunds { client_id: 3, tx_id: 7 },
+        ], test.errors);
+    }
+
+    #[test]
+    fn limits() {
+        let mut test = TestDef{
+            input_data: "type, client, tx, amount, dest, timestamp
+                deposit,    1,  1,  100.0,  ,   0
+                withdrawal, 1,  2,  60.0,   ,   10
+                withdrawal, 1,  3,  40.0,   ,   20
+                transfer,   1,  4,  20.0,   2,  30
+                deposit,    1,  5,  1.0,    ,   40
+                deposit,    1,  6,  1.0,    ,   50",
+            expected_transactions: vec![
+                (1, RecTx{ timestamp: Some(0), ..rec(1, TxType::Deposit, "100.0") }),
+                (3, RecTx{ timestamp: Some(20), ..rec(1, TxType::Withdrawal, "-40.0") }),
+                (5, RecTx{ timestamp: Some(40), ..rec(1, TxType::Deposit, "1.0") }),
+            ],
+            expected_accounts: vec![
+                (1, Acct{ available: amt("61.0"), held: amt("0.0"), total: amt("61.0"), lock: None }),
+            ],
+            errors: vec![],
+        };
+        let mut engine = Engine::new();
+        engine.set_limits(Limits{
+            max_withdrawal: Some(amt("50.0")),
+            daily_withdrawal: Some(amt("50.0")),
+            velocity: Some(Velocity{ count: 3, window: 100 }),
+            ..Default::default()
+        });
+        test.run_with(engine);
+        assert_eq!(vec![
+            ProcessError::LimitExceeded { client_id: 1, tx_id: 2, rule: LimitRule::MaxWithdrawal },
+            ProcessError::LimitExceeded { client_id: 1, tx_id: 4, rule: LimitRule::DailyWithdrawal },
+            ProcessError::LimitExceeded { client_id: 1, tx_id: 6, rule: LimitRule::Velocity },
+        ], test.errors);
+        assert_eq!("limit_exceeded", test.errors[0].code());
+        assert_eq!("transaction 4 exceeds the daily_withdrawal limit of client 1", test.errors[1].to_string());
+    }
+
+    fn with_policy(policy: DisputePolicy) -> Engine {
+        let mut engine = Engine::new();
+        engine.set_dispute_policy(policy);
+        engine
+    }
+
+    #[test]
+    fn policy_no_redispute() {
+        let mut test = TestDef{
+            input_data: "type, client, tx, amount
+                deposit,    1,  1,  1.0
+                dispute,    1,  1,
+                resolve,    1,  1,
+                dispute,    1,  1,  ",
+            expected_transactions: vec![
+                (1, RecTx{ disputes: 1, ..rec(1, TxType::Deposit, "1.0") }),
+            ],
+            expected_accounts: vec![
+                (1, Acct{ available: amt("1.0"), held: amt("0.0"), total: amt("1.0"), lock: None }),
+            ],
+            errors: vec![],
+        };
+        test.run_with(with_policy(DisputePolicy{ allow_redispute: false, ..Default::default() }));
+        assert_eq!(vec![ProcessError::DisputeLimitReached { client_id: 1, tx_id: 1 }], test.errors);
+    }
+
+    #[test]
+    fn policy_max_disputes() {
+        let mut test = TestDef{
+            input_data: "type, client, tx, amount
+                deposit,    1,  1,  1.0
+                dispute,    1,  1,
+                resolve,    1,  1,
+                dispute,    1,  1,
+                resolve,    1,  1,
+                dispute,    1,  1,  ",
+            expected_transactions: vec![
+                (1, RecTx{ disputes: 2, ..rec(1, TxType::Deposit, "1.0") }),
+            ],
+            expected_accounts: vec![
+                (1, Acct{ available: amt("1.0"), held: amt("0.0"), total: amt("1.0"), lock: None }),
+            ],
+            errors: vec![],
+        };
+        test.run_with(with_policy(DisputePolicy{ max_disputes: Some(2), ..Default::default() }));
+        assert_eq!(vec![ProcessError::DisputeLimitReached { client_id: 1, tx_id: 1 }], test.errors);
+    }
+
+    #[test]
+    fn policy_withdrawals_not_disputable() {
+        let mut test = TestDef{
+            input_data: "type, client, tx, amount
+                deposit,    1,  1,  1.0
+                withdrawal, 1,  2,  0.5
+                dispute,    1,  2,
+                dispute,    1,  1,  ",
+            expected_transactions: vec![
+                (1, RecTx{ disputed: amt("1.0"), disputes: 1, ..rec(1, TxType::Deposit, "1.0") }),
+                (2, rec(1, TxType::Withdrawal, "-0.5")),
+            ],
+            expected_accounts: vec![
+                (1, Acct{ available: amt("-0.5"), held: amt("1.0"), total: amt("0.5"), lock: None }),
+            ],
+            errors: vec![],
+        };
+        test.run_with(with_policy(DisputePolicy{ withdrawals_disputable: false, ..Default::default() }));
+        assert_eq!(vec![ProcessError::NotDisputable { client_id: 1, tx_id: 2 }], test.errors);
+    }
+
+    #[test]
+    fn policy_no_negative_available() {
+        let mut test = TestDef{
+            input_data: "type, client, tx, amount
+                deposit,    1,  1,  1.0
+                deposit,    1,  2,  0.25
+                withdrawal, 1,  3,  0.5
+                dispute,    1,  1,
+                dispute,    1,  2,
+                dispute,    1,  3,  ",
+            expected_transactions: vec![
+                (1, rec(1, TxType::Deposit, "1.0")),
+                (2, RecTx{ disputed: amt("0.25"), disputes: 1, ..rec(1, TxType::Deposit, "0.25") }),
+                (3, RecTx{ disputed: amt("-0.5"), disputes: 1, ..rec(1, TxType::Withdrawal, "-0.5") }),
+            ],
+            expected_accounts: vec![
+                (1, Acct{ available: amt("1.0"), held: amt("-0.25"), total: amt("0.75"), lock: None }),
+            ],
+            errors: vec![],
+        };
+        test.run_with(with_policy(DisputePolicy{ allow_negative_available: false, ..Default::default() }));
+        assert_eq!(vec![ProcessError::InsufficientFunds { client_id: 1, tx_id: 1 }], test.errors);
+    }
+
+    #[test]
+    fn policy_dispute_window_and_deadline() {
+        let mut test = TestDef{
+            input_data: "type, client, tx, amount, timestamp
+                deposit,    1,  1,  5.0,    10
+                deposit,    1,  2,  3.0,    20
+                deposit,    1,  3,  1.0,    15
+                dispute,    1,  1,  ,       200
+                dispute,    1,  2,  ,       60
+                deposit,    1,  3,  1.0,    110
+                deposit,    1,  4,  1.0,
+                deposit,    1,  5,  1.0,    111
+                resolve,    1,  2,  ,       112",
+            expected_transactions: vec![
+                (1, RecTx{ timestamp: Some(10), ..rec(1, TxType::Deposit, "5.0") }),
+                (2, RecTx{ disputes: 1, timestamp: Some(20), ..rec(1, TxType::Deposit, "3.0") }),
+                (3, RecTx{ timestamp: Some(110), ..rec(1, TxType::Deposit, "1.0") }),
+                (4, rec(1, TxType::Deposit, "1.0")),
+                (5, RecTx{ timestamp: Some(111), ..rec(1, TxType::Deposit, "1.0") }),
+            ],
+            expected_accounts: vec![
+                // the dispute was resolved before the deposit made just past its deadline
+                (1, Acct{ available: amt("11.0"), held: amt("0.0"), total: amt("11.0"), lock: None }),
+            ],
+            errors: vec![],
+        };
+        test.run_with(with_policy(DisputePolicy{ dispute_window: Some(100), resolution_deadline: Some(50), ..Default::default() }));
+        assert_eq!(vec![
+            ProcessError::TimestampOutOfOrder { client_id: 1, tx_id: 3, timestamp: 15, latest: 20 },
+            ProcessError::DisputeWindowClosed { client_id: 1, tx_id: 1 },
+            ProcessError::InvalidStateTransition { client_id: 1, tx_id: 2, from: TxState::Undisputed, requested: TxType::Resolve },
+        ], test.errors);
+
+        // a dispute charged back at its deadline is settled even when the
+        // transaction that got there is rejected (here, by the chargeback's lock)
+        let mut test = TestDef{
+            input_data: "type, client, tx, amount, timestamp
+                deposit,    1,  1,  2.0,    0
+                dispute,    1,  1,  ,       0
+                deposit,    1,  2,  1.0,    51",
+            expected_transactions: vec![
+                (1, RecTx{ charged_back: amt("2.0"), disputes: 1, timestamp: Some(0), ..rec(1, TxType::Deposit, "2.0") }),
+            ],
+            expected_accounts: vec![
+                (1, Acct{ available: amt("0.0"), held: amt("0.0"), total: amt("0.0"), lock: Some(LockReason::Chargeback) }),
+            ],
+            errors: vec![],
+        };
+        test.run_with(with_policy(DisputePolicy{ resolution_deadline: Some(50), on_deadline: DeadlineAction::Chargeback, ..Default::default() }));
+        assert_eq!(vec![ProcessError::AccountLocked { client_id: 1, tx_id: 2 }], test.errors);
+    }
+
+    #[test]
+    fn deadline_settlements_are_journaled() {
+        let mut engine = with_policy(DisputePolicy{ resolution_deadline: Some(50), ..Default::default() });
+        engine.keep_journal();
+        let at = |tx_type, tx_id, amount: Option<&str>, timestamp| Tx{
+            tx_type,
+            client_id: 1,
+            tx_id,
+            amount: amount.map(amt),
+            dest: None,
+            reason: None,
+            timestamp: Some(timestamp),
+        };
+        engine.process_tx(at(TxType::Deposit, 1, Some("2.0"), 0)).unwrap();
+        engine.process_tx(at(TxType::Dispute, 1, None, 10)).unwrap();
+        engine.process_tx(at(TxType::Withdrawal, 2, Some("5.0"), 61)).unwrap_err();
+
+        let settled: Vec<_> = engine.journal().map(|e| (e.seq, e.tx_type, e.tx_id)).collect();
+        assert_eq!(vec![(0, TxType::Deposit, 1), (1, TxType::Dispute, 1), (2, TxType::Resolve, 1)], settled);
+        assert_eq!(Some(&61), engine.clocks().get(&1));
+    }
+
+    #[test]
+    fn freeze_unfreeze_close() {
+        let mut test = TestDef{
+            input_data: "type, client, tx, amount, dest, reason
+                deposit,    1,  1,  1.0,    ,   
+                freeze,     1,  2,     ,    ,   suspicious activity
+                deposit,    1,  3,  1.0,    ,   
+                freeze,     1,  4,     ,    ,   again
+                unfreeze,   1,  5,     ,    ,   
+                unfreeze,   1,  6,     ,    ,   cleared
+                deposit,    1,  7,  1.0,    ,   
+                close,      1,  8,     ,    ,   requested by client
+                unfreeze,   1,  9,     ,    ,   reopen
+                close,      1, 10,     ,    ,   again",
+            expected_transactions: vec![
+                (1, rec(1, TxType::Deposit, "1.0")),
+                (7, rec(1, TxType::Deposit, "1.0")),
+            ],
+            expected_accounts: vec![
+                (1, Acct{ available: amt("2.0"), held: amt("0.0"), total: amt("2.0"), lock: Some(LockReason::Closed) }),
+            ],
+            errors: vec![],
+        };
+        test.run();
+        assert_eq!(vec![
+            ProcessError::AccountLocked { client_id: 1, tx_id: 3 },
+            ProcessError::InvalidLockChange { client_id: 1, tx_id: 4, lock: Some(LockReason::Frozen), requested: TxType::Freeze },
+            ProcessError::MissingReason { client_id: 1, tx_id: 5 },
+            ProcessError::InvalidLockChange { client_id: 1, tx_id: 9, lock: Some(LockReason::Closed), requested: TxType::Unfreeze },
+            ProcessError::InvalidLockChange { client_id: 1, tx_id: 10, lock: Some(LockReason::Closed), requested: TxType::Close },
+        ], test.errors);
+
+        // every accepted change is audited
+        let mut engine = Engine::new();
+        for tx in [
+            Tx{ tx_type: TxType::Freeze, client_id: 1, tx_id: 1, amount: None, dest: None, reason: Some("a".into()), timestamp: None },
+            Tx{ tx_type: TxType::Close, client_id: 1, tx_id: 2, amount: None, dest: None, reason: Some("b".into()), timestamp: None },
+        ] {
+            engine.process_tx(tx).unwrap();
+        }
+        assert_eq!(vec![
+            AuditEntry{ client_id: 1, tx_id: 1, action: TxType::Freeze, reason: "a".into(), from: None, to: Some(LockReason::Frozen) },
+            AuditEntry{ client_id: 1, tx_id: 2, action: TxType::Close, reason: "b".into(), from: Some(LockReason::Frozen), to: Some(LockReason::Closed) },
+        ], engine.audit_log());
+    }
+
+    #[test]
+    fn lock_policy() {
+        let mut test = TestDef{
+            input_data: "type, client, tx, amount, dest, reason
+                deposit,    1,  1,  1.0,    ,
+                deposit,    1,  2,  1.0,    ,
+                dispute,    1,  1,     ,    ,
+                dispute,    1,  2,     ,    ,
+                chargeback, 1,  1,     ,    ,
+                resolve,    1,  2,     ,    ,
+                deposit,    1,  3,  1.0,    ,
+                unfreeze,   1,  4,     ,    ,   reviewed
+                deposit,    1,  5,  1.0,    ,   ",
+            expected_transactions: vec![
+                (1, RecTx{ charged_back: amt("1.0"), disputes: 1, ..rec(1, TxType::Deposit, "1.0") }),
+                (2, RecTx{ disputes: 1, ..rec(1, TxType::Deposit, "1.0") }),
+                (5, rec(1, TxType::Deposit, "1.0")),
+            ],
+            expected_accounts: vec![
+                (1, Acct{ available: amt("2.0"), held: amt("0.0"), total: amt("2.0"), lock: None }),
+            ],
+            errors: vec![],
+        };
+        let mut engine = Engine::new();
+        engine.set_lock_policy(LockPolicy::default().allow(LockReason::Chargeback, TxType::Resolve));
+        test.run_with(engine);
+        assert_eq!(vec![ProcessError::AccountLocked { client_id: 1, tx_id: 3 }], test.errors);
+    }
+
+    #[test]
+    fn journal() {
+        let data = "type, client, tx, amount, dest
+            deposit,    1,  1,  2.0,
+            transfer,   1,  2,  0.5,    2
+            withdrawal, 2,  3,  1.0,
+            dispute,    1,  2,     ,    ";
+        let mut engine = Engine::new();
+        engine.keep_journal();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        engine.subscribe(move |e: &Event| sender.send(e.clone()).unwrap());
+
+        let mut reader = ReaderBuilder::new().trim(Trim::All).from_reader(data.as_bytes());
+        for res in reader.deserialize() {
+            _ = engine.process_tx(res.unwrap());
+        }
+
+        let event = |seq, tx_id, client_id, tx_type, deltas: [&str; 3], balances: [&str; 3]| Event{
+            seq, tx_id, client_id, tx_type, timestamp: None,
+            available_delta: amt(deltas[0]), held_delta: amt(deltas[1]), total_delta: amt(deltas[2]),
+            available: amt(balances[0]), held: amt(balances[1]), total: amt(balances[2]),
+            locked: false,
+        };
+        let expected = vec![
+            event(0, 1, 1, TxType::Deposit, ["2.0", "0", "2.0"], ["2.0", "0", "2.0"]),
+            event(1, 2, 1, TxType::Transfer, ["-0.5", "0", "-0.5"], ["1.5", "0", "1.5"]),
+            event(1, 2, 2, TxType::Transfer, ["0.5", "0", "0.5"], ["0.5", "0", "0.5"]),
+            event(3, 2, 1, TxType::Dispute, ["0.5", "-0.5", "0"], ["2.0", "-0.5", "1.5"]),
+            event(3, 2, 2, TxType::Dispute, ["-0.5", "0.5", "0"], ["0", "0.5", "0.5"]),
+        ];
+        assert_eq!(expected, engine.journal().cloned().collect::<Vec<_>>());
+        assert_eq!(expected, receiver.try_iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn history() {
+        let data = "type, client, tx, amount, dest, timestamp
+            deposit,    1,  1,  2.0,    ,   100
+            transfer,   1,  2,  0.5,    2,  200
+            withdrawal, 2,  3,  1.0,    ,   200
+            deposit,    1,  4,  1.0,    ,
+            deposit,    1,  5,  1.0,    ,   300";
+        let mut engine = Engine::new();
+        engine.process_tx(Tx{ tx_type: TxType::Deposit, client_id: 3, tx_id: 9, amount: Some(amt("4.0")), dest: None, reason: None, timestamp: None }).unwrap();
+        engine.keep_history();
+
+        let mut reader = ReaderBuilder::new().trim(Trim::All).from_reader(data.as_bytes());
+        for res in reader.deserialize() {
+            _ = engine.process_tx(res.unwrap());
+        }
+
+        let balance = |client_id, as_of| engine.balance_at(client_id, as_of).map(|a| a.total);
+        // the deposit to client 3 was the first transaction (with sequence number 0)
+        assert_eq!(None, balance(1, AsOf::Seq(0)));
+        assert_eq!(Some(amt("2.0")), balance(1, AsOf::Seq(1)));
+        assert_eq!(Some(amt("1.5")), balance(1, AsOf::Seq(3)));
+        assert_eq!(Some(amt("3.5")), balance(1, AsOf::Seq(5)));
+        assert_eq!(Some(amt("2.0")), balance(1, AsOf::Time(199)));
+        assert_eq!(Some(amt("2.5")), balance(1, AsOf::Time(299)));
+        // the rejected withdrawal left client 2 as the transfer did
+        assert_eq!(None, balance(2, AsOf::Seq(1)));
+        assert_eq!(Some(amt("0.5")), balance(2, AsOf::Time(200)));
+        // accounts from before the history started keep their opening balances
+        assert_eq!(Some(amt("4.0")), balance(3, AsOf::Seq(0)));
+
+        let totals: Vec<_> = engine.balances_at(AsOf::Seq(2)).map(|(client_id, a)| (client_id, a.total)).collect();
+        assert_eq!(vec![(1, amt("1.5")), (2, amt("0.5")), (3, amt("4.0"))], totals);
+    }
+
+    #[test]
+    fn ledger() {
+        let mut test = TestDef{
+            input_data: "type, client, tx, amount, dest
+                deposit,    1,  1,  3.0,
+                withdrawal, 1,  2,  1.0,
+                transfer,   1,  3,  0.5,    2
+                dispute,    1,  1,     ,
+                chargeback, 1,  1,     ,
+                dispute,    1,  3,     ,
+                resolve,    1,  3,     ,    ",
+            expected_transactions: vec![
+                (1, RecTx{ charged_back: amt("3.0"), disputes: 1, ..rec(1, TxType::Deposit, "3.0") }),
+                (2, rec(1, TxType::Withdrawal, "-1.0")),
+                (3, RecTx{ disputes: 1, dest: Some(2), ..rec(1, TxType::Transfer, "-0.5") }),
+            ],
+            expected_accounts: vec![
+                (1, Acct{ available: amt("-1.5"), held: amt("0.0"), total: amt("-1.5"), lock: Some(LockReason::Chargeback) }),
+                (2, Acct{ available: amt("0.5"), held: amt("0.0"), total: amt("0.5"), lock: None }),
+            ],
+            errors: vec![],
+        };
+        let mut engine = Engine::new();
+        engine.set_lock_policy(LockPolicy::default().allow(LockReason::Chargeback, TxType::Dispute).allow(LockReason::Chargeback, TxType::Resolve));
+        test.run_with(engine);
+        assert!(test.errors.is_empty());
+
+        let mut engine = Engine::new();
+        for (tx_type, tx_id, amount) in [(TxType::Deposit, 1, "3.0"), (TxType::Withdrawal, 2, "1.0")] {
+            engine.process_tx(Tx{ tx_type, client_id: 1, tx_id, amount: Some(amt(amount)), dest: None, reason: None, timestamp: None }).unwrap();
+        }
+        engine.process_tx(Tx{ tx_type: TxType::Dispute, client_id: 1, tx_id: 1, amount: None, dest: None, reason: None, timestamp: None }).unwrap();
+        engine.process_tx(Tx{ tx_type: TxType::Chargeback, client_id: 1, tx_id: 1, amount: None, dest: None, reason: None, timestamp: None }).unwrap();
+        assert_eq!(amt("-2.0"), engine.ledger().balance(LedgerAcct::External));
+        assert_eq!(amt("3.0"), engine.ledger().balance(LedgerAcct::Settlement));
+        assert_eq!(Ok(()), engine.trial_balance());
+
+        assert_eq!(amt("-1.0"), engine.ledger().balance(LedgerAcct::Available(1)));
+
+        // an account changed outside of the ledger throws the books off
+        engine.acct_map.get_mut(&1).unwrap().available = amt("1.0");
+        assert_eq!(Err(Imbalance::Total { client_id: 1 }), engine.trial_balance());
+        engine.acct_map.get_mut(&1).unwrap().total = amt("1.0");
+        assert_eq!(Err(Imbalance::Mismatch { client_id: 1 }), engine.trial_balance());
+        engine.ledger_mut().set(LedgerAcct::Available(1), amt("1.0"));
+        assert_eq!(Err(Imbalance::Unbalanced { off_by: 20_000 }), engine.trial_balance());
+
+        // and so does a client kept in the ledger without an account
+        let mut engine = Engine::new();
+        engine.ledger_mut().set(LedgerAcct::Held(4), amt("1.0"));
+        engine.ledger_mut().set(LedgerAcct::External, amt("-1.0"));
+        assert_eq!(Err(Imbalance::Mismatch { client_id: 4 }), engine.trial_balance());
+
+        // accounts already in the store are opened in the ledger
+        let full = Acct{ available: amt("2.0"), held: amt("1.0"), total: amt("3.0"), lock: None };
+        let engine = Engine::with_stores(TxMap::new(), AcctMap::from([(1, full)]));
+        assert_eq!(amt("-3.0"), engine.ledger().balance(LedgerAcct::External));
+        assert_eq!(Ok(()), engine.trial_balance());
     }
 }
@@ -0,0 +1,222 @@
+//! Contains the [`ProcessError`] enum describing why a transaction was rejected.
+
+use std::error::Error;
+use std::fmt;
+
+use crate::account::{AcctError, LockReason};
+use crate::amount::Amount;
+use crate::engine::TxState;
+use crate::invariants::Violation;
+use crate::limits::LimitRule;
+use crate::transaction::TxType;
+
+/// The reasons the engine can reject a transaction.
+///
+/// Every variant carries the client and transaction IDs of the rejected row, and
+/// [`ProcessError::code`] gives a stable identifier for tooling to match on.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProcessError {
+    /// A recorded transaction reused the ID of an already recorded transaction.
+    DuplicateTxId { client_id: u16, tx_id: u32 },
+    /// A recorded transaction had no amount.
+    MissingAmount { client_id: u16, tx_id: u32 },
+    /// A withdrawal, transfer, or fee asked for more than the available funds.
+    InsufficientFunds { client_id: u16, tx_id: u32 },
+    /// A recorded transaction was for zero or a negative amount.
+    NonPositiveAmount { client_id: u16, tx_id: u32 },
+    /// The client's account is locked.
+    AccountLocked { client_id: u16, tx_id: u32 },
+    /// A transfer had no destination, or the other account of a transfer, fee, or
+    /// interest was the client itself.
+    InvalidDestination { client_id: u16, tx_id: u32 },
+    /// The other account of a transfer, fee, or interest is locked.
+    DestinationLocked { client_id: u16, tx_id: u32, dest: u16 },
+    /// The other account of a transfer (or a disputed transfer), fee, or interest
+    /// refused the change (e.g., it would overflow its balance).
+    DestinationRefused { client_id: u16, tx_id: u32, dest: u16, reason: AcctError },
+    /// A fee or interest was processed without a house account to post it against.
+    NoHouseAccount { client_id: u16, tx_id: u32 },
+    /// A dispute referenced a transaction that can't be disputed (a fee or interest,
+    /// or a withdrawal when the dispute policy doesn't allow it).
+    NotDisputable { client_id: u16, tx_id: u32 },
+    /// A dispute referenced a transaction that has been disputed as many times as
+    /// the dispute policy allows.
+    DisputeLimitReached { client_id: u16, tx_id: u32 },
+    /// A dispute-related transaction referenced a transaction that was never recorded.
+    UnknownTx { client_id: u16, tx_id: u32 },
+    /// A dispute-related transaction referenced another client's transaction.
+    ClientMismatch { client_id: u16, tx_id: u32 },
+    /// A dispute-related transaction is not allowed in the referenced transaction's state.
+    InvalidStateTransition { client_id: u16, tx_id: u32, from: TxState, requested: TxType },
+    /// A dispute asked for more than the undisputed portion of the referenced
+    /// transaction, or a resolve or chargeback for more than its disputed portion.
+    DisputeAmountExceeded { client_id: u16, tx_id: u32, requested: TxType, amount: Amount, max: Amount },
+    /// A transaction's timestamp was earlier than the client's latest accepted one.
+    TimestampOutOfOrder { client_id: u16, tx_id: u32, timestamp: u64, latest: u64 },
+    /// A dispute came later after the referenced transaction than the dispute
+    /// policy's window allows.
+    DisputeWindowClosed { client_id: u16, tx_id: u32 },
+    /// A deposit, withdrawal, or transfer broke one of the risk limits (see
+    /// [`crate::limits`]).
+    LimitExceeded { client_id: u16, tx_id: u32, rule: LimitRule },
+    /// A balance would be too large to represent.
+    Overflow { client_id: u16, tx_id: u32 },
+    /// An administrative transaction had no reason.
+    MissingReason { client_id: u16, tx_id: u32 },
+    /// An administrative transaction doesn't apply to the account's lock (e.g.,
+    /// unfreezing an account that isn't locked).
+    InvalidLockChange { client_id: u16, tx_id: u32, lock: Option<LockReason>, requested: TxType },
+    /// An accepted transaction couldn't be written to the write-ahead log.
+    WalWrite { client_id: u16, tx_id: u32, message: String },
+    /// The events of an accepted transaction couldn't be handed to a journal subscriber.
+    JournalWrite { client_id: u16, tx_id: u32, message: String },
+    /// An accepted transaction broke an invariant of a strict engine (see
+    /// [`crate::invariants`]).
+    InvariantViolated { client_id: u16, tx_id: u32, violation: Box<Violation> },
+}
+
+impl ProcessError {
+    /// Converts an account error into the matching process error for a transaction.
+    pub fn from_acct(err: AcctError, client_id: u16, tx_id: u32) -> Self {
+        match err {
+            AcctError::NonPositiveAmount => ProcessError::NonPositiveAmount { client_id, tx_id },
+            AcctError::InsufficientFunds => ProcessError::InsufficientFunds { client_id, tx_id },
+            AcctError::Overflow => ProcessError::Overflow { client_id, tx_id },
+        }
+    }
+
+    /// Returns the stable, machine-readable code for this error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ProcessError::DuplicateTxId { .. } => "duplicate_tx_id",
+            ProcessError::MissingAmount { .. } => "missing_amount",
+            ProcessError::InsufficientFunds { .. } => "insufficient_funds",
+            ProcessError::NonPositiveAmount { .. } => "non_positive_amount",
+            ProcessError::AccountLocked { .. } => "account_locked",
+            ProcessError::InvalidDestination { .. } => "invalid_destination",
+            ProcessError::DestinationLocked { .. } => "destination_locked",
+            ProcessError::DestinationRefused { .. } => "destination_refused",
+            ProcessError::NoHouseAccount { .. } => "no_house_account",
+            ProcessError::NotDisputable { .. } => "not_disputable",
+            ProcessError::DisputeLimitReached { .. } => "dispute_limit_reached",
+            ProcessError::UnknownTx { .. } => "unknown_tx",
+            ProcessError::ClientMismatch { .. } => "client_mismatch",
+            ProcessError::InvalidStateTransition { .. } => "invalid_state_transition",
+            ProcessError::DisputeAmountExceeded { .. } => "dispute_amount_exceeded",
+            ProcessError::TimestampOutOfOrder { .. } => "timestamp_out_of_order",
+            ProcessError::DisputeWindowClosed { .. } => "dispute_window_closed",
+            ProcessError::LimitExceeded { .. } => "limit_exceeded",
+            ProcessError::Overflow { .. } => "overflow",
+            ProcessError::MissingReason { .. } => "missing_reason",
+            ProcessError::InvalidLockChange { .. } => "invalid_lock_change",
+            ProcessError::WalWrite { .. } => "wal_write_failed",
+            ProcessError::JournalWrite { .. } => "journal_write_failed",
+            ProcessError::InvariantViolated { .. } => "invariant_violated",
+        }
+    }
+
+    /// Returns whether processing should stop because of this error, rather than
+    /// skipping the transaction and moving on.
+    pub fn is_fatal(&self) -> bool {
+        matches!(self, ProcessError::WalWrite { .. } | ProcessError::JournalWrite { .. } | ProcessError::InvariantViolated { .. })
+    }
+
+    /// Returns the client ID of the rejected transaction.
+    pub fn client_id(&self) -> u16 {
+        match *self {
+            ProcessError::DuplicateTxId { client_id, .. }
+            | ProcessError::MissingAmount { client_id, .. }
+            | ProcessError::InsufficientFunds { client_id, .. }
+            | ProcessError::NonPositiveAmount { client_id, .. }
+            | ProcessError::AccountLocked { client_id, .. }
+            | ProcessError::InvalidDestination { client_id, .. }
+            | ProcessError::DestinationLocked { client_id, .. }
+            | ProcessError::DestinationRefused { client_id, .. }
+            | ProcessError::NoHouseAccount { client_id, .. }
+            | ProcessError::NotDisputable { client_id, .. }
+            | ProcessError::DisputeLimitReached { client_id, .. }
+            | ProcessError::UnknownTx { client_id, .. }
+            | ProcessError::ClientMismatch { client_id, .. }
+            | ProcessError::InvalidStateTransition { client_id, .. }
+            | ProcessError::DisputeAmountExceeded { client_id, .. }
+            | ProcessError::TimestampOutOfOrder { client_id, .. }
+            | ProcessError::DisputeWindowClosed { client_id, .. }
+            | ProcessError::LimitExceeded { client_id, .. }
+            | ProcessError::Overflow { client_id, .. }
+            | ProcessError::MissingReason { client_id, .. }
+            | ProcessError::InvalidLockChange { client_id, .. }
+            | ProcessError::WalWrite { client_id, .. }
+            | ProcessError::JournalWrite { client_id, .. }
+            | ProcessError::InvariantViolated { client_id, .. } => client_id,
+        }
+    }
+
+    /// Returns the transaction ID of the rejected transaction.
+    pub fn tx_id(&self) -> u32 {
+        match *self {
+            ProcessError::DuplicateTxId { tx_id, .. }
+            | ProcessError::MissingAmount { tx_id, .. }
+            | ProcessError::InsufficientFunds { tx_id, .. }
+            | ProcessError::NonPositiveAmount { tx_id, .. }
+            | ProcessError::AccountLocked { tx_id, .. }
+            | ProcessError::InvalidDestination { tx_id, .. }
+            | ProcessError::DestinationLocked { tx_id, .. }
+            | ProcessError::DestinationRefused { tx_id, .. }
+            | ProcessError::NoHouseAccount { tx_id, .. }
+            | ProcessError::NotDisputable { tx_id, .. }
+            | ProcessError::DisputeLimitReached { tx_id, .. }
+            | ProcessError::UnknownTx { tx_id, .. }
+            | ProcessError::ClientMismatch { tx_id, .. }
+            | ProcessError::InvalidStateTransition { tx_id, .. }
+            | ProcessError::DisputeAmountExceeded { tx_id, .. }
+            | ProcessError::TimestampOutOfOrder { tx_id, .. }
+            | ProcessError::DisputeWindowClosed { tx_id, .. }
+            | ProcessError::LimitExceeded { tx_id, .. }
+            | ProcessError::Overflow { tx_id, .. }
+            | ProcessError::MissingReason { tx_id, .. }
+            | ProcessError::InvalidLockChange { tx_id, .. }
+            | ProcessError::WalWrite { tx_id, .. }
+            | ProcessError::JournalWrite { tx_id, .. }
+            | ProcessError::InvariantViolated { tx_id, .. } => tx_id,
+        }
+    }
+}
+
+impl fmt::Display for ProcessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProcessError::DuplicateTxId { tx_id, .. } => write!(f, "transaction id {tx_id} already exists"),
+            ProcessError::MissingAmount { tx_id, .. } => write!(f, "transaction {tx_id} missing amount"),
+            ProcessError::InsufficientFunds { client_id, tx_id } => write!(f, "client {client_id} has insufficient funds for transaction {tx_id}"),
+            ProcessError::NonPositiveAmount { tx_id, .. } => write!(f, "transaction {tx_id} amount must be positive"),
+            ProcessError::AccountLocked { client_id, tx_id } => write!(f, "unable to process transaction {tx_id} - account {client_id} locked"),
+            ProcessError::InvalidDestination { tx_id, .. } => write!(f, "transaction {tx_id} needs a destination other than the client"),
+            ProcessError::DestinationLocked { tx_id, dest, .. } => write!(f, "unable to process transaction {tx_id} - other account {dest} locked"),
+            ProcessError::DestinationRefused { tx_id, dest, reason, .. } => write!(f, "unable to process transaction {tx_id} - other account {dest} refused it ({reason})"),
+            ProcessError::NoHouseAccount { tx_id, .. } => write!(f, "no house account to post transaction {tx_id} against"),
+            ProcessError::NotDisputable { tx_id, .. } => write!(f, "transaction {tx_id} can't be disputed"),
+            ProcessError::DisputeLimitReached { tx_id, .. } => write!(f, "transaction {tx_id} can't be disputed again"),
+            ProcessError::UnknownTx { tx_id, .. } => write!(f, "no transaction {tx_id}"),
+            ProcessError::ClientMismatch { client_id, tx_id } => write!(f, "no transaction {tx_id} for client {client_id}"),
+            ProcessError::InvalidStateTransition { tx_id, from, requested, .. } => write!(f, "invalid tx {requested} for transaction {tx_id} in state {from}"),
+            ProcessError::DisputeAmountExceeded { tx_id, requested, amount, max, .. } => {
+                let portion = if *requested == TxType::Dispute { "undisputed" } else { "disputed" };
+                write!(f, "invalid tx {requested} of {amount} for transaction {tx_id} - only {max} is {portion}")
+            }
+            ProcessError::TimestampOutOfOrder { client_id, tx_id, timestamp, latest } => {
+                write!(f, "transaction {tx_id} at {timestamp} is earlier than client {client_id}'s latest transaction at {latest}")
+            }
+            ProcessError::DisputeWindowClosed { tx_id, .. } => write!(f, "transaction {tx_id} can no longer be disputed"),
+            ProcessError::LimitExceeded { client_id, tx_id, rule } => write!(f, "transaction {tx_id} exceeds the {rule} limit of client {client_id}"),
+            ProcessError::Overflow { client_id, tx_id } => write!(f, "transaction {tx_id} overflows the balance of client {client_id}"),
+            ProcessError::MissingReason { tx_id, .. } => write!(f, "transaction {tx_id} missing reason"),
+            ProcessError::InvalidLockChange { client_id, tx_id, lock: None, requested } => write!(f, "invalid tx {requested} for transaction {tx_id} - account {client_id} isn't locked"),
+            ProcessError::InvalidLockChange { client_id, tx_id, lock: Some(lock), requested } => write!(f, "invalid tx {requested} for transaction {tx_id} - account {client_id} is locked ({lock})"),
+            ProcessError::WalWrite { tx_id, message, .. } => write!(f, "unable to log transaction {tx_id}: {message}"),
+            ProcessError::JournalWrite { tx_id, message, .. } => write!(f, "unable to journal transaction {tx_id}: {message}"),
+            ProcessError::InvariantViolated { tx_id, violation, .. } => write!(f, "transaction {tx_id} broke an invariant - {violation}"),
+        }
+    }
+}
+
+impl Error for ProcessError {}
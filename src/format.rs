@@ -0,0 +1,75 @@
+//! Contains the [`Format`]s transactions can be read in and results written out in.
+
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+/// The format of an input or output.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Comma-separated values, starting with a header row.
+    #[default]
+    Csv,
+    /// One JSON object per line, keyed by the same names as the CSV columns.
+    JsonLines,
+}
+
+impl Format {
+    /// Picks the format of a file by its extension (`.jsonl` or `.ndjson` for JSON
+    /// Lines), defaulting to CSV.
+    pub fn from_path<P>(path: P) -> Self
+        where P: AsRef<Path>
+    {
+        match path.as_ref().extension().and_then(|e| e.to_str()) {
+            Some("jsonl" | "ndjson") => Format::JsonLines,
+            _ => Format::Csv,
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    /// Writes the format the way it's given on the command line.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Format::Csv => "csv",
+            Format::JsonLines => "jsonl",
+        })
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    /// Parses a format the way it's given on the command line.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Format::Csv),
+            "jsonl" => Ok(Format::JsonLines),
+            _ => Err(format!("unknown format {s}")),
+        }
+    }
+}
+
+//------------------------------------------------------------------------------
+//------------------------------------------------------------------------------
+// TESTS
+//------------------------------------------------------------------------------
+//------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn formats() {
+        assert_eq!(Format::Csv, Format::from_path("tx.csv"));
+        assert_eq!(Format::Csv, Format::from_path("-"));
+        assert_eq!(Format::JsonLines, Format::from_path("data/tx.jsonl"));
+        assert_eq!(Format::JsonLines, Format::from_path("tx.ndjson"));
+
+        for format in [Format::Csv, Format::JsonLines] {
+            assert_eq!(Ok(format), format.to_string().parse());
+        }
+        assert!("json".parse::<Format>().is_err());
+    }
+}
@@ -0,0 +1,154 @@
+//! Contains the [`History`] of every account's balances, which answers
+//! point-in-time balance queries (see [`Engine::balance_at`](crate::Engine::balance_at)).
+//!
+//! A checkpoint of an account is kept after every transaction that changed it,
+//! so a query is a binary search over the client's checkpoints rather than a
+//! replay of the transactions before it.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::account::Acct;
+
+/// A point in the history of the accounts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AsOf {
+    /// Right after the transaction with this sequence number (its position in
+    /// the input, as in the journal).
+    Seq(u64),
+    /// Right after the last transaction at or before this timestamp.
+    Time(u64),
+}
+
+impl fmt::Display for AsOf {
+    /// Writes the point the way it's given on the command line.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AsOf::Seq(seq) => write!(f, "seq:{seq}"),
+            AsOf::Time(time) => write!(f, "time:{time}"),
+        }
+    }
+}
+
+impl FromStr for AsOf {
+    type Err = String;
+
+    /// Parses a point the way it's given on the command line (`seq:<n>` or
+    /// `time:<timestamp>`).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let point = match s.split_once(':') {
+            Some(("seq", n)) => n.parse().ok().map(AsOf::Seq),
+            Some(("time", t)) => t.parse().ok().map(AsOf::Time),
+            _ => None,
+        };
+        point.ok_or_else(|| format!("invalid point in time {s}"))
+    }
+}
+
+/// The state of an account after a transaction changed it.
+#[derive(Debug, Clone, PartialEq)]
+struct Checkpoint {
+    /// The sequence number of the transaction (none for the opening balances)
+    seq: Option<u64>,
+    /// The latest timestamp of the changes to the account so far, if any
+    time: Option<u64>,
+    acct: Acct,
+}
+
+/// Every account's balances after every change, from when the history was
+/// started.
+#[derive(Debug, Default)]
+pub(crate) struct History {
+    checkpoints: BTreeMap<u16, Vec<Checkpoint>>,
+}
+
+impl History {
+    /// Starts a history from the accounts' current (opening) balances.
+    pub(crate) fn new<I>(accts: I) -> Self
+        where I: IntoIterator<Item = (u16, Acct)>
+    {
+        let checkpoints = accts.into_iter()
+            .map(|(client_id, acct)| (client_id, vec![Checkpoint { seq: None, time: None, acct }]))
+            .collect();
+        History { checkpoints }
+    }
+
+    /// Keeps a checkpoint of every account an accepted transaction changed.
+    ///
+    /// Since transfers, fees, and interest change another client's account at the
+    /// sending client's time, a checkpoint's time is the latest timestamp of the
+    /// account's changes so far, which keeps each account's checkpoints in order.
+    pub(crate) fn record(&mut self, seq: u64, timestamp: Option<u64>, after: &[(u16, Acct)]) {
+        for (client_id, acct) in after {
+            let checkpoints = self.checkpoints.entry(*client_id).or_default();
+            let time = checkpoints.last().and_then(|c| c.time).max(timestamp);
+            checkpoints.push(Checkpoint { seq: Some(seq), time, acct: acct.clone() });
+        }
+    }
+
+    /// Returns a client's account as it was at a point, if it existed by then.
+    pub(crate) fn at(&self, client_id: u16, as_of: AsOf) -> Option<&Acct> {
+        let checkpoints = self.checkpoints.get(&client_id)?;
+        let before = match as_of {
+            AsOf::Seq(seq) => checkpoints.partition_point(|c| c.seq.is_none_or(|s| s <= seq)),
+            AsOf::Time(time) => checkpoints.partition_point(|c| c.time.is_none_or(|t| t <= time)),
+        };
+        before.checked_sub(1).map(|i| &checkpoints[i].acct)
+    }
+
+    /// Iterates over every client with a checkpoint, in order.
+    pub(crate) fn clients(&self) -> impl Iterator<Item = u16> + '_ {
+        self.checkpoints.keys().copied()
+    }
+}
+
+//------------------------------------------------------------------------------
+//------------------------------------------------------------------------------
+// TESTS
+//------------------------------------------------------------------------------
+//------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::amount::Amount;
+
+    fn acct(available: i64) -> Acct {
+        Acct{ available: Amount::from_raw(available), total: Amount::from_raw(available), ..Default::default() }
+    }
+
+    #[test]
+    fn points_in_time() {
+        let opening = acct(10);
+        let mut history = History::new([(1, opening.clone())]);
+        history.record(0, None, &[(1, acct(20))]);
+        history.record(1, Some(100), &[(1, acct(30)), (2, acct(5))]);
+        history.record(3, None, &[(1, acct(40))]);
+        history.record(4, Some(50), &[(2, acct(6))]);
+
+        assert_eq!(Some(&opening), History::new([(1, opening.clone())]).at(1, AsOf::Seq(0)));
+        assert_eq!(Some(&acct(20)), history.at(1, AsOf::Seq(0)));
+        assert_eq!(Some(&acct(30)), history.at(1, AsOf::Seq(2)));
+        assert_eq!(Some(&acct(40)), history.at(1, AsOf::Seq(9)));
+        assert_eq!(Some(&acct(20)), history.at(1, AsOf::Time(99)));
+        // changes without a timestamp count as being at the latest time so far
+        assert_eq!(Some(&acct(40)), history.at(1, AsOf::Time(100)));
+
+        // the other side of a transfer only exists from the transfer on, and keeps
+        // the sender's time
+        assert_eq!(None, history.at(2, AsOf::Seq(0)));
+        assert_eq!(None, history.at(2, AsOf::Time(99)));
+        assert_eq!(Some(&acct(6)), history.at(2, AsOf::Time(100)));
+        assert_eq!(None, history.at(3, AsOf::Seq(9)));
+        assert_eq!(vec![1, 2], history.clients().collect::<Vec<_>>());
+
+        assert_eq!(Ok(AsOf::Seq(42)), "seq:42".parse());
+        assert_eq!(Ok(AsOf::Time(1_700_000_000)), "time:1700000000".parse());
+        for point in [AsOf::Seq(7), AsOf::Time(8)] {
+            assert_eq!(Ok(point), point.to_string().parse());
+        }
+        assert!("42".parse::<AsOf>().is_err());
+        assert!("seq:-1".parse::<AsOf>().is_err());
+    }
+}
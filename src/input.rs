@@ -1,9 +1,43 @@
-use std::env;
+//! Contains the helpers for reading transactions in.
+
 use std::error::Error;
-use std::ffi::OsString;
-use std::io::Read;
-use csv::{Reader, ReaderBuilder, Trim};
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use csv::{Position, Reader, ReaderBuilder, StringRecord, Trim};
+use serde::de;
+use serde::Deserialize;
+use serde_json::{Number, Value};
+
+use crate::amount::Amount;
+use crate::engine::Engine;
+use crate::format::Format;
+use crate::limits::Limits;
+use crate::output::{RecordWriter, Rejection};
+use crate::store::{AccountStore, TxStore};
+use crate::transaction::Tx;
+
+/// The code reported for rows that couldn't be parsed into a [`Tx`].
+pub const MALFORMED_ROW: &str = "malformed_row";
+
+/// The path that means "read from stdin" instead of a file.
+pub const STDIN: &str = "-";
+
+/// The keys of a JSON Lines row, which are the columns of a CSV row.
+const JSON_KEYS: [&str; 7] = ["type", "client", "tx", "amount", "dest", "reason", "timestamp"];
+
+/// Opens a path for reading, where [`STDIN`] means stdin.
+pub fn open(path: &OsStr) -> io::Result<Box<dyn Read>> {
+    if path == STDIN {
+        Ok(Box::new(io::stdin().lock()))
+    } else {
+        Ok(Box::new(BufReader::new(File::open(path)?)))
+    }
+}
 
+/// Builds a CSV reader that trims whitespace and allows rows to leave off the
+/// optional `amount` and `dest` columns.
 pub fn reader<R>(data: R) -> Reader<R>
     where R: Read
 {
@@ -13,13 +47,408 @@ pub fn reader<R>(data: R) -> Reader<R>
         .from_reader(data)
 }
 
-/// Returns the first positional argument sent to this process. If there are no
-/// positional arguments, then this returns an error.
+/// Reads an input one row at a time, keeping each row's raw values around so
+/// that rows which can't be parsed can still be reported.
+pub struct Rows<R> {
+    source: Source<R>,
+    headers: StringRecord,
+    record: StringRecord,
+    /// The raw text of the current row, for JSON Lines
+    line: String,
+    /// The current row parsed, for JSON Lines
+    json: Option<serde_json::Result<JsonRow>>,
+}
+
+enum Source<R> {
+    Csv(Reader<R>),
+    /// The input and the number of lines read from it so far
+    JsonLines(BufReader<R>, u64),
+}
+
+impl<R> Rows<R>
+    where R: Read
+{
+    /// Reads the rows of a CSV reader (see [`reader`]).
+    pub fn new(mut reader: Reader<R>) -> csv::Result<Self> {
+        let headers = reader.headers()?.clone();
+        Ok(Rows { source: Source::Csv(reader), headers, record: StringRecord::new(), line: String::new(), json: None })
+    }
+
+    /// Reads the rows of an input in the given format.
+    pub fn with_format(format: Format, data: R) -> csv::Result<Self> {
+        match format {
+            Format::Csv => Self::new(reader(data)),
+            Format::JsonLines => Ok(Rows {
+                source: Source::JsonLines(BufReader::new(data), 0),
+                headers: StringRecord::from(&JSON_KEYS[..]),
+                record: StringRecord::new(),
+                line: String::new(),
+                json: None,
+            }),
+        }
+    }
+
+    /// Reads the next row, returning `None` at the end of the input.
+    ///
+    /// Blank lines of JSON Lines are skipped, as they are in CSV.
+    pub fn next_row(&mut self) -> csv::Result<Option<Row<'_>>> {
+        match &mut self.source {
+            Source::Csv(reader) => {
+                if !reader.read_record(&mut self.record)? {
+                    return Ok(None);
+                }
+                Ok(Some(Row { headers: &self.headers, record: &self.record, json: None }))
+            }
+            Source::JsonLines(data, lines) => loop {
+                self.line.clear();
+                if data.read_line(&mut self.line)? == 0 {
+                    return Ok(None);
+                }
+                *lines += 1;
+                if self.line.trim().is_empty() {
+                    continue;
+                }
+
+                // the raw values are kept as text, like a CSV row's (which leaves a
+                // line that isn't an object with no values at all)
+                let mut parsed = serde_json::from_str::<Value>(&self.line);
+                self.record.clear();
+                for key in JSON_KEYS {
+                    match parsed.as_ref().ok().and_then(|v| v.get(key)) {
+                        None | Some(Value::Null) => self.record.push_field(""),
+                        Some(Value::String(s)) => self.record.push_field(s),
+                        Some(v) => self.record.push_field(&v.to_string()),
+                    }
+                }
+                let mut position = Position::new();
+                position.set_line(*lines);
+                self.record.set_position(Some(position));
+
+                let amount = parsed.as_mut().ok().and_then(Value::as_object_mut).and_then(|o| o.remove("amount"));
+                self.json = Some(parsed.map(|object| JsonRow { object, amount }));
+                return Ok(Some(Row { headers: &self.headers, record: &self.record, json: self.json.as_ref() }));
+            },
+        }
+    }
+}
+
+/// A single row of an input.
+pub struct Row<'a> {
+    headers: &'a StringRecord,
+    record: &'a StringRecord,
+    /// The parsed JSON Lines row
+    json: Option<&'a serde_json::Result<JsonRow>>,
+}
+
+impl<'a> Row<'a> {
+    /// Returns the line number of the row in the input.
+    pub fn line(&self) -> u64 {
+        self.record.position().map_or(0, |p| p.line())
+    }
+
+    /// Returns the raw value of the named column (or key), or an empty string if
+    /// the row doesn't have that column.
+    pub fn field(&self, name: &str) -> &'a str {
+        self.headers.iter()
+            .position(|h| h == name)
+            .and_then(|i| self.record.get(i))
+            .unwrap_or("")
+    }
+
+    /// Parses the row into a transaction.
+    pub fn tx(&self) -> Result<Tx, RowError> {
+        match self.json {
+            Some(Ok(json)) => json.tx().map_err(RowError::Json),
+            // the rows keep the error itself, so this is a copy of it
+            Some(Err(e)) => Err(RowError::Json(de::Error::custom(e))),
+            None => self.record.deserialize(Some(self.headers)).map_err(RowError::Csv),
+        }
+    }
+}
+
+/// A JSON Lines row, with its amount taken out to be read on its own.
+struct JsonRow {
+    object: Value,
+    amount: Option<Value>,
+}
+
+impl JsonRow {
+    /// Parses the row into a transaction.
+    ///
+    /// The amount may be given as a string (like the accounts are written out) or as
+    /// a number (see [`number_amount`]).
+    fn tx(&self) -> serde_json::Result<Tx> {
+        let mut tx = Tx::deserialize(&self.object)?;
+        tx.amount = match &self.amount {
+            Some(Value::Number(n)) => Some(number_amount(n).map_err(de::Error::custom)?),
+            Some(amount) => Option::deserialize(amount)?,
+            None => None,
+        };
+        Ok(tx)
+    }
+}
+
+/// Reads an amount given as a JSON number.
+///
+/// Integers are read exactly, and other numbers from the shortest decimal that
+/// reads back as the same `f64` (so `0.1` is exactly 0.1). Since an `f64` only
+/// holds 15 significant digits for sure, an amount with more than that has to be
+/// a string.
+fn number_amount(n: &Number) -> Result<Amount, Box<dyn Error>> {
+    let Some(f) = n.as_f64().filter(|_| n.is_f64()) else {
+        return Ok(n.to_string().parse()?);
+    };
+    // unlike the number's own, this never uses an exponent
+    let text = f.to_string();
+    let amount = text.parse()?;
+    let digits = text.trim_start_matches('-').replace('.', "");
+    if digits.trim_matches('0').len() > f64::DIGITS as usize {
+        return Err(format!("amount {text} has more significant digits than a JSON number holds exactly").into());
+    }
+    Ok(amount)
+}
+
+/// Why a row couldn't be parsed into a [`Tx`].
+#[derive(Debug)]
+pub enum RowError {
+    Csv(csv::Error),
+    Json(serde_json::Error),
+}
+
+impl fmt::Display for RowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RowError::Csv(e) => e.fmt(f),
+            RowError::Json(e) => write!(f, "JSON deserialize error: {e}"),
+        }
+    }
+}
+
+impl Error for RowError {}
+
+/// A row of a client configuration CSV.
+#[derive(Deserialize)]
+struct ClientConfigRow {
+    client: u16,
+    credit_limit: Amount,
+}
+
+/// Reads the credit limit of every client in a client configuration CSV (with
+/// `client` and `credit_limit` columns), to be set with
+/// [`Engine::set_credit_limit`].
 ///
-/// CREDIT: This is taken directly from the `csv` crate tutorial found @ https://docs.rs/csv/latest/csv/tutorial/index.html
-pub fn get_first_arg() -> Result<OsString, Box<dyn Error>> {
-    match env::args_os().nth(1) {
-        None => Err(From::from("expected 1 argument, but got none")),
-        Some(file_path) => Ok(file_path),
+/// Unlike transactions, a row that can't be parsed (or a negative limit) fails
+/// the whole configuration.
+pub fn read_credit_limits<R>(data: R) -> Result<Vec<(u16, Amount)>, Box<dyn Error>>
+    where R: Read
+{
+    let mut limits = Vec::new();
+    for row in reader(data).deserialize() {
+        let row: ClientConfigRow = row?;
+        if row.credit_limit.is_negative() {
+            return Err(format!("credit limit of client {} is negative", row.client).into());
+        }
+        limits.push((row.client, row.credit_limit));
+    }
+    Ok(limits)
+}
+
+/// Reads the risk limits from JSON (see [`Limits`]), to be set with
+/// [`Engine::set_limits`].
+pub fn read_limits<R>(data: R) -> Result<Limits, Box<dyn Error>>
+    where R: Read
+{
+    let limits: Limits = serde_json::from_reader(data)?;
+    if [limits.max_withdrawal, limits.daily_withdrawal].into_iter().flatten().any(|max| max.is_negative()) {
+        return Err("withdrawal limits can't be negative".into());
+    }
+    if limits.day == 0 {
+        return Err("the length of a day must be positive".into());
+    }
+    Ok(limits)
+}
+
+/// Processes every row of a CSV reader through the engine (see [`process_rows`]).
+pub fn process_csv<T, A, R, W>(engine: &mut Engine<T, A>, source: &str, reader: Reader<R>, rejected: Option<&mut RecordWriter<W>>, skip: &mut u64) -> Result<(), Box<dyn Error>>
+    where T: TxStore, A: AccountStore, R: Read, W: Write
+{
+    process_rows(engine, source, Rows::new(reader)?, rejected, skip)
+}
+
+/// Processes every row of an input through the engine.
+///
+/// Rows that can't be parsed or that the engine rejects are skipped, and written
+/// to the `rejected` report (labelled with the `source` they came from) if there
+/// is one. Only I/O errors and fatal engine errors (reported along with the row
+/// that caused them) stop the processing.
+///
+/// Well-formed rows aren't processed at all while `skip` is above zero, which is
+/// how the rows already recovered from a write-ahead log (see
+/// [`Engine::processed`]) are skipped when resuming. It is counted down as rows
+/// are skipped so that it can carry over from one input to the next.
+pub fn process_rows<T, A, R, W>(engine: &mut Engine<T, A>, source: &str, mut rows: Rows<R>, mut rejected: Option<&mut RecordWriter<W>>, skip: &mut u64) -> Result<(), Box<dyn Error>>
+    where T: TxStore, A: AccountStore, R: Read, W: Write
+{
+    while let Some(row) = rows.next_row()? {
+        let result = match row.tx() {
+            Ok(_) if *skip > 0 => {
+                *skip -= 1;
+                continue;
+            }
+            Ok(tx) => match engine.process_tx(tx) {
+                Err(e) if e.is_fatal() => return Err(format!("{source} row {}: {e}", row.line()).into()),
+                res => res.map_err(|e| (e.code(), e.to_string())),
+            },
+            Err(e) => Err((MALFORMED_ROW, e.to_string())),
+        };
+        if let (Err((code, message)), Some(writer)) = (result, rejected.as_mut()) {
+            writer.serialize(Rejection::new(source, &row, code, message))?;
+        }
+    }
+    if let Some(writer) = rejected {
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+//------------------------------------------------------------------------------
+//------------------------------------------------------------------------------
+// TESTS
+//------------------------------------------------------------------------------
+//------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::output;
+    use crate::transaction::MAX_REASON_LEN;
+
+    #[test]
+    fn multiple_inputs() {
+        let monday = "type, client, tx, amount
+            deposit,    1,  1,  1.0
+            deposit,    1,  2,  oops
+            withdrawal, 1,  3,  5.0";
+        let tuesday = "type, client, tx, amount
+            deposit,    1,  4,  1.0
+            deposit,    1,  1,  1.0";
+
+        let mut engine = Engine::new();
+        let mut rejected = output::writer(Format::Csv, Vec::new());
+        rejected.header(&Rejection::HEADER).unwrap();
+        let mut skip = 0;
+        process_csv(&mut engine, "monday.csv", reader(monday.as_bytes()), Some(&mut rejected), &mut skip).unwrap();
+        process_csv(&mut engine, "tuesday.csv", reader(tuesday.as_bytes()), Some(&mut rejected), &mut skip).unwrap();
+
+        assert_eq!(Ok(engine.acct_map[&1].available), "2.0".parse());
+        let report = String::from_utf8(rejected.into_inner().unwrap()).unwrap();
+        let rows: Vec<_> = report.lines().map(|l| l.split(',').take(8).collect::<Vec<_>>().join(",")).collect();
+        assert_eq!(vec![
+            "file,row,type,client,tx,amount,dest,code",
+            "monday.csv,3,deposit,1,2,oops,,malformed_row",
+            "monday.csv,4,withdrawal,1,3,5.0,,insufficient_funds",
+            "tuesday.csv,3,deposit,1,1,1.0,,duplicate_tx_id",
+        ], rows);
+    }
+
+    #[test]
+    fn skip_carries_over() {
+        let monday = "type, client, tx, amount
+            deposit,    1,  1,  1.0
+            deposit,    1,  2,  oops";
+        let tuesday = "type, client, tx, amount
+            deposit,    1,  3,  1.0
+            deposit,    1,  4,  1.0";
+
+        // the malformed row doesn't count towards the rows being skipped
+        let mut engine = Engine::new();
+        let mut skip = 2;
+        process_csv(&mut engine, "monday.csv", reader(monday.as_bytes()), None::<&mut RecordWriter<Vec<u8>>>, &mut skip).unwrap();
+        assert_eq!(1, skip);
+        process_csv(&mut engine, "tuesday.csv", reader(tuesday.as_bytes()), None::<&mut RecordWriter<Vec<u8>>>, &mut skip).unwrap();
+        assert_eq!(0, skip);
+
+        assert_eq!(1, engine.tx_map.len());
+        assert!(engine.tx_map.contains_key(&4));
+    }
+
+    #[test]
+    fn credit_limits() {
+        let config = "client, credit_limit
+            1,      100.0
+            7,      0.5";
+        let limits = read_credit_limits(config.as_bytes()).unwrap();
+        assert_eq!(vec![(1, Amount::from_raw(1_000_000)), (7, Amount::from_raw(5_000))], limits);
+
+        assert!(read_credit_limits("client, credit_limit\n1, -1.0".as_bytes()).is_err());
+        assert!(read_credit_limits("client, credit_limit\n1, lots".as_bytes()).is_err());
+        assert!(read_credit_limits("client\n1".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn limits() {
+        let json = r#"{"max_withdrawal": "500.0", "velocity": {"count": 10, "window": 3600}}"#;
+        let limits = read_limits(json.as_bytes()).unwrap();
+        assert_eq!(Some(Amount::from_raw(5_000_000)), limits.max_withdrawal);
+        assert_eq!(None, limits.daily_withdrawal);
+        assert_eq!(86_400, limits.day);
+        assert_eq!(Some((10, 3600)), limits.velocity.map(|v| (v.count, v.window)));
+
+        assert_eq!(Limits::default(), read_limits("{}".as_bytes()).unwrap());
+        assert!(read_limits(r#"{"max_withdrawal": "-1.0"}"#.as_bytes()).is_err());
+        assert!(read_limits(r#"{"day": 0}"#.as_bytes()).is_err());
+        assert!(read_limits(r#"{"max_withdrawals": "1.0"}"#.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn long_reasons() {
+        let longest = "a".repeat(MAX_REASON_LEN);
+        let csv = format!("type,client,tx,reason\nfreeze,1,1,{longest}\nunfreeze,1,2,{longest}a\n");
+        let jsonl = format!("{{\"type\": \"freeze\", \"client\": 1, \"tx\": 1, \"reason\": \"{longest}\"}}\n{{\"type\": \"unfreeze\", \"client\": 1, \"tx\": 2, \"reason\": \"{longest}a\"}}\n");
+
+        for (format, data) in [(Format::Csv, csv), (Format::JsonLines, jsonl)] {
+            let mut rows = Rows::with_format(format, data.as_bytes()).unwrap();
+            assert_eq!(Some(longest.as_str()), rows.next_row().unwrap().unwrap().tx().unwrap().reason.as_deref());
+            let e = rows.next_row().unwrap().unwrap().tx().unwrap_err();
+            assert!(e.to_string().contains("reason is longer than 65535 bytes"), "{e}");
+        }
+    }
+
+    #[test]
+    fn json_lines() {
+        let data = r#"{"type": "deposit", "client": 1, "tx": 1, "amount": "1.5"}
+
+{"type": "withdrawal", "client": 1, "tx": 2, "amount": 0.25, "dest": null}
+{"type": "deposit", "client": 1, "tx": 3, "amount": 0.12345}
+{"type": "dispute", "client": "one", "tx": 1}
+not json
+{"type": "withdrawal", "client": 1, "tx": 4, "amount": 5}
+{"type": "deposit", "client": 1, "tx": 5, "amount": 1e-5}
+{"type": "deposit", "client": 1, "tx": 6, "amount": 1234567890123.4567}
+{"type": "deposit", "client": 1, "tx": 7, "amount": true}
+{"type": "deposit", "client": 1, "tx": 8, "amount": 2.5e-1}
+"#;
+
+        let mut engine = Engine::new();
+        let mut rejected = output::writer(Format::JsonLines, Vec::new());
+        let rows = Rows::with_format(Format::JsonLines, data.as_bytes()).unwrap();
+        process_rows(&mut engine, "tx.jsonl", rows, Some(&mut rejected), &mut 0).unwrap();
+
+        assert_eq!(Ok(engine.acct_map[&1].available), "1.5".parse());
+        let report = String::from_utf8(rejected.into_inner().unwrap()).unwrap();
+        let rows: Vec<(String, u64, String, String, String)> = report.lines()
+            .map(|l| serde_json::from_str::<Value>(l).unwrap())
+            .map(|r| (r["file"].as_str().unwrap().to_owned(), r["row"].as_u64().unwrap(), r["client"].as_str().unwrap().to_owned(), r["amount"].as_str().unwrap().to_owned(), r["code"].as_str().unwrap().to_owned()))
+            .collect();
+        let row = |row, client: &str, amount: &str, code: &str| ("tx.jsonl".to_owned(), row, client.to_owned(), amount.to_owned(), code.to_owned());
+        assert_eq!(vec![
+            row(4, "1", "0.12345", MALFORMED_ROW),
+            row(5, "one", "", MALFORMED_ROW),
+            row(6, "", "", MALFORMED_ROW),
+            row(7, "1", "5", "insufficient_funds"),
+            row(8, "1", "0.00001", MALFORMED_ROW),
+            row(9, "1", "1234567890123.4568", MALFORMED_ROW),
+            row(10, "1", "true", MALFORMED_ROW),
+        ], rows);
     }
 }
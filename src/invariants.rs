@@ -0,0 +1,229 @@
+//! Contains the [`Invariant`]s a strict [`Engine`](crate::Engine) checks after
+//! every transaction (see [`Engine::set_strict`](crate::Engine::set_strict)).
+//!
+//! Only the accounts a transaction touched are checked, so each check costs about
+//! as much as the number of transactions those clients have recorded.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::account::{Acct, LockReason};
+use crate::amount::Amount;
+use crate::engine::{Config, RecTx};
+use crate::store::TxStore;
+use crate::transaction::{Tx, TxType};
+
+/// A rule the state of every account must always follow.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Invariant {
+    /// An account's total is the sum of its available and held funds.
+    Total,
+    /// An account's held funds are the sum of the disputed portions of its
+    /// transactions.
+    Held,
+    /// A locked account only changes by the transactions the lock policy allows,
+    /// only an unfreeze unlocks it, and a closed account stays closed.
+    Locked,
+    /// An account's total only goes below zero (past the client's credit limit) by
+    /// a chargeback, an allowed fee overdraft, or interest paid out of the house
+    /// account (beyond spending the funds a disputed withdrawal or transfer
+    /// returned, which are held negatively).
+    NegativeTotal,
+}
+
+impl Invariant {
+    /// Returns the stable, machine-readable name of the invariant.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Invariant::Total => "total_is_available_plus_held",
+            Invariant::Held => "held_is_disputed_amounts",
+            Invariant::Locked => "locked_account_unchanged",
+            Invariant::NegativeTotal => "no_negative_total",
+        }
+    }
+}
+
+/// A transaction that broke an invariant, along with the state it changed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Violation {
+    pub invariant: Invariant,
+    /// The client whose account broke the invariant.
+    pub client_id: u16,
+    /// Every account the transaction touched, from before it.
+    pub before: Vec<(u16, Acct)>,
+    /// Every account the transaction touched, from after it.
+    pub after: Vec<(u16, Acct)>,
+    /// The recorded transaction the transaction referenced, from before it.
+    pub tx_before: Option<RecTx>,
+    /// The recorded transaction the transaction created or referenced, from after it.
+    pub tx_after: Option<RecTx>,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invariant {} broken for client {}", self.invariant.code(), self.client_id)?;
+        for (label, accts, tx) in [("before", &self.before, &self.tx_before), ("after", &self.after, &self.tx_after)] {
+            write!(f, "\n  {label}:")?;
+            for (client_id, a) in accts {
+                let lock = a.lock.map_or_else(|| "none".to_owned(), |l| l.to_string());
+                write!(f, "\n    client {client_id}: available {}, held {}, total {}, lock {lock}", a.available, a.held, a.total)?;
+            }
+            if let Some(t) = tx {
+                write!(f, "\n    tx: {} of {} for client {}, {} disputed, {} charged back, disputed {} time(s)", t.kind, t.amount, t.client_id, t.disputed, t.charged_back, t.disputes)?;
+                if let Some(dest) = t.dest {
+                    write!(f, ", to client {dest}")?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Checks the invariants, keeping track of which recorded transactions each
+/// client's held funds can come from.
+#[derive(Debug, Default)]
+pub(crate) struct Checker {
+    /// The recorded transactions each client is the client (or destination) of
+    txs: HashMap<u16, Vec<u32>>,
+}
+
+impl Checker {
+    /// Creates a checker for the transactions already recorded.
+    pub(crate) fn new<T>(tx_map: &T) -> Self
+        where T: TxStore
+    {
+        let mut checker = Checker::default();
+        for (tx_id, t) in tx_map.iter() {
+            checker.record(tx_id, &t);
+        }
+        checker
+    }
+
+    /// Keeps track of a newly recorded transaction.
+    pub(crate) fn record(&mut self, tx_id: u32, t: &RecTx) {
+        self.txs.entry(t.client_id).or_default().push(tx_id);
+        if let Some(dest) = t.dest {
+            self.txs.entry(dest).or_default().push(tx_id);
+        }
+    }
+
+    /// Checks every account an accepted transaction touched, returning the first
+    /// invariant broken and by whose account.
+    pub(crate) fn check<T>(&self, tx: &Tx, config: &Config, tx_map: &T, before: &[(u16, Acct)], after: &[(u16, Acct)]) -> Result<(), (Invariant, u16)>
+        where T: TxStore
+    {
+        for ((client_id, b), (_, a)) in before.iter().zip(after) {
+            let client_id = *client_id;
+            let broken = |invariant| Err((invariant, client_id));
+
+            if a.available.checked_add(a.held) != Some(a.total) {
+                return broken(Invariant::Total);
+            }
+            if self.held(client_id, tx_map) != Some(a.held) {
+                return broken(Invariant::Held);
+            }
+
+            let changed = (a.available, a.held, a.total) != (b.available, b.held, b.total);
+            let allowed = client_id == tx.client_id && b.lock.is_none_or(|l| config.lock_policy.allows(l, tx.tx_type));
+            let unlocked = b.lock.is_some() && a.lock.is_none() && tx.tx_type != TxType::Unfreeze;
+            let reopened = b.lock == Some(LockReason::Closed) && a.lock != Some(LockReason::Closed);
+            if (b.locked() && changed && !allowed) || unlocked || reopened {
+                return broken(Invariant::Locked);
+            }
+
+            // the credit limit is never negative, so adding it can only overflow upwards
+            let credited = a.total.checked_add(config.credit_limit(client_id));
+            let overdrawn = credited.is_some_and(|t| t < a.held.min(Amount::default())) && a.total < b.total;
+            let may_overdraw = match tx.tx_type {
+                TxType::Chargeback => true,
+                TxType::Fee => client_id == tx.client_id && config.allow_fee_overdraft,
+                TxType::Interest => Some(client_id) == config.house_account,
+                _ => false,
+            };
+            if overdrawn && !may_overdraw {
+                return broken(Invariant::NegativeTotal);
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the sum of the disputed portions of a client's transactions (from the
+    /// client's side), if it fits.
+    fn held<T>(&self, client_id: u16, tx_map: &T) -> Option<Amount>
+        where T: TxStore
+    {
+        let mut held = Amount::default();
+        for tx_id in self.txs.get(&client_id).into_iter().flatten() {
+            if let Some(t) = tx_map.get(*tx_id) {
+                held = if t.client_id == client_id { held.checked_add(t.disputed)? } else { held.checked_sub(t.disputed)? };
+            }
+        }
+        Some(held)
+    }
+}
+
+//------------------------------------------------------------------------------
+//------------------------------------------------------------------------------
+// TESTS
+//------------------------------------------------------------------------------
+//------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::engine::Engine;
+    use crate::error::ProcessError;
+    use crate::ledger::LedgerAcct;
+
+    fn amt(s: &str) -> Amount {
+        s.parse().unwrap()
+    }
+
+    fn deposit(tx_id: u32, amount: &str) -> Tx {
+        Tx{ tx_type: TxType::Deposit, client_id: 1, tx_id, amount: Some(amt(amount)), dest: None, reason: None, timestamp: None }
+    }
+
+    #[test]
+    fn violations() {
+        let mut engine = Engine::new();
+        engine.process_tx(deposit(1, "1.0")).unwrap();
+        engine.set_strict(true);
+        engine.process_tx(deposit(2, "1.0")).unwrap();
+
+        // funds held outside of any dispute
+        let tampered = Acct{ available: amt("2.0"), held: amt("1.0"), total: amt("3.0"), lock: None };
+        engine.acct_map.insert(1, tampered.clone());
+        engine.ledger_mut().set(LedgerAcct::Available(1), tampered.available);
+        engine.ledger_mut().set(LedgerAcct::Held(1), tampered.held);
+        let Err(ProcessError::InvariantViolated { client_id: 1, tx_id: 3, violation }) = engine.process_tx(deposit(3, "0.5")) else {
+            panic!("expected a violation");
+        };
+        assert_eq!(Invariant::Held, violation.invariant);
+        assert_eq!(vec![(1, tampered)], violation.before);
+        assert_eq!(vec![(1, Acct{ available: amt("2.5"), held: amt("1.0"), total: amt("3.5"), lock: None })], violation.after);
+        assert_eq!(None, violation.tx_before);
+        assert_eq!(Some(amt("0.5")), violation.tx_after.map(|t| t.amount));
+
+        // a total that isn't the sum of the balances
+        engine.acct_map.insert(1, Acct{ available: amt("2.0"), held: amt("0.0"), total: amt("3.0"), lock: None });
+        let freeze = Tx{ tx_type: TxType::Freeze, amount: None, reason: Some("review".to_owned()), ..deposit(4, "0") };
+        let err = engine.process_tx(freeze).unwrap_err();
+        assert!(err.is_fatal());
+        assert_eq!("invariant_violated", err.code());
+        let ProcessError::InvariantViolated { violation, .. } = err else { unreachable!() };
+        assert_eq!(Invariant::Total, violation.invariant);
+
+        // the rules that depend on how the account changed
+        let checker = Checker::default();
+        let config = Config::default();
+        let tx_map = crate::engine::TxMap::new();
+        let open = Acct{ available: amt("-1.0"), total: amt("-1.0"), ..Default::default() };
+        let closed = Acct{ lock: Some(LockReason::Closed), ..open.clone() };
+        let check = |tx: &Tx, before: &Acct, after: &Acct| checker.check(tx, &config, &tx_map, &[(1, before.clone())], &[(1, after.clone())]);
+        assert_eq!(Err((Invariant::Locked, 1)), check(&deposit(5, "1.0"), &closed, &Acct{ lock: Some(LockReason::Closed), ..Default::default() }));
+        assert_eq!(Err((Invariant::Locked, 1)), check(&deposit(5, "1.0"), &closed, &open));
+        assert_eq!(Err((Invariant::NegativeTotal, 1)), check(&deposit(5, "1.0"), &Acct::default(), &open));
+        let chargeback = Tx{ tx_type: TxType::Chargeback, amount: None, ..deposit(5, "1.0") };
+        assert_eq!(Ok(()), check(&chargeback, &Acct::default(), &open));
+    }
+}
@@ -0,0 +1,143 @@
+//! Contains the [`Event`]s an [`Engine`](crate::Engine) journals every accepted
+//! transaction as, and the [`Subscriber`]s that receive them.
+//!
+//! Each accepted transaction becomes one event per account it touched (so a
+//! transfer becomes two), in the order the accounts were changed. An event holds
+//! both how the account's balances changed and what they ended up as, so the
+//! balances of any client after any transaction can be read straight off the
+//! journal.
+
+use std::io::{self, Write};
+
+use serde::Serialize;
+
+use crate::account::Acct;
+use crate::amount::Amount;
+use crate::error::ProcessError;
+use crate::transaction::{Tx, TxType};
+
+/// A change to a single account made by an accepted transaction.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Event {
+    /// The sequence number of the transaction (see [`Engine::processed`](crate::Engine::processed)).
+    pub seq: u64,
+    #[serde(rename = "tx")]
+    pub tx_id: u32,
+    /// The client whose account changed (which for the other side of a transfer,
+    /// fee, or interest isn't the client of the transaction).
+    #[serde(rename = "client")]
+    pub client_id: u16,
+    #[serde(rename = "type")]
+    pub tx_type: TxType,
+    /// The timestamp of the transaction, if it had one.
+    pub timestamp: Option<u64>,
+    pub available_delta: Amount,
+    pub held_delta: Amount,
+    pub total_delta: Amount,
+    /// The balances after the change.
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
+    pub locked: bool,
+}
+
+impl Event {
+    /// Builds the event for a transaction's change to a client's account.
+    pub(crate) fn new(seq: u64, tx: &Tx, client_id: u16, before: &Acct, after: &Acct) -> Self {
+        // every balance changes by at most a single amount, which always fits
+        let delta = |after: Amount, before: Amount| after.checked_sub(before).expect("balance change overflowed");
+        Event {
+            seq,
+            tx_id: tx.tx_id,
+            client_id,
+            tx_type: tx.tx_type,
+            timestamp: tx.timestamp,
+            available_delta: delta(after.available, before.available),
+            held_delta: delta(after.held, before.held),
+            total_delta: delta(after.total, before.total),
+            available: after.available,
+            held: after.held,
+            total: after.total,
+            locked: after.locked(),
+        }
+    }
+}
+
+/// Receives every event an engine journals, as soon as the transaction is accepted.
+///
+/// Failing to take an event is a fatal error for the transaction (see
+/// [`ProcessError::is_fatal`](crate::ProcessError::is_fatal)), just like failing to
+/// log it to the write-ahead log.
+pub trait Subscriber: Send {
+    fn event(&mut self, event: &Event) -> io::Result<()>;
+
+    /// Takes a transaction the engine rejected (with the sequence number it was
+    /// submitted as), which changed nothing. Nothing by default.
+    fn rejected(&mut self, _seq: u64, _tx: &Tx, _error: &ProcessError) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Makes sure every event taken so far has been fully handled.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<F> Subscriber for F
+    where F: FnMut(&Event) + Send
+{
+    fn event(&mut self, event: &Event) -> io::Result<()> {
+        self(event);
+        Ok(())
+    }
+}
+
+/// A subscriber that writes every event as a line of JSON.
+pub struct JsonLines<W> {
+    out: W,
+}
+
+impl<W> JsonLines<W>
+    where W: Write
+{
+    pub fn new(out: W) -> Self {
+        JsonLines { out }
+    }
+}
+
+impl<W> Subscriber for JsonLines<W>
+    where W: Write + Send
+{
+    fn event(&mut self, event: &Event) -> io::Result<()> {
+        serde_json::to_writer(&mut self.out, event)?;
+        self.out.write_all(b"\n")
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+//------------------------------------------------------------------------------
+//------------------------------------------------------------------------------
+// TESTS
+//------------------------------------------------------------------------------
+//------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn json_lines() {
+        let tx = Tx{ tx_type: TxType::Deposit, client_id: 1, tx_id: 2, amount: Some(Amount::from_raw(15_000)), dest: None, reason: None, timestamp: Some(1_700_000_000) };
+        let after = Acct{ available: Amount::from_raw(15_000), total: Amount::from_raw(15_000), ..Default::default() };
+        let event = Event::new(3, &tx, 1, &Acct::default(), &after);
+
+        let mut out = JsonLines::new(Vec::new());
+        out.event(&event).unwrap();
+        out.event(&event).unwrap();
+        let line = r#"{"seq":3,"tx":2,"client":1,"type":"deposit","timestamp":1700000000,"available_delta":"1.5000","held_delta":"0.0000","total_delta":"1.5000","available":"1.5000","held":"0.0000","total":"1.5000","locked":false}"#;
+        assert_eq!(format!("{line}\n{line}\n"), String::from_utf8(out.out).unwrap());
+    }
+}
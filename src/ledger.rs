@@ -0,0 +1,333 @@
+//! Contains the double-entry [`Ledger`] every movement of funds is posted to.
+//!
+//! Every client has two accounts in the ledger, their available funds and their
+//! held funds, and the `available` and `held` balances of their [`Acct`] are
+//! derived from them whenever an entry is posted (an account's `total` is always
+//! the sum of the two). Funds enter and leave through the accounts outside of any
+//! client: deposits come from (and withdrawals go to) the external account, and
+//! charged back funds go to the settlement account.
+//!
+//! Every transaction posts [`Entry`]s that debit one account and credit another
+//! by the same amount, so the balances of every account always sum to zero (see
+//! [`Engine::trial_balance`]). Balances are credit-normal, meaning crediting an
+//! account increases its balance.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+
+use crate::account::{Acct, AcctError, LockReason};
+use crate::amount::Amount;
+use crate::engine::Engine;
+use crate::store::{AccountStore, TxStore};
+
+/// An account in the ledger.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum LedgerAcct {
+    /// A client's available funds.
+    Available(u16),
+    /// A client's held funds.
+    Held(u16),
+    /// Where deposits come from and withdrawals go to.
+    External,
+    /// Where charged back funds go to.
+    Settlement,
+}
+
+impl LedgerAcct {
+    /// Returns whether this account is outside of any client.
+    pub fn is_outside(self) -> bool {
+        matches!(self, LedgerAcct::External | LedgerAcct::Settlement)
+    }
+
+    /// Returns the client this account belongs to, if any.
+    pub fn client(self) -> Option<u16> {
+        match self {
+            LedgerAcct::Available(client_id) | LedgerAcct::Held(client_id) => Some(client_id),
+            LedgerAcct::External | LedgerAcct::Settlement => None,
+        }
+    }
+}
+
+impl fmt::Display for LedgerAcct {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LedgerAcct::Available(client_id) => write!(f, "available:{client_id}"),
+            LedgerAcct::Held(client_id) => write!(f, "held:{client_id}"),
+            LedgerAcct::External => f.write_str("external"),
+            LedgerAcct::Settlement => f.write_str("settlement"),
+        }
+    }
+}
+
+/// A movement of a positive amount from one account (the debit) to another (the
+/// credit).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Entry {
+    pub debit: LedgerAcct,
+    pub credit: LedgerAcct,
+    pub amount: Amount,
+}
+
+impl Entry {
+    /// Builds an entry moving an amount from one account to another, or the other
+    /// way around for a negative amount (`None` if it can't be flipped).
+    pub fn new(debit: LedgerAcct, credit: LedgerAcct, amount: Amount) -> Option<Self> {
+        if amount < Amount::default() {
+            Some(Entry { debit: credit, credit: debit, amount: amount.checked_neg()? })
+        } else {
+            Some(Entry { debit, credit, amount })
+        }
+    }
+
+    /// Returns an account's balance after the entry is posted to it, or `None` if it
+    /// overflows.
+    pub fn apply(&self, acct: LedgerAcct, balance: Amount) -> Option<Amount> {
+        match acct {
+            _ if acct == self.credit => balance.checked_add(self.amount),
+            _ if acct == self.debit => balance.checked_sub(self.amount),
+            _ => Some(balance),
+        }
+    }
+}
+
+/// The balance of every account in the ledger.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Ledger {
+    /// Only the accounts with a nonzero balance are kept
+    balances: BTreeMap<LedgerAcct, Amount>,
+}
+
+/// The balances some of the accounts in a [`Ledger`] would have after posting
+/// entries, worked out without posting them.
+#[derive(Debug)]
+pub(crate) struct Posting {
+    changed: Vec<(LedgerAcct, Amount)>,
+}
+
+impl Posting {
+    fn balance(&self, acct: LedgerAcct) -> Option<Amount> {
+        self.changed.iter().find(|(a, _)| *a == acct).map(|(_, b)| *b)
+    }
+}
+
+impl Ledger {
+    /// Returns the balance of an account.
+    pub fn balance(&self, acct: LedgerAcct) -> Amount {
+        self.balances.get(&acct).copied().unwrap_or_default()
+    }
+
+    /// Iterates over the balances of every account whose balance isn't zero.
+    pub fn iter(&self) -> impl Iterator<Item = (LedgerAcct, Amount)> + '_ {
+        self.balances.iter().map(|(k, v)| (*k, *v))
+    }
+
+    /// Iterates over the balances of the accounts outside of any client.
+    pub fn outside(&self) -> impl Iterator<Item = (LedgerAcct, Amount)> + '_ {
+        self.iter().filter(|(acct, _)| acct.is_outside())
+    }
+
+    /// Works out the balances the accounts picked out by `owns` would have after
+    /// posting the entries (the sides of the entries on other accounts are left
+    /// to whichever ledger keeps them).
+    pub(crate) fn stage<F>(&self, entries: &[Entry], owns: F) -> Result<Posting, AcctError>
+        where F: Fn(LedgerAcct) -> bool
+    {
+        let mut posting = Posting { changed: Vec::new() };
+        for entry in entries {
+            for acct in [entry.debit, entry.credit].into_iter().filter(|a| owns(*a)) {
+                let balance = posting.balance(acct).unwrap_or_else(|| self.balance(acct));
+                let balance = entry.apply(acct, balance).ok_or(AcctError::Overflow)?;
+                match posting.changed.iter_mut().find(|(a, _)| *a == acct) {
+                    Some((_, b)) => *b = balance,
+                    None => posting.changed.push((acct, balance)),
+                }
+            }
+        }
+        Ok(posting)
+    }
+
+    /// Returns a client's account with the balances it would have after a staged
+    /// posting.
+    ///
+    /// If there's a `floor`, the posting may not take the available funds below it
+    /// (unless it adds to them).
+    pub(crate) fn acct(&self, posting: &Posting, client_id: u16, lock: Option<LockReason>, floor: Option<Amount>) -> Result<Acct, AcctError> {
+        let (available, held) = (LedgerAcct::Available(client_id), LedgerAcct::Held(client_id));
+        let balance = |acct| posting.balance(acct).unwrap_or_else(|| self.balance(acct));
+        if floor.is_some_and(|floor| balance(available) < self.balance(available) && balance(available) < floor) {
+            return Err(AcctError::InsufficientFunds);
+        }
+        let mut acct = Acct { lock, ..Default::default() };
+        acct.set(balance(available), balance(held))?;
+        Ok(acct)
+    }
+
+    /// Posts a staged posting.
+    pub(crate) fn commit(&mut self, posting: Posting) {
+        for (acct, balance) in posting.changed {
+            self.set(acct, balance);
+        }
+    }
+
+    /// Posts entries opening a client's accounts with the balances of an [`Acct`],
+    /// moving the funds in from the external account.
+    pub(crate) fn open(&mut self, client_id: u16, acct: &Acct) -> Result<(), AcctError> {
+        let entries: Option<Vec<Entry>> = [(LedgerAcct::Available(client_id), acct.available), (LedgerAcct::Held(client_id), acct.held)]
+            .into_iter()
+            .map(|(ledger_acct, balance)| Entry::new(LedgerAcct::External, ledger_acct, balance))
+            .collect();
+        let posting = self.stage(&entries.ok_or(AcctError::Overflow)?, |_| true)?;
+        self.commit(posting);
+        Ok(())
+    }
+
+    /// Sets the balance of an account.
+    pub(crate) fn set(&mut self, acct: LedgerAcct, balance: Amount) {
+        if balance == Amount::ZERO {
+            self.balances.remove(&acct);
+        } else {
+            self.balances.insert(acct, balance);
+        }
+    }
+
+    /// Adds the balances of another ledger (e.g., a shard's) to this one, leaving
+    /// this one untouched if any of the sums overflow.
+    pub(crate) fn merge(&mut self, other: &Ledger) -> Result<(), AcctError> {
+        let sums = other.iter()
+            .map(|(acct, balance)| Some((acct, self.balance(acct).checked_add(balance)?)))
+            .collect::<Option<Vec<_>>>()
+            .ok_or(AcctError::Overflow)?;
+        for (acct, sum) in sums {
+            self.set(acct, sum);
+        }
+        Ok(())
+    }
+}
+
+impl FromIterator<(LedgerAcct, Amount)> for Ledger {
+    fn from_iter<I>(balances: I) -> Self
+        where I: IntoIterator<Item = (LedgerAcct, Amount)>
+    {
+        let mut ledger = Ledger::default();
+        for (acct, balance) in balances {
+            ledger.set(acct, balance);
+        }
+        ledger
+    }
+}
+
+/// Why the books don't balance.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Imbalance {
+    /// A client's total isn't the sum of their available and held funds.
+    Total { client_id: u16 },
+    /// A client's available or held funds aren't the balances of their accounts in
+    /// the ledger.
+    Mismatch { client_id: u16 },
+    /// The balances of every account don't sum to zero.
+    Unbalanced { off_by: i128 },
+}
+
+impl fmt::Display for Imbalance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Imbalance::Total { client_id } => write!(f, "total of client {client_id} isn't their available and held funds"),
+            Imbalance::Mismatch { client_id } => write!(f, "balances of client {client_id} don't match the ledger"),
+            Imbalance::Unbalanced { off_by } => write!(f, "ledger is off by {off_by} ten-thousandths"),
+        }
+    }
+}
+
+impl Error for Imbalance {}
+
+impl<T, A> Engine<T, A>
+    where T: TxStore, A: AccountStore
+{
+    /// Checks that every client's available and held funds are the balances of
+    /// their accounts in the ledger (with their total the sum of the two), and that
+    /// the balances of every account in the ledger sum to zero.
+    pub fn trial_balance(&self) -> Result<(), Imbalance> {
+        let ledger = self.ledger();
+        for (client_id, acct) in self.acct_map.iter() {
+            if acct.available.checked_add(acct.held) != Some(acct.total) {
+                return Err(Imbalance::Total { client_id });
+            }
+            if acct.available != ledger.balance(LedgerAcct::Available(client_id)) || acct.held != ledger.balance(LedgerAcct::Held(client_id)) {
+                return Err(Imbalance::Mismatch { client_id });
+            }
+        }
+        // a client with balances in the ledger but no account is also a mismatch
+        for (ledger_acct, balance) in ledger.iter() {
+            if let Some(client_id) = ledger_acct.client().filter(|_| balance != Amount::ZERO) {
+                if self.acct_map.get(client_id).is_none() {
+                    return Err(Imbalance::Mismatch { client_id });
+                }
+            }
+        }
+        match ledger.iter().map(|(_, b)| i128::from(b.raw())).sum() {
+            0 => Ok(()),
+            off_by => Err(Imbalance::Unbalanced { off_by }),
+        }
+    }
+}
+
+//------------------------------------------------------------------------------
+//------------------------------------------------------------------------------
+// TESTS
+//------------------------------------------------------------------------------
+//------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn amt(s: &str) -> Amount {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn entries() {
+        let entry = Entry::new(LedgerAcct::Available(1), LedgerAcct::Held(1), amt("-2.0")).unwrap();
+        assert_eq!(Entry{ debit: LedgerAcct::Held(1), credit: LedgerAcct::Available(1), amount: amt("2.0") }, entry);
+        assert_eq!(Some(amt("2.0")), entry.apply(LedgerAcct::Available(1), Amount::ZERO));
+        assert_eq!(Some(amt("-2.0")), entry.apply(LedgerAcct::Held(1), Amount::ZERO));
+        assert_eq!(Some(amt("0.0")), entry.apply(LedgerAcct::Available(2), Amount::ZERO));
+        assert_eq!(None, Entry::new(LedgerAcct::Available(1), LedgerAcct::Held(1), Amount::from_raw(i64::MIN)));
+
+        let mut ledger = Ledger::default();
+        let client = |acct: LedgerAcct| acct.client() == Some(1);
+        assert_eq!(Ok(()), ledger.open(1, &Acct{ available: amt("1.0"), held: amt("2.0"), total: amt("3.0"), lock: None }));
+        assert_eq!(amt("-3.0"), ledger.balance(LedgerAcct::External));
+        let posting = ledger.stage(&[entry], client).unwrap();
+        assert_eq!(Ok(Acct{ available: amt("3.0"), held: amt("0.0"), total: amt("3.0"), lock: None }), ledger.acct(&posting, 1, None, Some(Amount::ZERO)));
+        ledger.commit(posting);
+
+        let withdrawal = Entry::new(LedgerAcct::Available(1), LedgerAcct::External, amt("4.0")).unwrap();
+        let posting = ledger.stage(&[withdrawal], client).unwrap();
+        assert_eq!(Err(AcctError::InsufficientFunds), ledger.acct(&posting, 1, None, Some(Amount::ZERO)));
+        assert_eq!(Err(AcctError::InsufficientFunds), ledger.acct(&posting, 1, None, Some(amt("-0.5"))));
+        let overdrawn = Acct{ available: amt("-1.0"), held: amt("0.0"), total: amt("-1.0"), lock: Some(LockReason::Frozen) };
+        assert_eq!(Ok(overdrawn.clone()), ledger.acct(&posting, 1, Some(LockReason::Frozen), Some(amt("-1.0"))));
+        assert_eq!(Ok(overdrawn), ledger.acct(&posting, 1, Some(LockReason::Frozen), None));
+
+        // each ledger stages the sides of the entries it keeps
+        let outside = ledger.stage(&[withdrawal, withdrawal], LedgerAcct::is_outside).unwrap();
+        ledger.commit(outside);
+        assert_eq!(vec![(LedgerAcct::External, amt("5.0"))], ledger.outside().collect::<Vec<_>>());
+        assert_eq!(amt("3.0"), ledger.balance(LedgerAcct::Available(1)));
+
+        let max = Entry::new(LedgerAcct::External, LedgerAcct::Available(1), Amount::from_raw(i64::MAX)).unwrap();
+        assert_eq!(AcctError::Overflow, ledger.stage(&[max], client).unwrap_err());
+
+        assert_eq!(Ok(()), ledger.merge(&ledger.clone()));
+        assert_eq!(amt("10.0"), ledger.balance(LedgerAcct::External));
+        assert_eq!(amt("6.0"), ledger.balance(LedgerAcct::Available(1)));
+
+        let mut full = Ledger::default();
+        full.set(LedgerAcct::External, amt("1.0"));
+        full.set(LedgerAcct::Available(1), Amount::from_raw(i64::MAX));
+        assert_eq!(Err(AcctError::Overflow), ledger.merge(&full));
+        assert_eq!(amt("10.0"), ledger.balance(LedgerAcct::External));
+    }
+}
@@ -0,0 +1,55 @@
+//! A toy payments engine.
+//!
+//! The [`Engine`] takes in a series of [`Tx`]s (deposits, withdrawals, and the
+//! dispute-related transactions that reference them) and keeps track of the
+//! resulting state of each client's [`Acct`].
+//!
+//! ```
+//! use toy_payments_engine::{Engine, input, output};
+//!
+//! let data = "type, client, tx, amount
+//!     deposit,    1,  1,  1.0
+//!     withdrawal, 1,  2,  0.25";
+//!
+//! let mut engine = Engine::new();
+//! input::process_csv(&mut engine, "example", input::reader(data.as_bytes()), None::<&mut output::RecordWriter<Vec<u8>>>, &mut 0)?;
+//!
+//! let acct = &engine.acct_map[&1];
+//! assert_eq!("0.7500", acct.available.to_string());
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+pub mod account;
+pub mod amount;
+mod checksum;
+pub mod engine;
+pub mod error;
+pub mod format;
+pub mod history;
+pub mod input;
+pub mod invariants;
+pub mod journal;
+pub mod ledger;
+pub mod limits;
+pub mod output;
+pub mod policy;
+pub mod sharded;
+mod snapshot;
+pub mod statement;
+pub mod stats;
+pub mod store;
+pub mod transaction;
+pub mod wal;
+
+pub use account::{Acct, AcctError, LockReason};
+pub use amount::Amount;
+pub use engine::{AuditEntry, Engine, RecTx, TxState};
+pub use error::ProcessError;
+pub use format::Format;
+pub use history::AsOf;
+pub use limits::{LimitRule, Limits};
+pub use policy::{DeadlineAction, DisputePolicy, LockPolicy};
+pub use sharded::ShardedEngine;
+pub use store::{AccountStore, TxStore};
+pub use transaction::{Tx, TxType};
+pub use wal::Wal;
@@ -0,0 +1,238 @@
+//! Contains the [`Limits`] that cap how much and how often clients can move
+//! funds, and the [`Usage`] of them each client has built up.
+//!
+//! Withdrawals and transfers both take funds out of a client's account, so both
+//! count as withdrawals here. The daily and velocity limits are measured with the
+//! transactions' timestamps, so only timestamped transactions count towards them
+//! (and are held to them).
+
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+
+use serde::Deserialize;
+
+use crate::amount::Amount;
+use crate::transaction::{Tx, TxType};
+
+/// A rule of the [`Limits`] that a transaction can break.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitRule {
+    /// A single withdrawal was for more than the maximum.
+    MaxWithdrawal,
+    /// A client's withdrawals in a day would add up to more than the maximum.
+    DailyWithdrawal,
+    /// A client would make more transactions within the window than allowed.
+    Velocity,
+}
+
+impl LimitRule {
+    /// Returns the stable, machine-readable name of the rule (as in the limits file).
+    pub fn code(&self) -> &'static str {
+        match self {
+            LimitRule::MaxWithdrawal => "max_withdrawal",
+            LimitRule::DailyWithdrawal => "daily_withdrawal",
+            LimitRule::Velocity => "velocity",
+        }
+    }
+}
+
+impl fmt::Display for LimitRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+/// At most `count` transactions within any `window` of time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Velocity {
+    pub count: u32,
+    /// The length of the window, in the units of the timestamps.
+    pub window: u64,
+}
+
+impl Velocity {
+    /// Returns whether a transaction made at `then` is still within the window at `now`.
+    fn within(&self, then: u64, now: u64) -> bool {
+        now.saturating_sub(then) < self.window
+    }
+}
+
+/// Caps how much and how often every client can move funds.
+///
+/// The default has no limits at all. Limits can be read from JSON, with amounts
+/// written as strings (like the accounts are written out), e.g.
+/// `{"max_withdrawal": "1000.0", "velocity": {"count": 10, "window": 3600}}`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    /// The most a single withdrawal can be for, if limited.
+    pub max_withdrawal: Option<Amount>,
+    /// The most a client can withdraw in a day, if limited.
+    pub daily_withdrawal: Option<Amount>,
+    /// The length of a day in the units of the timestamps (a day of Unix seconds
+    /// by default), which must be positive.
+    pub day: u64,
+    /// The most deposits, withdrawals, and transfers a client can make within a
+    /// window of time, if limited.
+    pub velocity: Option<Velocity>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_withdrawal: None,
+            daily_withdrawal: None,
+            day: 86_400,
+            velocity: None,
+        }
+    }
+}
+
+impl Limits {
+    /// Returns the first rule a client's transaction for a (positive) amount would
+    /// break, given the client's usage so far.
+    pub(crate) fn check(&self, tx: &Tx, amount: Amount, usage: Option<&Usage>) -> Result<(), LimitRule> {
+        if !counted(tx.tx_type) {
+            return Ok(());
+        }
+        if withdraws(tx.tx_type) {
+            if self.max_withdrawal.is_some_and(|max| amount > max) {
+                return Err(LimitRule::MaxWithdrawal);
+            }
+            if let (Some(max), Some(now)) = (self.daily_withdrawal, tx.timestamp) {
+                let withdrawn = usage.map_or(Amount::ZERO, |u| u.withdrawn_on(self.day_of(now)));
+                if withdrawn.checked_add(amount).is_none_or(|total| total > max) {
+                    return Err(LimitRule::DailyWithdrawal);
+                }
+            }
+        }
+        if let (Some(velocity), Some(now)) = (self.velocity, tx.timestamp) {
+            let recent = usage.map_or(0, |u| u.recent.iter().rev().take_while(|then| velocity.within(**then, now)).count());
+            if recent >= velocity.count as usize {
+                return Err(LimitRule::Velocity);
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds an accepted transaction (that passed [`Limits::check`]) to its
+    /// client's usage, if it counts towards any of the limits.
+    pub(crate) fn record(&self, tx: &Tx, amount: Amount, usage: &mut BTreeMap<u16, Usage>) {
+        let Some(now) = tx.timestamp else {
+            return;
+        };
+        let daily = self.daily_withdrawal.is_some() && withdraws(tx.tx_type);
+        if !counted(tx.tx_type) || (!daily && self.velocity.is_none()) {
+            return;
+        }
+
+        let usage = usage.entry(tx.client_id).or_default();
+        if daily {
+            let today = self.day_of(now);
+            let withdrawn = usage.withdrawn_on(today).checked_add(amount).expect("checked against the daily limit");
+            usage.withdrawn = Some((today, withdrawn));
+        }
+        if let Some(velocity) = self.velocity {
+            // the client's clock never goes backwards, so the oldest are at the front
+            while usage.recent.front().is_some_and(|then| !velocity.within(*then, now)) {
+                usage.recent.pop_front();
+            }
+            usage.recent.push_back(now);
+        }
+    }
+
+    fn day_of(&self, timestamp: u64) -> u64 {
+        timestamp.checked_div(self.day).unwrap_or(0)
+    }
+}
+
+/// Returns whether a transaction type counts towards the limits.
+fn counted(tx_type: TxType) -> bool {
+    matches!(tx_type, TxType::Deposit | TxType::Withdrawal | TxType::Transfer)
+}
+
+/// Returns whether a transaction type counts as a withdrawal.
+fn withdraws(tx_type: TxType) -> bool {
+    matches!(tx_type, TxType::Withdrawal | TxType::Transfer)
+}
+
+/// How much of the [`Limits`] a client has used up.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Usage {
+    /// The day of the client's latest timestamped withdrawal, and how much they
+    /// withdrew that day.
+    pub withdrawn: Option<(u64, Amount)>,
+    /// When the client's recent transactions were made (those that may still be
+    /// within the velocity window), oldest first.
+    pub recent: VecDeque<u64>,
+}
+
+impl Usage {
+    /// Returns how much the client withdrew on a day.
+    fn withdrawn_on(&self, day: u64) -> Amount {
+        match self.withdrawn {
+            Some((d, withdrawn)) if d == day => withdrawn,
+            _ => Amount::ZERO,
+        }
+    }
+}
+
+//------------------------------------------------------------------------------
+//------------------------------------------------------------------------------
+// TESTS
+//------------------------------------------------------------------------------
+//------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn amt(s: &str) -> Amount {
+        s.parse().unwrap()
+    }
+
+    fn tx(tx_type: TxType, amount: &str, timestamp: Option<u64>) -> Tx {
+        Tx{ tx_type, client_id: 1, tx_id: 1, amount: Some(amt(amount)), dest: None, reason: None, timestamp }
+    }
+
+    /// Records the transaction if it passes the limits.
+    fn submit(limits: &Limits, usage: &mut BTreeMap<u16, Usage>, tx: Tx) -> Result<(), LimitRule> {
+        let amount = tx.amount.unwrap();
+        limits.check(&tx, amount, usage.get(&tx.client_id))?;
+        limits.record(&tx, amount, usage);
+        Ok(())
+    }
+
+    #[test]
+    fn withdrawals() {
+        let limits = Limits{ max_withdrawal: Some(amt("5.0")), daily_withdrawal: Some(amt("8.0")), day: 100, ..Default::default() };
+        let mut usage = BTreeMap::new();
+
+        assert_eq!(Err(LimitRule::MaxWithdrawal), submit(&limits, &mut usage, tx(TxType::Withdrawal, "5.5", None)));
+        assert_eq!(Ok(()), submit(&limits, &mut usage, tx(TxType::Deposit, "50.0", Some(10))));
+        assert_eq!(Ok(()), submit(&limits, &mut usage, tx(TxType::Withdrawal, "5.0", Some(10))));
+        assert_eq!(Ok(()), submit(&limits, &mut usage, tx(TxType::Transfer, "3.0", Some(20))));
+        assert_eq!(Err(LimitRule::DailyWithdrawal), submit(&limits, &mut usage, tx(TxType::Withdrawal, "0.0001", Some(99))));
+        // a transaction without a timestamp isn't held to the daily limit
+        assert_eq!(Ok(()), submit(&limits, &mut usage, tx(TxType::Withdrawal, "1.0", None)));
+        // and the next day starts over
+        assert_eq!(Ok(()), submit(&limits, &mut usage, tx(TxType::Withdrawal, "5.0", Some(100))));
+        assert_eq!(Some((1, amt("5.0"))), usage[&1].withdrawn);
+    }
+
+    #[test]
+    fn velocity() {
+        let limits = Limits{ velocity: Some(Velocity{ count: 2, window: 60 }), ..Default::default() };
+        let mut usage = BTreeMap::new();
+
+        assert_eq!(Ok(()), submit(&limits, &mut usage, tx(TxType::Deposit, "1.0", Some(0))));
+        assert_eq!(Ok(()), submit(&limits, &mut usage, tx(TxType::Withdrawal, "1.0", Some(30))));
+        assert_eq!(Err(LimitRule::Velocity), submit(&limits, &mut usage, tx(TxType::Deposit, "1.0", Some(59))));
+        // fees and interest aren't made by the client
+        assert_eq!(Ok(()), submit(&limits, &mut usage, tx(TxType::Fee, "1.0", Some(59))));
+        assert_eq!(Ok(()), submit(&limits, &mut usage, tx(TxType::Deposit, "1.0", Some(60))));
+        assert_eq!(Err(LimitRule::Velocity), submit(&limits, &mut usage, tx(TxType::Transfer, "1.0", Some(89))));
+        assert_eq!(VecDeque::from([30, 60]), usage[&1].recent);
+    }
+}
@@ -1,34 +1,344 @@
 use std::error::Error;
-use std::fs::File;
-use std::io::stdout;
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, stdout, BufReader, BufWriter, Read, Stdout, Write};
+use std::path::Path;
+use std::process::ExitCode;
 
-mod account;
-mod engine;
-mod input;
-mod output;
-mod transaction;
+use toy_payments_engine::journal::JsonLines;
+use toy_payments_engine::statement::Statement;
+use toy_payments_engine::stats::Stats;
+use toy_payments_engine::output::Rejection;
+use toy_payments_engine::{input, output, AsOf, Engine, Format, ShardedEngine, Wal};
+
+use cli::{Command, EngineArgs, ProcessArgs, ReplayArgs, StatementArgs, StatsArgs, ValidateArgs};
+
+mod cli;
 
 // NOTE: The `csv` crate related code is mostly taken from its documentation.
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let mut engine = engine::Engine::default();
+fn main() -> ExitCode {
+    let command = match Command::parse() {
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!("error: {e}");
+            return ExitCode::from(2);
+        }
+    };
+
+    let result = match command {
+        Command::Process(args) => process(args),
+        Command::Validate(args) => validate(args),
+        Command::Stats(args) => stats(args),
+        Command::Statement(args) => statement(args),
+        Command::Replay(args) => replay(args),
+        Command::Help(usage) => {
+            println!("{usage}");
+            Ok(ExitCode::SUCCESS)
+        }
+    };
+    result.unwrap_or_else(|e| {
+        eprintln!("error: {e}");
+        ExitCode::FAILURE
+    })
+}
+
+fn process(args: ProcessArgs) -> Result<ExitCode, Box<dyn Error>> {
+    let mut engine = engine(&args.engine)?;
 
-    let file_path = input::get_first_arg()?;
-    let file = File::open(file_path)?;
-    let mut reader = input::reader(file);
+    let inputs = open_inputs(&args.inputs, args.input_format)?;
+    let mut rejected = match args.rejected {
+        Some(path) => {
+            let mut writer = create(path, args.output_format)?;
+            writer.header(&Rejection::HEADER)?;
+            Some(writer)
+        }
+        None => None,
+    };
 
-    for result in reader.deserialize() {
-        let tx: transaction::Tx = result?;
-        _ = engine.process_tx(tx); // purposefully ignoring errors for now
+    if let Some(path) = args.snapshot_in {
+        engine.load_snapshot(BufReader::new(File::open(path)?))?;
+    }
+    // checked from after the snapshot, since the checker indexes the recorded transactions
+    engine.set_strict(args.strict);
+    // kept before recovering so that the history covers the whole log
+    if args.as_of.is_some() {
+        engine.keep_history();
+    }
+    // subscribed before recovering so that the journal covers the whole log
+    if let Some(path) = args.journal {
+        engine.subscribe(JsonLines::new(BufWriter::new(File::create(path)?)));
     }
 
-    let mut writer = output::writer(stdout());
-    writer.write_record(&["client", "available", "held", "total", "locked"])?;
+    // resume from the write-ahead log if there is one, skipping the rows it already has
+    if let Some(path) = args.wal {
+        let wal = if Path::new(&path).exists() {
+            Wal::recover(&path, &mut engine)?
+        } else {
+            Wal::create(&path)?
+        };
+        engine.set_wal(wal);
+    }
+    let mut skip = engine.processed();
 
-    for account in engine.acct_map.iter().map(|(k, v)| (*k, v.available, v.held, v.total, v.locked)) {
-        writer.serialize(account)?;
+    if let Some(threads) = args.threads {
+        engine = process_sharded(engine, threads, inputs, rejected.as_mut())?;
+    } else {
+        for (name, format, data) in inputs {
+            input::process_rows(&mut engine, &name, input::Rows::with_format(format, data)?, rejected.as_mut(), &mut skip)?;
+        }
+        engine.flush_journal()?;
+    }
+
+    // the books must balance before anything is written out
+    engine.trial_balance()?;
+    if let Some(path) = args.snapshot_out {
+        save_snapshot(&engine, path)?;
+        // the snapshot covers the log now, so a run starting from it only needs
+        // the log to cover its own transactions
+        engine.truncate_wal()?;
+    }
+    if let Some(path) = args.audit {
+        output::write_audit(&engine, &mut create(path, args.output_format)?)?;
+    }
+    if let Some(path) = args.transactions {
+        output::write_transactions(&engine, &mut create(path, args.output_format)?)?;
+    }
+    write_accounts(&engine, args.as_of, args.output_format)?;
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Processes the inputs across several threads, returning the resulting state.
+///
+/// Rows are only reported once every shard has finished, so the report is built
+/// up in memory and sorted back into input order. The transaction columns of rows
+/// the engine rejected are written from the parsed transaction rather than the
+/// raw input.
+fn process_sharded<W>(engine: Engine, threads: usize, inputs: Vec<Input>, rejected: Option<&mut output::RecordWriter<W>>) -> Result<Engine, Box<dyn Error>>
+    where W: Write
+{
+    let mut sharded = ShardedEngine::with_state(engine, threads);
+    let mut names = Vec::with_capacity(inputs.len());
+    let mut report = Vec::new();
+
+    for (i, (name, format, data)) in inputs.into_iter().enumerate() {
+        let mut rows = input::Rows::with_format(format, data)?;
+        while let Some(row) = rows.next_row()? {
+            match row.tx() {
+                Ok(tx) => sharded.submit((i, row.line()), tx),
+                Err(e) if rejected.is_some() => {
+                    let fields = ["type", "client", "tx", "amount", "dest"].map(|f| row.field(f).to_owned());
+                    report.push(((i, row.line()), fields, input::MALFORMED_ROW, e.to_string()));
+                }
+                Err(_) => {}
+            }
+        }
+        names.push(name);
+    }
+
+    let (engine, rejections) = sharded.finish();
+
+    if let Some(writer) = rejected {
+        for r in rejections {
+            let tx = r.tx;
+            let fields = [
+                tx.tx_type.to_string(),
+                tx.client_id.to_string(),
+                tx.tx_id.to_string(),
+                tx.amount.map_or_else(String::new, |a| a.to_string()),
+                tx.dest.map_or_else(String::new, |d| d.to_string()),
+            ];
+            report.push((r.key, fields, r.error.code(), r.error.to_string()));
+        }
+        report.sort_by_key(|(key, ..)| *key);
+
+        for ((i, row), [tx_type, client, tx, amount, dest], code, message) in report {
+            writer.serialize(Rejection { file: &names[i], row, tx_type: &tx_type, client: &client, tx: &tx, amount: &amount, dest: &dest, code, message })?;
+        }
         writer.flush()?;
     }
+    Ok(engine)
+}
+
+fn validate(args: ValidateArgs) -> Result<ExitCode, Box<dyn Error>> {
+    let mut report = output::writer(Format::Csv, stdout());
+    report.header(&["file", "row", "message"])?;
+
+    let mut malformed = 0;
+    for (name, format, data) in open_inputs(&args.inputs, args.input_format)? {
+        let mut rows = input::Rows::with_format(format, data)?;
+        while let Some(row) = rows.next_row()? {
+            if let Err(e) = row.tx() {
+                malformed += 1;
+                report.serialize((&name, row.line(), e.to_string()))?;
+            }
+        }
+    }
+    report.flush()?;
+
+    if malformed > 0 {
+        eprintln!("{malformed} malformed row(s)");
+        Ok(ExitCode::FAILURE)
+    } else {
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+fn stats(args: StatsArgs) -> Result<ExitCode, Box<dyn Error>> {
+    let mut engine = engine(&args.engine)?;
+    let mut stats = Stats::default();
+
+    for (_, format, data) in open_inputs(&args.inputs, args.input_format)? {
+        let mut rows = input::Rows::with_format(format, data)?;
+        while let Some(row) = rows.next_row()? {
+            match row.tx() {
+                Ok(tx) => {
+                    let (tx_type, client_id) = (tx.tx_type, tx.client_id);
+                    stats.record(tx_type, client_id, &engine.process_tx(tx));
+                }
+                Err(_) => stats.record_malformed(),
+            }
+        }
+    }
+
+    let mut out = stdout().lock();
+    stats.write_report(&mut out)?;
+    writeln!(out, "\n{:<12} {:>16}", "ledger", "balance")?;
+    for (ledger_acct, balance) in engine.ledger().outside() {
+        writeln!(out, "{:<12} {:>16}", ledger_acct.to_string(), balance.to_string())?;
+    }
+    match engine.trial_balance() {
+        Ok(()) => writeln!(out, "{:<12} {:>16}", "trial", "balanced")?,
+        Err(e) => writeln!(out, "{:<12} {:>16}", "trial", e)?,
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn statement(args: StatementArgs) -> Result<ExitCode, Box<dyn Error>> {
+    let mut engine = engine(&args.engine)?;
+    let inputs = open_inputs(&args.inputs, args.input_format)?;
+
+    if let Some(path) = args.snapshot_in {
+        engine.load_snapshot(BufReader::new(File::open(path)?))?;
+    }
+    let opening = engine.acct_map.get(&args.client_id).cloned().unwrap_or_default();
+    if args.text {
+        engine.subscribe(Statement::text(args.client_id, &opening, BufWriter::new(stdout()))?);
+    } else {
+        let writer = output::writer(args.output_format.unwrap_or_default(), stdout());
+        engine.subscribe(Statement::records(args.client_id, &opening, writer)?);
+    }
+
+    for (name, format, data) in inputs {
+        input::process_rows(&mut engine, &name, input::Rows::with_format(format, data)?, None::<&mut output::RecordWriter<Stdout>>, &mut 0)?;
+    }
+    engine.flush_journal()?;
+    Ok(ExitCode::SUCCESS)
+}
+
+fn replay(args: ReplayArgs) -> Result<ExitCode, Box<dyn Error>> {
+    let mut engine = engine(&args.engine)?;
+
+    if let Some(path) = args.snapshot_in {
+        engine.load_snapshot(BufReader::new(File::open(path)?))?;
+    }
+    engine.set_strict(args.strict);
+    if args.as_of.is_some() {
+        engine.keep_history();
+    }
+    if let Some(path) = args.journal {
+        engine.subscribe(JsonLines::new(BufWriter::new(File::create(path)?)));
+    }
+    if let Some(path) = args.wal {
+        Wal::replay(path, &mut engine)?;
+    }
+    engine.flush_journal()?;
+
+    // the books must balance before anything is written out
+    engine.trial_balance()?;
+    if let Some(path) = args.snapshot_out {
+        save_snapshot(&engine, path)?;
+    }
+    if let Some(path) = args.audit {
+        output::write_audit(&engine, &mut create(path, args.output_format)?)?;
+    }
+    if let Some(path) = args.transactions {
+        output::write_transactions(&engine, &mut create(path, args.output_format)?)?;
+    }
+    write_accounts(&engine, args.as_of, args.output_format)?;
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Writes the accounts to stdout, as of a point in time if given.
+fn write_accounts(engine: &Engine, as_of: Option<AsOf>, format: Option<Format>) -> Result<(), Box<dyn Error>> {
+    let mut writer = output::writer(format.unwrap_or_default(), stdout());
+    match as_of {
+        Some(as_of) => output::write_balances(engine.balances_at(as_of), &mut writer),
+        None => output::write_accounts(engine, &mut writer),
+    }
+}
+
+/// Creates an engine configured by the command line options.
+fn engine(args: &EngineArgs) -> Result<Engine, Box<dyn Error>> {
+    let mut engine = Engine::new();
+    if let Some(client_id) = args.house_account {
+        engine.set_house_account(client_id);
+    }
+    if let Some(path) = &args.client_config {
+        let limits = File::open(path).map_err(Box::<dyn Error>::from)
+            .and_then(|file| input::read_credit_limits(BufReader::new(file)))
+            .map_err(|e| format!("unable to read {}: {e}", path.to_string_lossy()))?;
+        for (client_id, limit) in limits {
+            engine.set_credit_limit(client_id, limit);
+        }
+    }
+    if let Some(path) = &args.limits {
+        let limits = File::open(path).map_err(Box::<dyn Error>::from)
+            .and_then(|file| input::read_limits(BufReader::new(file)))
+            .map_err(|e| format!("unable to read {}: {e}", path.to_string_lossy()))?;
+        engine.set_limits(limits);
+    }
+    engine.set_fee_overdraft(args.allow_fee_overdraft);
+    engine.set_dispute_policy(args.dispute_policy);
+    engine.set_lock_policy(args.lock_policy);
+    Ok(engine)
+}
+
+/// An opened input, the name it's reported by, and the format it's read in.
+type Input = (String, Format, Box<dyn Read>);
+
+/// Opens every input up front so that a missing file is reported before any
+/// processing happens.
+///
+/// Inputs are read in the given format, or by their extension if there isn't one.
+fn open_inputs(paths: &[OsString], format: Option<Format>) -> Result<Vec<Input>, Box<dyn Error>> {
+    paths.iter()
+        .map(|path| {
+            let name = path.to_string_lossy().into_owned();
+            let format = format.unwrap_or_else(|| Format::from_path(path));
+            match input::open(path) {
+                Ok(data) => Ok((name, format, data)),
+                Err(e) => Err(format!("unable to open {name}: {e}").into()),
+            }
+        })
+        .collect()
+}
+
+/// Creates an output file, written in the given format or by its extension if
+/// there isn't one.
+fn create(path: OsString, format: Option<Format>) -> io::Result<output::RecordWriter<File>> {
+    let format = format.unwrap_or_else(|| Format::from_path(&path));
+    Ok(output::writer(format, File::create(path)?))
+}
 
+/// Writes the snapshot next to its final path and moves it into place so that a
+/// crash never leaves a partial snapshot behind.
+fn save_snapshot(engine: &Engine, path: OsString) -> Result<(), Box<dyn Error>> {
+    let mut tmp = path.clone();
+    tmp.push(".tmp");
+    let mut out = BufWriter::new(File::create(&tmp)?);
+    engine.save_snapshot(&mut out)?;
+    out.into_inner()?.sync_all()?;
+    fs::rename(tmp, path)?;
     Ok(())
 }
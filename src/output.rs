@@ -1,8 +1,286 @@
-use std::io::Write;
-use csv::Writer;
+//! Contains the helpers for writing results out.
 
-pub fn writer<W>(out: W) -> Writer<W>
+use std::error::Error;
+use std::io::{self, BufWriter, Write};
+use csv::{Writer, WriterBuilder};
+use serde::Serialize;
+
+use crate::account::{Acct, LockReason};
+use crate::amount::Amount;
+use crate::engine::{Engine, TxState};
+use crate::format::Format;
+use crate::input::Row;
+use crate::store::{AccountStore, TxStore};
+use crate::transaction::TxType;
+
+/// Writes records out in one of the [`Format`]s.
+///
+/// Records are structs, whose field names are the keys of a JSON Lines record.
+/// CSV records only hold the values, under the header row written by
+/// [`RecordWriter::header`].
+pub enum RecordWriter<W>
+    where W: Write
+{
+    Csv(Box<Writer<W>>),
+    JsonLines(BufWriter<W>),
+}
+
+impl<W> RecordWriter<W>
+    where W: Write
+{
+    /// Writes the header row of a CSV output (and nothing for JSON Lines).
+    pub fn header(&mut self, names: &[&str]) -> csv::Result<()> {
+        match self {
+            RecordWriter::Csv(writer) => writer.write_record(names),
+            RecordWriter::JsonLines(_) => Ok(()),
+        }
+    }
+
+    pub fn serialize<S>(&mut self, record: S) -> Result<(), Box<dyn Error>>
+        where S: Serialize
+    {
+        match self {
+            RecordWriter::Csv(writer) => writer.serialize(record)?,
+            RecordWriter::JsonLines(out) => {
+                serde_json::to_writer(&mut *out, &record)?;
+                out.write_all(b"\n")?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        match self {
+            RecordWriter::Csv(writer) => writer.flush(),
+            RecordWriter::JsonLines(out) => out.flush(),
+        }
+    }
+
+    /// Flushes the writer and returns the underlying output.
+    pub fn into_inner(self) -> io::Result<W> {
+        match self {
+            RecordWriter::Csv(writer) => (*writer).into_inner().map_err(|e| io::Error::new(e.error().kind(), e.error().to_string())),
+            RecordWriter::JsonLines(out) => out.into_inner().map_err(|e| e.into_error()),
+        }
+    }
+}
+
+/// Builds a writer for the format.
+pub fn writer<W>(format: Format, out: W) -> RecordWriter<W>
     where W: Write
 {
-    Writer::from_writer(out)
+    match format {
+        Format::Csv => RecordWriter::Csv(Box::new(WriterBuilder::new().has_headers(false).from_writer(out))),
+        Format::JsonLines => RecordWriter::JsonLines(BufWriter::new(out)),
+    }
+}
+
+/// A row of the accounts output.
+#[derive(Serialize)]
+struct AccountRow {
+    client: u16,
+    available: Amount,
+    held: Amount,
+    total: Amount,
+    locked: bool,
+    credit_used: Amount,
+}
+
+/// Writes the state of every account in the engine, ordered by client ID.
+pub fn write_accounts<T, A, W>(engine: &Engine<T, A>, writer: &mut RecordWriter<W>) -> Result<(), Box<dyn Error>>
+    where T: TxStore, A: AccountStore, W: Write
+{
+    write_balances(engine.acct_map.iter(), writer)
+}
+
+/// Writes the state of the accounts in the order given (e.g., as of a point in
+/// time, see [`Engine::balances_at`]).
+pub fn write_balances<I, W>(accts: I, writer: &mut RecordWriter<W>) -> Result<(), Box<dyn Error>>
+    where I: IntoIterator<Item = (u16, Acct)>, W: Write
+{
+    writer.header(&["client", "available", "held", "total", "locked", "credit_used"])?;
+
+    for (client, a) in accts {
+        writer.serialize(AccountRow {
+            client,
+            available: a.available,
+            held: a.held,
+            total: a.total,
+            locked: a.locked(),
+            credit_used: a.credit_used(),
+        })?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// A row of the audit log output.
+#[derive(Serialize)]
+struct AuditRow<'a> {
+    client: u16,
+    tx: u32,
+    action: TxType,
+    reason: &'a str,
+    from: Option<LockReason>,
+    to: Option<LockReason>,
+}
+
+/// Writes every accepted administrative transaction, in the order the engine
+/// logged them.
+pub fn write_audit<T, A, W>(engine: &Engine<T, A>, writer: &mut RecordWriter<W>) -> Result<(), Box<dyn Error>>
+    where T: TxStore, A: AccountStore, W: Write
+{
+    writer.header(&["client", "tx", "action", "reason", "from", "to"])?;
+
+    for e in engine.audit_log() {
+        writer.serialize(AuditRow { client: e.client_id, tx: e.tx_id, action: e.action, reason: &e.reason, from: e.from, to: e.to })?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// A row of the transactions output.
+#[derive(Serialize)]
+struct TxRow {
+    tx: u32,
+    client: u16,
+    #[serde(rename = "type")]
+    tx_type: TxType,
+    amount: Amount,
+    state: TxState,
+    disputed: Amount,
+    charged_back: Amount,
+    disputes: u32,
+    dest: Option<u16>,
+}
+
+/// Writes every recorded transaction (with the amounts from the client's side)
+/// and its dispute state, ordered by transaction ID.
+pub fn write_transactions<T, A, W>(engine: &Engine<T, A>, writer: &mut RecordWriter<W>) -> Result<(), Box<dyn Error>>
+    where T: TxStore, A: AccountStore, W: Write
+{
+    writer.header(&["tx", "client", "type", "amount", "state", "disputed", "charged_back", "disputes", "dest"])?;
+
+    for (tx, t) in engine.tx_map.iter() {
+        writer.serialize(TxRow {
+            tx,
+            client: t.client_id,
+            tx_type: t.kind,
+            amount: t.amount,
+            state: t.state(),
+            disputed: t.disputed,
+            charged_back: t.charged_back,
+            disputes: t.disputes,
+            dest: t.dest,
+        })?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// A row in the rejected transactions report.
+///
+/// The transaction columns are copied verbatim from the input so that the row can
+/// be matched against the source system even when it couldn't be parsed.
+#[derive(Debug, Serialize)]
+pub struct Rejection<'a> {
+    /// The input the row came from.
+    pub file: &'a str,
+    /// The line number of the row in the input.
+    pub row: u64,
+    #[serde(rename = "type")]
+    pub tx_type: &'a str,
+    pub client: &'a str,
+    pub tx: &'a str,
+    pub amount: &'a str,
+    pub dest: &'a str,
+    /// The stable code of the error (see [`crate::error::ProcessError::code`]).
+    pub code: &'a str,
+    pub message: String,
+}
+
+impl<'a> Rejection<'a> {
+    /// The header row of the report.
+    pub const HEADER: [&'static str; 9] = ["file", "row", "type", "client", "tx", "amount", "dest", "code", "message"];
+
+    /// Builds the report row for a rejected input row.
+    pub fn new(file: &'a str, row: &Row<'a>, code: &'a str, message: String) -> Self {
+        Rejection {
+            file,
+            row: row.line(),
+            tx_type: row.field("type"),
+            client: row.field("client"),
+            tx: row.field("tx"),
+            amount: row.field("amount"),
+            dest: row.field("dest"),
+            code,
+            message,
+        }
+    }
+}
+
+//------------------------------------------------------------------------------
+//------------------------------------------------------------------------------
+// TESTS
+//------------------------------------------------------------------------------
+//------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::engine::RecTx;
+
+    #[test]
+    fn accounts() {
+        let mut engine = Engine::new();
+        engine.acct_map.insert(1, Acct{ available: Amount::from_raw(15_000), total: Amount::from_raw(15_000), lock: None, ..Default::default() });
+        engine.acct_map.insert(2, Acct{ available: Amount::from_raw(-2_500), total: Amount::from_raw(-2_500), lock: Some(LockReason::Frozen), ..Default::default() });
+
+        let mut csv = writer(Format::Csv, Vec::new());
+        write_accounts(&engine, &mut csv).unwrap();
+        assert_eq!(
+            "client,available,held,total,locked,credit_used\n1,1.5000,0.0000,1.5000,false,0.0000\n2,-0.2500,0.0000,-0.2500,true,0.2500\n",
+            String::from_utf8(csv.into_inner().unwrap()).unwrap()
+        );
+
+        let mut json = writer(Format::JsonLines, Vec::new());
+        write_accounts(&engine, &mut json).unwrap();
+        assert_eq!(
+            concat!(
+                r#"{"client":1,"available":"1.5000","held":"0.0000","total":"1.5000","locked":false,"credit_used":"0.0000"}"#, "\n",
+                r#"{"client":2,"available":"-0.2500","held":"0.0000","total":"-0.2500","locked":true,"credit_used":"0.2500"}"#, "\n",
+            ),
+            String::from_utf8(json.into_inner().unwrap()).unwrap()
+        );
+
+        // nothing but the header when there are no accounts
+        let mut csv = writer(Format::Csv, Vec::new());
+        write_accounts(&Engine::new(), &mut csv).unwrap();
+        assert_eq!("client,available,held,total,locked,credit_used\n", String::from_utf8(csv.into_inner().unwrap()).unwrap());
+    }
+
+    #[test]
+    fn transactions() {
+        let mut engine = Engine::new();
+        let amount = Amount::from_raw(20_000);
+        let half = Amount::from_raw(10_000);
+        engine.tx_map.insert(2, RecTx{ client_id: 1, kind: TxType::Transfer, amount: amount.checked_neg().unwrap(), disputed: half.checked_neg().unwrap(), charged_back: Amount::ZERO, disputes: 1, dest: Some(3), timestamp: None, disputed_at: None });
+        engine.tx_map.insert(1, RecTx{ client_id: 1, kind: TxType::Deposit, amount, disputed: Amount::ZERO, charged_back: amount, disputes: 2, dest: None, timestamp: None, disputed_at: None });
+
+        let mut csv = writer(Format::Csv, Vec::new());
+        write_transactions(&engine, &mut csv).unwrap();
+        assert_eq!(
+            concat!(
+                "tx,client,type,amount,state,disputed,charged_back,disputes,dest\n",
+                "1,1,deposit,2.0000,chargebacked,0.0000,2.0000,2,\n",
+                "2,1,transfer,-2.0000,disputed,-1.0000,0.0000,1,3\n",
+            ),
+            String::from_utf8(csv.into_inner().unwrap()).unwrap()
+        );
+
+        let mut json = writer(Format::JsonLines, Vec::new());
+        write_transactions(&engine, &mut json).unwrap();
+        let out = String::from_utf8(json.into_inner().unwrap()).unwrap();
+        assert_eq!(Some(r#"{"tx":1,"client":1,"type":"deposit","amount":"2.0000","state":"chargebacked","disputed":"0.0000","charged_back":"2.0000","disputes":2,"dest":null}"#), out.lines().next());
+    }
 }
@@ -0,0 +1,148 @@
+//! Contains the policies that decide which transactions an
+//! [`Engine`](crate::Engine) allows.
+
+use crate::account::LockReason;
+use crate::transaction::TxType;
+
+/// Decides which disputes are allowed.
+///
+/// The default allows everything the engine has always allowed: a resolved
+/// transaction can be disputed again any number of times, withdrawals can be
+/// disputed (with a negative hold), a dispute can take the available funds below
+/// zero, and there are no time limits on disputing a transaction or on settling
+/// a dispute.
+///
+/// The time limits are in the units of the transactions' timestamps (e.g.,
+/// seconds for Unix timestamps), and only apply to transactions that have one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DisputePolicy {
+    /// Whether a transaction can be disputed again after being resolved.
+    pub allow_redispute: bool,
+    /// The most times a single transaction can be disputed, if limited.
+    pub max_disputes: Option<u32>,
+    /// Whether withdrawals can be disputed at all.
+    pub withdrawals_disputable: bool,
+    /// Whether a dispute can take an account's available funds below zero (or below
+    /// the client's credit limit, see [`Engine::set_credit_limit`](crate::Engine::set_credit_limit)).
+    pub allow_negative_available: bool,
+    /// The longest a transaction can be disputed after it was made, if limited.
+    pub dispute_window: Option<u64>,
+    /// The longest a dispute can be left open before it's settled automatically,
+    /// if limited.
+    pub resolution_deadline: Option<u64>,
+    /// How a dispute left open past the resolution deadline is settled.
+    pub on_deadline: DeadlineAction,
+}
+
+impl Default for DisputePolicy {
+    fn default() -> Self {
+        DisputePolicy {
+            allow_redispute: true,
+            max_disputes: None,
+            withdrawals_disputable: true,
+            allow_negative_available: true,
+            dispute_window: None,
+            resolution_deadline: None,
+            on_deadline: DeadlineAction::Resolve,
+        }
+    }
+}
+
+impl DisputePolicy {
+    /// Returns whether a transaction that has already been disputed `disputes`
+    /// times can be disputed again.
+    pub fn allows_dispute(&self, disputes: u32) -> bool {
+        if disputes > 0 && !self.allow_redispute {
+            return false;
+        }
+        self.max_disputes.is_none_or(|max| disputes < max)
+    }
+}
+
+/// How a dispute left open past the resolution deadline is settled (see
+/// [`DisputePolicy::resolution_deadline`]).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DeadlineAction {
+    /// The dispute is resolved, releasing the held funds back to the client.
+    #[default]
+    Resolve,
+    /// The dispute is charged back, like a chargeback would.
+    Chargeback,
+}
+
+impl DeadlineAction {
+    /// Returns the transaction the dispute is settled with.
+    pub fn tx_type(self) -> TxType {
+        match self {
+            DeadlineAction::Resolve => TxType::Resolve,
+            DeadlineAction::Chargeback => TxType::Chargeback,
+        }
+    }
+}
+
+/// Decides which transactions are still allowed on a locked account.
+///
+/// Administrative transactions (e.g., unfreezing an account) are always allowed,
+/// and by default nothing else is.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct LockPolicy {
+    /// A bit per transaction type (see [`TxType::to_byte`]) for each lock reason
+    allowed: [u16; 3],
+}
+
+impl LockPolicy {
+    /// Allows a transaction type on accounts locked for the reason.
+    pub fn allow(mut self, reason: LockReason, tx_type: TxType) -> Self {
+        self.allowed[reason as usize] |= 1 << tx_type.to_byte();
+        self
+    }
+
+    /// Returns whether a transaction type is allowed on an account locked for the reason.
+    pub fn allows(&self, reason: LockReason, tx_type: TxType) -> bool {
+        tx_type.is_admin() || self.allowed[reason as usize] & (1 << tx_type.to_byte()) != 0
+    }
+}
+
+//------------------------------------------------------------------------------
+//------------------------------------------------------------------------------
+// TESTS
+//------------------------------------------------------------------------------
+//------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn allows_dispute() {
+        let policy = DisputePolicy::default();
+        assert!(policy.allows_dispute(0));
+        assert!(policy.allows_dispute(100));
+
+        let policy = DisputePolicy{ allow_redispute: false, ..Default::default() };
+        assert!(policy.allows_dispute(0));
+        assert!(!policy.allows_dispute(1));
+
+        let policy = DisputePolicy{ max_disputes: Some(2), ..Default::default() };
+        assert!(policy.allows_dispute(1));
+        assert!(!policy.allows_dispute(2));
+
+        let policy = DisputePolicy{ max_disputes: Some(0), ..Default::default() };
+        assert!(!policy.allows_dispute(0));
+    }
+
+    #[test]
+    fn allows_when_locked() {
+        let policy = LockPolicy::default()
+            .allow(LockReason::Chargeback, TxType::Resolve)
+            .allow(LockReason::Frozen, TxType::Resolve)
+            .allow(LockReason::Frozen, TxType::Chargeback);
+
+        assert!(policy.allows(LockReason::Chargeback, TxType::Resolve));
+        assert!(!policy.allows(LockReason::Chargeback, TxType::Chargeback));
+        assert!(policy.allows(LockReason::Frozen, TxType::Chargeback));
+        assert!(!policy.allows(LockReason::Frozen, TxType::Deposit));
+        assert!(!policy.allows(LockReason::Closed, TxType::Resolve));
+        assert!(policy.allows(LockReason::Closed, TxType::Unfreeze));
+    }
+}
@@ -0,0 +1,652 @@
+//! Contains the [`ShardedEngine`] that processes transactions on several threads.
+//!
+//! Almost every transaction only touches the account and recorded transactions
+//! of a single client, so clients are partitioned across shards (by client ID)
+//! that each run their own [`Engine`] on a worker thread. Transactions are routed
+//! to their client's shard in the order they're submitted, so each client's
+//! transactions are still processed in order.
+//!
+//! Transfers (and the dispute-related transactions that reference them), fees,
+//! and interest touch two accounts. When those accounts are on different shards, both shards are paused
+//! once they've caught up and lend their engines back to the submitting thread,
+//! which processes the transaction itself before handing the engines back.
+//!
+//! Administrative transactions only touch their own client's account, but since
+//! each shard keeps its own audit log, the combined log is ordered by client
+//! rather than by when each entry was processed.
+//!
+//! Deposits, withdrawals, and chargebacks also move funds in and out of the
+//! ledger accounts outside of any client, whose balances each shard keeps its
+//! share of. The submitting thread keeps track of how far those balances could
+//! have moved since the shards were last synced, and before a transaction could
+//! take them out of range it syncs the shards and, if it still could, processes
+//! the transaction itself against the whole balances. That way a transaction is
+//! rejected for overflowing them exactly when the serial engine would reject it.
+//!
+//! Transaction IDs are unique across all clients though, so the IDs of recorded
+//! transactions are claimed up front when they're submitted. Before a claim is
+//! held against another transaction, the claiming transaction's shard is asked
+//! whether it was actually recorded (unless that's already known), and the claim
+//! is dropped if it was rejected, so its ID can be reused like with the serial
+//! engine. When a row breaks more than one rule though, the error reported can
+//! differ from the one the serial engine would report.
+
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap};
+use std::mem;
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender};
+use std::thread::{self, JoinHandle};
+
+use crate::amount::Amount;
+use crate::engine::{AcctMap, AuditEntry, Config, Engine, TxMap};
+use crate::ledger::{Ledger, LedgerAcct};
+use crate::limits::Usage;
+use crate::error::ProcessError;
+use crate::store::AccountStore;
+use crate::transaction::{Tx, TxType};
+
+/// The number of jobs sent to a shard at a time.
+const BATCH: usize = 1024;
+/// The number of batches that can be waiting on a shard before submitting blocks.
+const QUEUE: usize = 16;
+
+enum Job<K> {
+    /// Process a transaction, reporting it under the key if it's rejected.
+    Process(K, Tx),
+    /// Make sure a client has an account (for transactions rejected up front).
+    Touch(u16),
+    /// Send the engine over and wait for it to be sent back.
+    Lend(Sender<Engine>, Receiver<Engine>),
+}
+
+/// A transaction that was rejected, along with the key it was submitted with.
+#[derive(Debug, PartialEq)]
+pub struct Rejected<K> {
+    pub key: K,
+    pub tx: Tx,
+    pub error: ProcessError,
+}
+
+type Worker<K> = JoinHandle<(Engine, Vec<Rejected<K>>)>;
+
+/// The client that claimed a transaction ID.
+struct Claim {
+    client_id: u16,
+    /// The destination client of a transfer
+    dest: Option<u16>,
+    /// The amount the transaction moved from the client's side, in ten-thousandths
+    amount: i128,
+    /// Whether the transaction is known to have been recorded (rather than still
+    /// being up to its shard, which may reject it)
+    recorded: bool,
+}
+
+/// Processes transactions across several worker threads, partitioned by client.
+///
+/// Transactions are submitted with a key (e.g., the row they came from) that
+/// rejections are reported with, since they're only known once the shards finish.
+pub struct ShardedEngine<K> {
+    /// The client that owns each claimed transaction ID
+    owners: HashMap<u32, Claim>,
+    /// The jobs waiting to be sent to each shard
+    pending: Vec<Vec<Job<K>>>,
+    senders: Vec<SyncSender<Vec<Job<K>>>>,
+    workers: Vec<Worker<K>>,
+    /// The transactions rejected up front, before reaching a shard
+    rejected: Vec<Rejected<K>>,
+    /// The settings every shard's engine was created with
+    config: Config,
+    /// The audit log of the engine the shards started from
+    audit: Vec<AuditEntry>,
+    /// The balances of the ledger accounts outside of any client the last time
+    /// the shards were synced (each shard keeps its own clients' accounts, and
+    /// how it's changed the outside accounts since)
+    ledger: Ledger,
+    /// The least and the most each outside account could have changed by since
+    /// the shards were last synced, in ten-thousandths
+    drift: BTreeMap<LedgerAcct, (i128, i128)>,
+}
+
+impl<K> ShardedEngine<K>
+    where K: Ord + Send + 'static
+{
+    /// Creates an engine with the given number of shards (at least one).
+    pub fn new(shards: usize) -> Self {
+        Self::with_state(Engine::new(), shards)
+    }
+
+    /// Creates an engine with the given number of shards (at least one), starting
+    /// from the state of an existing engine.
+    ///
+    /// The existing engine can't have a write-ahead log, strict mode, a history, a
+    /// journal, or subscribers, since they all need every transaction in a single
+    /// order, which transactions spread across shards don't have. Its dispute
+    /// policy can't have a resolution deadline either, since a shard settling a
+    /// dispute on its own can't reach the other side of a transfer on another shard.
+    pub fn with_state(mut engine: Engine, shards: usize) -> Self {
+        assert!(shards > 0, "a sharded engine needs at least one shard");
+        if let Some(feature) = engine.serial_feature() {
+            panic!("a sharded engine can't keep {feature}");
+        }
+        assert!(
+            engine.config().dispute_policy.resolution_deadline.is_none(),
+            "a sharded engine can't settle disputes past a resolution deadline"
+        );
+        let config = engine.config().clone();
+        let audit = mem::take(engine.audit_mut());
+        let mut ledgers: Vec<Ledger> = (0..shards).map(|_| Ledger::default()).collect();
+        let mut ledger = Ledger::default();
+        for (ledger_acct, balance) in engine.ledger().iter() {
+            match ledger_acct.client() {
+                Some(client_id) => ledgers[shard_of(client_id, shards)].set(ledger_acct, balance),
+                None => ledger.set(ledger_acct, balance),
+            }
+        }
+
+        let mut owners = HashMap::new();
+        let mut txs: Vec<TxMap> = (0..shards).map(|_| TxMap::new()).collect();
+        let mut accts: Vec<AcctMap> = (0..shards).map(|_| AcctMap::new()).collect();
+        let mut clocks: Vec<BTreeMap<u16, u64>> = (0..shards).map(|_| BTreeMap::new()).collect();
+        for (client_id, timestamp) in mem::take(engine.clocks_mut()) {
+            clocks[shard_of(client_id, shards)].insert(client_id, timestamp);
+        }
+        let mut usage: Vec<BTreeMap<u16, Usage>> = (0..shards).map(|_| BTreeMap::new()).collect();
+        for (client_id, used) in mem::take(engine.usage_mut()) {
+            usage[shard_of(client_id, shards)].insert(client_id, used);
+        }
+        for (tx_id, tx) in engine.tx_map {
+            owners.insert(tx_id, Claim { client_id: tx.client_id, dest: tx.dest, amount: i128::from(tx.amount.raw()), recorded: true });
+            txs[shard_of(tx.client_id, shards)].insert(tx_id, tx);
+        }
+        for (client_id, acct) in engine.acct_map {
+            accts[shard_of(client_id, shards)].insert(client_id, acct);
+        }
+
+        let mut senders = Vec::with_capacity(shards);
+        let mut workers = Vec::with_capacity(shards);
+        for ((((txs, accts), ledger), clocks), usage) in txs.into_iter().zip(accts).zip(ledgers).zip(clocks).zip(usage) {
+            let (sender, receiver) = mpsc::sync_channel::<Vec<Job<K>>>(QUEUE);
+            let config = config.clone();
+            senders.push(sender);
+            workers.push(thread::spawn(move || {
+                let mut engine: Engine = Engine::from_parts(txs, accts, ledger);
+                engine.set_config(config);
+                *engine.clocks_mut() = clocks;
+                *engine.usage_mut() = usage;
+                let mut rejected = Vec::new();
+                for job in receiver.into_iter().flatten() {
+                    match job {
+                        Job::Process(key, tx) => {
+                            if let Err(error) = engine.process_tx(tx.clone()) {
+                                rejected.push(Rejected { key, tx, error });
+                            }
+                        }
+                        Job::Touch(client_id) => {
+                            engine.acct_map.update(client_id, |_| ());
+                        }
+                        Job::Lend(lend, back) => {
+                            // the submitting thread only stops waiting on a shard when
+                            // another one panics, which `finish` reports
+                            if lend.send(engine).is_err() {
+                                return (Engine::new(), rejected);
+                            }
+                            engine = back.recv().expect("submitting thread dropped a lent engine");
+                        }
+                    }
+                }
+                (engine, rejected)
+            }));
+        }
+
+        ShardedEngine {
+            owners,
+            pending: (0..shards).map(|_| Vec::with_capacity(BATCH)).collect(),
+            senders,
+            workers,
+            rejected: Vec::new(),
+            config,
+            audit,
+            ledger,
+            drift: BTreeMap::new(),
+        }
+    }
+
+    /// Submits a transaction to be processed by its client's shard.
+    pub fn submit(&mut self, key: K, tx: Tx) {
+        let (client_id, tx_id) = (tx.client_id, tx.tx_id);
+
+        // a claim that would be held against this transaction is only kept if its
+        // transaction was recorded
+        let collides = match self.owners.get(&tx_id) {
+            Some(claim) if !claim.recorded => tx.tx_type.is_recorded() || claim.client_id != client_id,
+            _ => false,
+        };
+        if collides && !tx.tx_type.is_admin() {
+            self.settle_claim(tx_id);
+        }
+
+        // claims (or checks) the ID, finding the other account the transaction touches
+        let claim = match tx.tx_type {
+            _ if tx.tx_type.is_admin() => Ok(None),
+            TxType::Dispute | TxType::Resolve | TxType::Chargeback => match self.owners.get(&tx_id) {
+                // no shard has it, so the client's shard reports it like the serial engine would
+                None => Ok(None),
+                Some(claim) if claim.client_id != client_id => Err(ProcessError::ClientMismatch { client_id, tx_id }),
+                Some(claim) => Ok(claim.dest),
+            },
+            _ => match self.owners.entry(tx_id) {
+                Entry::Occupied(_) => Err(ProcessError::DuplicateTxId { client_id, tx_id }),
+                Entry::Vacant(entry) => {
+                    let amount = i128::from(tx.amount.unwrap_or_default().raw());
+                    let (dest, amount) = match tx.tx_type {
+                        TxType::Transfer => (tx.dest, -amount),
+                        TxType::Withdrawal | TxType::Fee => (None, -amount),
+                        _ => (None, amount),
+                    };
+                    entry.insert(Claim { client_id, dest, amount, recorded: false });
+                    match tx.tx_type {
+                        TxType::Fee | TxType::Interest => Ok(self.config.house_account),
+                        _ => Ok(dest),
+                    }
+                }
+            },
+        };
+
+        let dest = match claim {
+            Ok(dest) => dest,
+            Err(error) => {
+                self.rejected.push(Rejected { key, tx, error });
+                self.queue(client_id, Job::Touch(client_id));
+                return;
+            }
+        };
+        if let Some((acct, least, most)) = self.reach(&tx) {
+            if !self.fits(acct, least, most) {
+                self.sync();
+                if !self.fits(acct, least, most) {
+                    self.process_lent(key, tx, dest, true);
+                    return;
+                }
+            }
+            let drift = self.drift.entry(acct).or_default();
+            *drift = (drift.0 + least, drift.1 + most);
+        }
+        match dest {
+            Some(dest) if self.shard(dest) != self.shard(client_id) => self.process_lent(key, tx, Some(dest), false),
+            _ => self.queue(client_id, Job::Process(key, tx)),
+        }
+    }
+
+    /// Waits for every shard to finish, returning the combined state of all of
+    /// the shards and every rejected transaction (ordered by key).
+    ///
+    /// The combined audit log is ordered by client, keeping each client's entries
+    /// in the order they were processed.
+    pub fn finish(mut self) -> (Engine, Vec<Rejected<K>>) {
+        for shard in 0..self.senders.len() {
+            self.flush(shard);
+        }
+        drop(self.senders);
+
+        let mut engine = Engine::new();
+        engine.set_config(self.config);
+        let mut rejected = self.rejected;
+        let mut audit = self.audit;
+        // the outside accounts were kept in range as transactions were submitted
+        engine.ledger_mut().merge(&self.ledger).expect("outside balances overflowed");
+        for worker in self.workers {
+            let (mut shard, mut shard_rejected) = worker.join().expect("shard panicked");
+            engine.tx_map.append(&mut shard.tx_map);
+            engine.acct_map.append(&mut shard.acct_map);
+            engine.clocks_mut().append(shard.clocks_mut());
+            engine.usage_mut().append(shard.usage_mut());
+            rejected.append(&mut shard_rejected);
+            audit.append(shard.audit_mut());
+            engine.ledger_mut().merge(shard.ledger()).expect("outside balances overflowed");
+        }
+        rejected.sort_by(|a, b| a.key.cmp(&b.key));
+        audit.sort_by_key(|entry| entry.client_id);
+        *engine.audit_mut() = audit;
+        (engine, rejected)
+    }
+
+    /// Returns the outside account a transaction could change the balance of, and
+    /// the least and the most it could change it by (in ten-thousandths).
+    fn reach(&self, tx: &Tx) -> Option<(LedgerAcct, i128, i128)> {
+        let amount = tx.amount.filter(|amount| amount.is_positive()).map(|amount| i128::from(amount.raw()));
+        match tx.tx_type {
+            TxType::Deposit => Some((LedgerAcct::External, -amount?, 0)),
+            TxType::Withdrawal => Some((LedgerAcct::External, 0, amount?)),
+            TxType::Chargeback => {
+                let claim = self.owners.get(&tx.tx_id)?;
+                match claim.dest {
+                    // both sides of a transfer are posted together, so they only
+                    // move the settlement account in the middle of the posting
+                    Some(_) => Some((LedgerAcct::Settlement, -claim.amount.abs(), claim.amount.abs())),
+                    None => Some((LedgerAcct::Settlement, claim.amount.min(0), claim.amount.max(0))),
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns whether an outside account's balance (and each shard's share of it)
+    /// stays in range however far it could have drifted, including a change of
+    /// between `least` and `most`.
+    fn fits(&self, acct: LedgerAcct, least: i128, most: i128) -> bool {
+        let (drifted_least, drifted_most) = self.drift.get(&acct).copied().unwrap_or_default();
+        let (least, most) = (drifted_least + least, drifted_most + most);
+        let balance = i128::from(self.ledger.balance(acct).raw());
+        let range = i128::from(i64::MIN)..=i128::from(i64::MAX);
+        [least, most, balance + least, balance + most].iter().all(|n| range.contains(n))
+    }
+
+    /// Waits for every shard to catch up, moving their shares of the outside
+    /// accounts' balances into `ledger`.
+    fn sync(&mut self) {
+        let mut lent: Vec<_> = (0..self.senders.len()).map(|shard| self.borrow(shard)).collect();
+        for (engine, _) in &mut lent {
+            let share: Ledger = engine.ledger().outside().collect();
+            // the outside accounts were kept in range as transactions were submitted
+            self.ledger.merge(&share).expect("outside balances overflowed");
+            for (acct, _) in share.iter() {
+                engine.ledger_mut().set(acct, Amount::ZERO);
+            }
+        }
+        self.drift.clear();
+        for (engine, back) in lent {
+            // a shard only stops waiting for its engine when it panics, which `finish` reports
+            _ = back.send(engine);
+        }
+    }
+
+    /// Processes a transaction on the submitting thread, with the engines lent by
+    /// the shard of its client and of its other account (if that's another one).
+    ///
+    /// If `whole`, the shards must have just been synced, and the client's shard
+    /// holds the whole balances of the outside accounts while it's processed.
+    fn process_lent(&mut self, key: K, tx: Tx, dest: Option<u16>, whole: bool) {
+        let src_shard = self.shard(tx.client_id);
+        let (mut src, src_back) = self.borrow(src_shard);
+        let mut dst = dest.map(|dest| self.shard(dest)).filter(|shard| *shard != src_shard).map(|shard| self.borrow(shard));
+        if whole {
+            *src.ledger_mut() = src.ledger().iter().chain(self.ledger.outside()).collect();
+        }
+
+        let result = match dst.as_mut() {
+            Some((dst, _)) => src.process_with(&tx, dst),
+            None => src.process_tx(tx.clone()),
+        };
+        // the shards are done with the transaction, so its claim is settled now
+        if tx.tx_type.is_recorded() {
+            self.settled(tx.tx_id, result.is_ok());
+        }
+        if let Err(error) = result {
+            self.rejected.push(Rejected { key, tx, error });
+        }
+
+        if whole {
+            let outside: Ledger = src.ledger().outside().collect();
+            let accts: Vec<_> = self.ledger.outside().chain(outside.iter()).map(|(acct, _)| acct).collect();
+            for acct in accts {
+                self.ledger.set(acct, outside.balance(acct));
+                src.ledger_mut().set(acct, Amount::ZERO);
+            }
+        }
+        // a shard only stops waiting for its engine when it panics, which `finish` reports
+        _ = src_back.send(src);
+        if let Some((dst, dst_back)) = dst {
+            _ = dst_back.send(dst);
+        }
+    }
+
+    /// Asks the shard of a claimed transaction whether it was recorded, keeping the
+    /// claim if it was and dropping it otherwise.
+    fn settle_claim(&mut self, tx_id: u32) {
+        let Some(client_id) = self.owners.get(&tx_id).map(|claim| claim.client_id) else {
+            return;
+        };
+        let (engine, back) = self.borrow(self.shard(client_id));
+        let recorded = engine.tx_map.contains_key(&tx_id);
+        // a shard only stops waiting for its engine when it panics, which `finish` reports
+        _ = back.send(engine);
+        self.settled(tx_id, recorded);
+    }
+
+    /// Keeps the claim on an ID if its transaction was recorded, and drops it
+    /// otherwise.
+    fn settled(&mut self, tx_id: u32, recorded: bool) {
+        if !recorded {
+            self.owners.remove(&tx_id);
+        } else if let Some(claim) = self.owners.get_mut(&tx_id) {
+            claim.recorded = true;
+        }
+    }
+
+    /// Waits for a shard to catch up and lend its engine, returning it along with
+    /// where to send it back to.
+    fn borrow(&mut self, shard: usize) -> (Engine, Sender<Engine>) {
+        let (lend, lent) = mpsc::channel();
+        let (back, returned) = mpsc::channel();
+        self.pending[shard].push(Job::Lend(lend, returned));
+        self.flush(shard);
+        (lent.recv().expect("shard panicked"), back)
+    }
+
+    fn shard(&self, client_id: u16) -> usize {
+        shard_of(client_id, self.senders.len())
+    }
+
+    fn queue(&mut self, client_id: u16, job: Job<K>) {
+        let shard = self.shard(client_id);
+        self.pending[shard].push(job);
+        if self.pending[shard].len() >= BATCH {
+            self.flush(shard);
+        }
+    }
+
+    fn flush(&mut self, shard: usize) {
+        let batch = mem::replace(&mut self.pending[shard], Vec::with_capacity(BATCH));
+        if !batch.is_empty() {
+            // a shard only stops receiving when it panics, which `finish` reports
+            _ = self.senders[shard].send(batch);
+        }
+    }
+}
+
+fn shard_of(client_id: u16, shards: usize) -> usize {
+    usize::from(client_id) % shards
+}
+
+//------------------------------------------------------------------------------
+//------------------------------------------------------------------------------
+// TESTS
+//------------------------------------------------------------------------------
+//------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::amount::Amount;
+    use crate::limits::Limits;
+
+    /// Generates a deterministic mix of transactions across many clients, where
+    /// every recorded transaction gets a fresh ID.
+    fn workload(count: u32) -> Vec<Tx> {
+        let mut seed: u64 = 42;
+        let mut next = move || {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (seed >> 33) as u32
+        };
+
+        let mut txs = Vec::new();
+        let mut recorded = Vec::new();
+        for tx_id in 1..=count {
+            let client_id = (next() % 50) as u16;
+            let amount = Some(Amount::from_raw(i64::from(next() % 100_000)));
+            let tx = match next() % 14 {
+                0..=3 => Tx{ tx_type: TxType::Deposit, client_id, tx_id, amount, dest: None, reason: None, timestamp: None },
+                4..=5 => Tx{ tx_type: TxType::Withdrawal, client_id, tx_id, amount, dest: None, reason: None, timestamp: None },
+                6..=7 => Tx{ tx_type: TxType::Transfer, client_id, tx_id, amount, dest: Some((next() % 50) as u16), reason: None, timestamp: None },
+                12 => Tx{ tx_type: TxType::Fee, client_id, tx_id, amount, dest: None, reason: None, timestamp: None },
+                13 => Tx{ tx_type: TxType::Interest, client_id, tx_id, amount, dest: None, reason: None, timestamp: None },
+                n if !recorded.is_empty() => {
+                    let (ref_id, ref_client) = recorded[next() as usize % recorded.len()];
+                    let tx_type = [TxType::Dispute, TxType::Resolve, TxType::Chargeback][n as usize % 3];
+                    Tx{ tx_type, client_id: ref_client, tx_id: ref_id, amount: None, dest: None, reason: None, timestamp: None }
+                }
+                _ => continue,
+            };
+            if tx.amount.is_some() {
+                recorded.push((tx_id, client_id));
+            }
+            txs.push(tx);
+
+            // now and then an account gets frozen or unfrozen
+            if next() % 100 == 0 {
+                let tx_type = [TxType::Freeze, TxType::Unfreeze][next() as usize % 2];
+                txs.push(Tx{ tx_type, client_id, tx_id, amount: None, dest: None, reason: Some("review".into()), timestamp: None });
+            }
+        }
+        txs
+    }
+
+    #[test]
+    fn matches_serial() {
+        let txs = workload(20_000);
+
+        let configured = || {
+            let mut engine = Engine::new();
+            engine.set_house_account(7);
+            engine.set_limits(Limits{ max_withdrawal: Some(Amount::from_raw(80_000)), ..Default::default() });
+            engine
+        };
+
+        let mut serial = configured();
+        serial.set_strict(true);
+        let mut serial_rejected = Vec::new();
+        for (row, tx) in txs.iter().enumerate() {
+            if let Err(e) = serial.process_tx(tx.clone()) {
+                assert!(!e.is_fatal(), "{e}");
+                serial_rejected.push((row, e));
+            }
+        }
+
+        for shards in [1, 3, 8] {
+            let mut sharded = ShardedEngine::with_state(configured(), shards);
+            for (row, tx) in txs.iter().enumerate() {
+                sharded.submit(row, tx.clone());
+            }
+            let (engine, rejected) = sharded.finish();
+
+            assert_eq!(serial.acct_map, engine.acct_map);
+            assert_eq!(serial.tx_map, engine.tx_map);
+            assert_eq!(serial.ledger(), engine.ledger());
+            assert_eq!(Ok(()), engine.trial_balance());
+            let mut audit = serial.audit_log().to_vec();
+            audit.sort_by_key(|entry| entry.client_id);
+            assert_eq!(audit, engine.audit_log());
+            let rejected: Vec<_> = rejected.into_iter().map(|r| (r.key, r.error)).collect();
+            assert_eq!(serial_rejected, rejected);
+        }
+    }
+
+    #[test]
+    fn global_tx_ids() {
+        let deposit = |client_id, tx_id| Tx{ tx_type: TxType::Deposit, client_id, tx_id, amount: Some(Amount::from_raw(10_000)), dest: None, reason: None, timestamp: None };
+
+        let mut sharded = ShardedEngine::new(2);
+        sharded.submit(1, deposit(1, 1));
+        sharded.submit(2, deposit(2, 1));
+        sharded.submit(3, Tx{ tx_type: TxType::Dispute, client_id: 2, tx_id: 1, amount: None, dest: None, reason: None, timestamp: None });
+        sharded.submit(4, Tx{ tx_type: TxType::Dispute, client_id: 3, tx_id: 7, amount: None, dest: None, reason: None, timestamp: None });
+        let (engine, rejected) = sharded.finish();
+
+        assert_eq!(1, engine.tx_map.len());
+        assert_eq!(3, engine.acct_map.len());
+        assert_eq!(vec![
+            (2, ProcessError::DuplicateTxId { client_id: 2, tx_id: 1 }),
+            (3, ProcessError::ClientMismatch { client_id: 2, tx_id: 1 }),
+            (4, ProcessError::UnknownTx { client_id: 3, tx_id: 7 }),
+        ], rejected.into_iter().map(|r| (r.key, r.error)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn rejected_tx_ids() {
+        let tx = |tx_type, client_id, tx_id, amount: Option<&str>, dest| Tx{ tx_type, client_id, tx_id, amount: amount.map(|a| a.parse().unwrap()), dest, reason: None, timestamp: None };
+        let txs = [
+            // rejected by its shard, then reused by a client on another shard
+            tx(TxType::Withdrawal, 1, 1, Some("1.0"), None),
+            tx(TxType::Deposit, 2, 1, Some("2.0"), None),
+            tx(TxType::Dispute, 2, 1, None, None),
+            // rejected while both shards are lent, then reused
+            tx(TxType::Deposit, 1, 2, Some("1.0"), None),
+            tx(TxType::Transfer, 1, 3, Some("5.0"), Some(2)),
+            tx(TxType::Deposit, 2, 3, Some("1.0"), None),
+            // a rejected ID is unknown rather than another client's
+            tx(TxType::Withdrawal, 3, 4, Some("1.0"), None),
+            tx(TxType::Dispute, 2, 4, None, None),
+            // and a recorded one is still claimed
+            tx(TxType::Deposit, 1, 2, Some("1.0"), None),
+        ];
+
+        let mut serial = Engine::new();
+        let serial_rejected: Vec<_> = txs.iter().enumerate().filter_map(|(row, tx)| Some((row, serial.process_tx(tx.clone()).err()?))).collect();
+        assert_eq!(vec![
+            (0, ProcessError::InsufficientFunds { client_id: 1, tx_id: 1 }),
+            (4, ProcessError::InsufficientFunds { client_id: 1, tx_id: 3 }),
+            (6, ProcessError::InsufficientFunds { client_id: 3, tx_id: 4 }),
+            (7, ProcessError::UnknownTx { client_id: 2, tx_id: 4 }),
+            (8, ProcessError::DuplicateTxId { client_id: 1, tx_id: 2 }),
+        ], serial_rejected);
+
+        let mut sharded = ShardedEngine::new(2);
+        for (row, tx) in txs.iter().enumerate() {
+            sharded.submit(row, tx.clone());
+        }
+        let (engine, rejected) = sharded.finish();
+        assert_eq!(serial.acct_map, engine.acct_map);
+        assert_eq!(serial.tx_map, engine.tx_map);
+        assert_eq!(serial_rejected, rejected.into_iter().map(|r| (r.key, r.error)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn outside_overflow() {
+        let tx = |tx_type, client_id, tx_id, amount: &str| Tx{ tx_type, client_id, tx_id, amount: Some(amount.parse().unwrap()), dest: None, reason: None, timestamp: None };
+        let txs = [
+            tx(TxType::Deposit, 1, 1, "500000000000000"),
+            tx(TxType::Deposit, 2, 2, "500000000000000"),
+            // leaves room in the external account for just under one more unit
+            tx(TxType::Deposit, 2, 3, "422337203685477"),
+            tx(TxType::Withdrawal, 1, 4, "1.0"),
+            tx(TxType::Deposit, 2, 5, "1.0"),
+            tx(TxType::Deposit, 2, 6, "1.0"),
+        ];
+
+        let mut serial = Engine::new();
+        let serial_rejected: Vec<_> = txs.iter().enumerate().filter_map(|(row, tx)| Some((row, serial.process_tx(tx.clone()).err()?))).collect();
+        assert_eq!(vec![
+            (1, ProcessError::Overflow { client_id: 2, tx_id: 2 }),
+            (5, ProcessError::Overflow { client_id: 2, tx_id: 6 }),
+        ], serial_rejected);
+
+        // the deposits are on different shards, so neither shard's share overflows
+        let mut sharded = ShardedEngine::new(2);
+        for (row, tx) in txs.iter().enumerate() {
+            sharded.submit(row, tx.clone());
+        }
+        let (engine, rejected) = sharded.finish();
+        assert_eq!(serial.acct_map, engine.acct_map);
+        assert_eq!(serial.ledger(), engine.ledger());
+        assert_eq!(Ok(()), engine.trial_balance());
+        assert_eq!(serial_rejected, rejected.into_iter().map(|r| (r.key, r.error)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    #[should_panic(expected = "a sharded engine can't keep strict mode")]
+    fn serial_features() {
+        let mut engine = Engine::new();
+        engine.set_strict(true);
+        ShardedEngine::<u64>::with_state(engine, 2);
+    }
+}
@@ -0,0 +1,405 @@
+//! Contains the snapshot format an [`Engine`]'s state can be saved to and
+//! restored from.
+//!
+//! A snapshot starts with a small header (a magic string and a format version),
+//! followed by every recorded transaction, then every account, then the audit
+//! log, then the balances of the ledger accounts outside of any client, then the
+//! latest timestamp of each client, then how much of the limits each client has
+//! used up, and ends with a CRC-32 of everything before it:
+//!
+//! ```text
+//! | magic | version (u16) | tx count (u32) | txs... | account count (u32) | accounts... | audit count (u32) | entries... | ledger count (u32) | balances... | clock count (u32) | clocks... | usage count (u32) | usage... | CRC-32 (u32) |
+//! ```
+//!
+//! All integers are little-endian and amounts are stored as ten-thousandths. An
+//! account's total isn't stored, since it's always the sum of its available and
+//! held funds.
+
+use std::io::{self, ErrorKind, Read, Write};
+
+use crate::account::{Acct, LockReason};
+use crate::amount::Amount;
+use crate::checksum::{crc32, Crc32};
+use crate::engine::{AuditEntry, Engine, RecTx};
+use crate::ledger::LedgerAcct;
+use crate::limits::Usage;
+use crate::store::{AccountStore, TxStore};
+use crate::transaction::TxType;
+
+const MAGIC: &[u8; 6] = b"TPSNAP";
+const VERSION: u16 = 9;
+
+impl<T, A> Engine<T, A>
+    where T: TxStore, A: AccountStore
+{
+    /// Writes every recorded transaction and account to a snapshot.
+    pub fn save_snapshot<W>(&self, out: W) -> io::Result<()>
+        where W: Write
+    {
+        let mut out = Encoder { out, crc: Crc32::new() };
+        out.write(MAGIC)?;
+        out.write(&VERSION.to_le_bytes())?;
+
+        out.write(&len(self.tx_map.len())?.to_le_bytes())?;
+        for (tx_id, tx) in self.tx_map.iter() {
+            out.write(&tx_id.to_le_bytes())?;
+            out.write(&tx.client_id.to_le_bytes())?;
+            out.write(&[tx.kind.to_byte()])?;
+            out.write(&tx.amount.raw().to_le_bytes())?;
+            out.write(&tx.disputed.raw().to_le_bytes())?;
+            out.write(&tx.charged_back.raw().to_le_bytes())?;
+            out.write(&tx.disputes.to_le_bytes())?;
+            match tx.dest {
+                Some(dest) => {
+                    out.write(&[1])?;
+                    out.write(&dest.to_le_bytes())?;
+                }
+                None => out.write(&[0])?,
+            }
+            out.time(tx.timestamp)?;
+            out.time(tx.disputed_at)?;
+        }
+
+        out.write(&len(self.acct_map.len())?.to_le_bytes())?;
+        for (client_id, acct) in self.acct_map.iter() {
+            out.write(&client_id.to_le_bytes())?;
+            out.write(&acct.available.raw().to_le_bytes())?;
+            out.write(&acct.held.raw().to_le_bytes())?;
+            out.write(&[lock_byte(acct.lock)])?;
+        }
+
+        out.write(&len(self.audit_log().len())?.to_le_bytes())?;
+        for entry in self.audit_log() {
+            out.write(&entry.client_id.to_le_bytes())?;
+            out.write(&entry.tx_id.to_le_bytes())?;
+            out.write(&[entry.action.to_byte(), lock_byte(entry.from), lock_byte(entry.to)])?;
+            out.write(&len(entry.reason.len())?.to_le_bytes())?;
+            out.write(entry.reason.as_bytes())?;
+        }
+
+        // the balances of the clients' accounts in the ledger are their accounts', so
+        // only the outside accounts are written
+        let outside: Vec<_> = self.ledger().outside().collect();
+        out.write(&len(outside.len())?.to_le_bytes())?;
+        for (ledger_acct, balance) in outside {
+            out.write(&[match ledger_acct {
+                LedgerAcct::External => 0,
+                _ => 1,
+            }])?;
+            out.write(&balance.raw().to_le_bytes())?;
+        }
+
+        out.write(&len(self.clocks().len())?.to_le_bytes())?;
+        for (client_id, timestamp) in self.clocks() {
+            out.write(&client_id.to_le_bytes())?;
+            out.write(&timestamp.to_le_bytes())?;
+        }
+
+        out.write(&len(self.usage().len())?.to_le_bytes())?;
+        for (client_id, usage) in self.usage() {
+            out.write(&client_id.to_le_bytes())?;
+            out.time(usage.withdrawn.map(|(day, _)| day))?;
+            out.write(&usage.withdrawn.map_or(0, |(_, withdrawn)| withdrawn.raw()).to_le_bytes())?;
+            out.write(&len(usage.recent.len())?.to_le_bytes())?;
+            for timestamp in &usage.recent {
+                out.write(&timestamp.to_le_bytes())?;
+            }
+        }
+
+        let crc = out.crc.finish();
+        out.out.write_all(&crc.to_le_bytes())?;
+        out.out.flush()
+    }
+
+    /// Loads every recorded transaction and account from a snapshot into the
+    /// engine's stores.
+    ///
+    /// The whole snapshot is checked before anything is loaded, so a corrupt
+    /// snapshot leaves the engine untouched.
+    pub fn load_snapshot<R>(&mut self, mut input: R) -> io::Result<()>
+        where R: Read
+    {
+        let mut data = Vec::new();
+        input.read_to_end(&mut data)?;
+
+        if data.len() < MAGIC.len() + 2 + 4 || &data[..MAGIC.len()] != MAGIC {
+            return Err(invalid("not a snapshot"));
+        }
+        let (body, crc) = data.split_at(data.len() - 4);
+        if crc32(body) != u32::from_le_bytes(crc.try_into().unwrap()) {
+            return Err(invalid("snapshot checksum mismatch"));
+        }
+
+        let mut body = Decoder { buf: &body[MAGIC.len()..] };
+        let version = body.u16()?;
+        if version != VERSION {
+            return Err(invalid(&format!("unsupported snapshot version {version}")));
+        }
+
+        let mut txs = Vec::new();
+        for _ in 0..body.u32()? {
+            let tx_id = body.u32()?;
+            let client_id = body.u16()?;
+            let kind = TxType::from_byte(body.u8()?)
+                .filter(|k| k.is_recorded())
+                .ok_or_else(|| invalid("invalid transaction type"))?;
+            let amount = Amount::from_raw(body.i64()?);
+            let disputed = Amount::from_raw(body.i64()?);
+            let charged_back = Amount::from_raw(body.i64()?);
+            let disputes = body.u32()?;
+            let dest = match body.u8()? {
+                0 => None,
+                _ => Some(body.u16()?),
+            };
+            let timestamp = body.time()?;
+            let disputed_at = body.time()?;
+            txs.push((tx_id, RecTx { client_id, kind, amount, disputed, charged_back, disputes, dest, timestamp, disputed_at }));
+        }
+
+        let mut accts = Vec::new();
+        for _ in 0..body.u32()? {
+            let client_id = body.u16()?;
+            let available = Amount::from_raw(body.i64()?);
+            let held = Amount::from_raw(body.i64()?);
+            let total = available.checked_add(held).ok_or_else(|| invalid("account total overflows"))?;
+            let lock = lock_reason(body.u8()?)?;
+            accts.push((client_id, Acct { available, held, total, lock }));
+        }
+
+        let mut audit = Vec::new();
+        for _ in 0..body.u32()? {
+            let client_id = body.u16()?;
+            let tx_id = body.u32()?;
+            let action = TxType::from_byte(body.u8()?)
+                .filter(|a| a.is_admin())
+                .ok_or_else(|| invalid("invalid audit action"))?;
+            let from = lock_reason(body.u8()?)?;
+            let to = lock_reason(body.u8()?)?;
+            let len = body.u32()? as usize;
+            let reason = String::from_utf8(body.bytes(len)?.to_vec()).map_err(|_| invalid("invalid audit reason"))?;
+            audit.push(AuditEntry { client_id, tx_id, action, reason, from, to });
+        }
+
+        let mut outside = Vec::new();
+        for _ in 0..body.u32()? {
+            let ledger_acct = match body.u8()? {
+                0 => LedgerAcct::External,
+                1 => LedgerAcct::Settlement,
+                a => return Err(invalid(&format!("invalid ledger account {a}"))),
+            };
+            outside.push((ledger_acct, Amount::from_raw(body.i64()?)));
+        }
+
+        let mut clocks = Vec::new();
+        for _ in 0..body.u32()? {
+            clocks.push((body.u16()?, body.u64()?));
+        }
+
+        let mut usage = Vec::new();
+        for _ in 0..body.u32()? {
+            let client_id = body.u16()?;
+            let day = body.time()?;
+            let withdrawn = Amount::from_raw(body.i64()?);
+            let recent = (0..body.u32()?).map(|_| body.u64()).collect::<io::Result<_>>()?;
+            usage.push((client_id, Usage { withdrawn: day.map(|day| (day, withdrawn)), recent }));
+        }
+
+        if !body.buf.is_empty() {
+            return Err(invalid("unexpected data at the end of the snapshot"));
+        }
+
+        for (tx_id, tx) in txs {
+            self.tx_map.put(tx_id, tx);
+        }
+        for (client_id, acct) in accts {
+            self.ledger_mut().set(LedgerAcct::Available(client_id), acct.available);
+            self.ledger_mut().set(LedgerAcct::Held(client_id), acct.held);
+            self.acct_map.put(client_id, acct);
+        }
+        self.audit_mut().extend(audit);
+        for (ledger_acct, balance) in outside {
+            self.ledger_mut().set(ledger_acct, balance);
+        }
+        self.clocks_mut().extend(clocks);
+        self.usage_mut().extend(usage);
+        self.reset_open_disputes();
+        Ok(())
+    }
+}
+
+fn lock_byte(lock: Option<LockReason>) -> u8 {
+    match lock {
+        None => 0,
+        Some(LockReason::Frozen) => 1,
+        Some(LockReason::Chargeback) => 2,
+        Some(LockReason::Closed) => 3,
+    }
+}
+
+fn lock_reason(b: u8) -> io::Result<Option<LockReason>> {
+    match b {
+        0 => Ok(None),
+        1 => Ok(Some(LockReason::Frozen)),
+        2 => Ok(Some(LockReason::Chargeback)),
+        3 => Ok(Some(LockReason::Closed)),
+        _ => Err(invalid(&format!("invalid lock reason {b}"))),
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, msg)
+}
+
+fn len(n: usize) -> io::Result<u32> {
+    u32::try_from(n).map_err(|_| invalid("too many entries for a snapshot"))
+}
+
+/// Writes data out while keeping a checksum of everything written.
+struct Encoder<W> {
+    out: W,
+    crc: Crc32,
+}
+
+impl<W> Encoder<W>
+    where W: Write
+{
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        self.crc.update(data);
+        self.out.write_all(data)
+    }
+
+    /// Writes an optional timestamp, preceded by a flag byte.
+    fn time(&mut self, timestamp: Option<u64>) -> io::Result<()> {
+        match timestamp {
+            Some(timestamp) => {
+                self.write(&[1])?;
+                self.write(&timestamp.to_le_bytes())
+            }
+            None => self.write(&[0]),
+        }
+    }
+}
+
+/// Reads little-endian integers off the front of a buffer.
+struct Decoder<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    fn take<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        self.bytes(N).map(|b| b.try_into().unwrap())
+    }
+
+    fn bytes(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        if self.buf.len() < n {
+            return Err(invalid("snapshot is truncated"));
+        }
+        let (bytes, rest) = self.buf.split_at(n);
+        self.buf = rest;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        self.take::<1>().map(|b| b[0])
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        self.take().map(u16::from_le_bytes)
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        self.take().map(u32::from_le_bytes)
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        self.take().map(u64::from_le_bytes)
+    }
+
+    fn i64(&mut self) -> io::Result<i64> {
+        self.take().map(i64::from_le_bytes)
+    }
+
+    /// Reads an optional timestamp, preceded by a flag byte.
+    fn time(&mut self) -> io::Result<Option<u64>> {
+        match self.u8()? {
+            0 => Ok(None),
+            _ => self.u64().map(Some),
+        }
+    }
+}
+
+//------------------------------------------------------------------------------
+//------------------------------------------------------------------------------
+// TESTS
+//------------------------------------------------------------------------------
+//------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::limits::{Limits, Velocity};
+    use crate::transaction::Tx;
+
+    fn tx(tx_type: TxType, client_id: u16, tx_id: u32, amount: Option<&str>) -> Tx {
+        Tx{ tx_type, client_id, tx_id, amount: amount.map(|a| a.parse().unwrap()), dest: None, reason: None, timestamp: None }
+    }
+
+    fn engine() -> Engine {
+        let mut engine = Engine::new();
+        engine.set_limits(Limits{ daily_withdrawal: Some(Amount::from_raw(1_000_000)), velocity: Some(Velocity{ count: 10, window: 60 }), ..Default::default() });
+        for tx in [
+            Tx{ timestamp: Some(100), ..tx(TxType::Deposit, 1, 1, Some("3.0")) },
+            tx(TxType::Deposit, 2, 2, Some("1.5")),
+            Tx{ timestamp: Some(120), ..tx(TxType::Withdrawal, 1, 3, Some("1.0")) },
+            Tx{ dest: Some(3), ..tx(TxType::Transfer, 1, 4, Some("0.5")) },
+            Tx{ timestamp: Some(150), ..tx(TxType::Dispute, 1, 1, None) },
+            tx(TxType::Dispute, 2, 2, None),
+            tx(TxType::Chargeback, 2, 2, None),
+            Tx{ reason: Some("fraud review".into()), ..tx(TxType::Close, 2, 5, None) },
+        ] {
+            engine.process_tx(tx).unwrap();
+        }
+        engine
+    }
+
+    #[test]
+    fn round_trip() {
+        let engine = engine();
+        let mut data = Vec::new();
+        engine.save_snapshot(&mut data).unwrap();
+
+        let mut restored = Engine::new();
+        restored.load_snapshot(data.as_slice()).unwrap();
+        assert_eq!(engine.tx_map, restored.tx_map);
+        assert_eq!(engine.acct_map, restored.acct_map);
+        assert_eq!(engine.audit_log(), restored.audit_log());
+        assert_eq!(engine.ledger(), restored.ledger());
+        assert_eq!(engine.clocks(), restored.clocks());
+        assert_eq!(engine.usage(), restored.usage());
+        assert_eq!(Some((0, Amount::from_raw(10_000))), restored.usage()[&1].withdrawn);
+        assert_eq!(Some(150), restored.tx_map[&1].disputed_at);
+        assert_eq!(Ok(()), restored.trial_balance());
+        assert_eq!(Some(LockReason::Closed), restored.acct_map[&2].lock);
+
+        // disputes can reference transactions from before the snapshot
+        assert!(restored.process_tx(tx(TxType::Resolve, 1, 1, None)).is_ok());
+        assert!(restored.process_tx(tx(TxType::Deposit, 2, 5, Some("1.0"))).is_err());
+        assert!(restored.process_tx(tx(TxType::Dispute, 1, 4, None)).is_ok());
+    }
+
+    #[test]
+    fn corrupt() {
+        let mut data = Vec::new();
+        engine().save_snapshot(&mut data).unwrap();
+
+        let mut flipped = data.clone();
+        flipped[20] ^= 1;
+        let mut restored = Engine::new();
+        assert!(restored.load_snapshot(flipped.as_slice()).is_err());
+        assert!(restored.tx_map.is_empty());
+        assert!(restored.acct_map.is_empty());
+
+        assert!(restored.load_snapshot(&data[..data.len() - 1]).is_err());
+        assert!(restored.load_snapshot(&b"not a snapshot at all"[..]).is_err());
+    }
+}
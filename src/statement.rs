@@ -0,0 +1,231 @@
+//! Contains the [`Statement`] of a single client's account.
+//!
+//! A statement is a journal [`Subscriber`] that writes a line for every
+//! transaction that touched the client's account, in the order they were
+//! processed: the accepted ones (including the other side of transfers, fees,
+//! and interest) with the balances after them, and the client's rejected ones
+//! with the balances they left unchanged.
+
+use std::io::{self, Write};
+
+use serde::Serialize;
+
+use crate::account::Acct;
+use crate::amount::Amount;
+use crate::error::ProcessError;
+use crate::journal::{Event, Subscriber};
+use crate::output::RecordWriter;
+use crate::transaction::{Tx, TxType};
+
+/// A line of a statement.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Line {
+    /// The sequence number of the transaction (empty for the opening balance).
+    pub seq: Option<u64>,
+    pub tx: Option<u32>,
+    #[serde(rename = "type")]
+    pub tx_type: Option<TxType>,
+    /// `accepted`, the code the transaction was rejected with, or `opening`.
+    pub result: &'static str,
+    /// How much the transaction changed the total by (empty if it was rejected).
+    pub change: Option<Amount>,
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
+    pub locked: bool,
+}
+
+impl Line {
+    const HEADER: [&'static str; 9] = ["seq", "tx", "type", "result", "change", "available", "held", "total", "locked"];
+
+    fn opening(acct: &Acct) -> Self {
+        Line {
+            seq: None,
+            tx: None,
+            tx_type: None,
+            result: "opening",
+            change: None,
+            available: acct.available,
+            held: acct.held,
+            total: acct.total,
+            locked: acct.locked(),
+        }
+    }
+}
+
+enum Output<W>
+    where W: Write
+{
+    Records(RecordWriter<W>),
+    Text(W),
+}
+
+/// Writes the statement of a client's account as the engine processes
+/// transactions (see [`Engine::subscribe`](crate::Engine::subscribe)).
+pub struct Statement<W>
+    where W: Write
+{
+    client_id: u16,
+    /// The last line written, whose balances are the account's current ones
+    last: Line,
+    out: Output<W>,
+}
+
+impl<W> Statement<W>
+    where W: Write
+{
+    /// Starts a statement written as records (CSV or JSON Lines), opening with the
+    /// account's current balances.
+    pub fn records(client_id: u16, opening: &Acct, mut writer: RecordWriter<W>) -> io::Result<Self> {
+        writer.header(&Line::HEADER)?;
+        Self::start(client_id, opening, Output::Records(writer))
+    }
+
+    /// Starts a statement written as plain text, opening with the account's
+    /// current balances.
+    pub fn text(client_id: u16, opening: &Acct, mut out: W) -> io::Result<Self> {
+        writeln!(out, "statement for client {client_id}\n")?;
+        writeln!(out, "{:>8} {:>10} {:<10} {:<24} {:>14} {:>14} {:>14} {:>14} {:>6}", "seq", "tx", "type", "result", "change", "available", "held", "total", "locked")?;
+        Self::start(client_id, opening, Output::Text(out))
+    }
+
+    fn start(client_id: u16, opening: &Acct, out: Output<W>) -> io::Result<Self> {
+        let mut statement = Statement { client_id, last: Line::opening(opening), out };
+        statement.write()?;
+        Ok(statement)
+    }
+
+    /// Writes the last line.
+    fn write(&mut self) -> io::Result<()> {
+        let line = &self.last;
+        match &mut self.out {
+            Output::Records(writer) => writer.serialize(line).map_err(|e| io::Error::other(e.to_string())),
+            Output::Text(out) => {
+                let text = |v: Option<String>| v.unwrap_or_default();
+                writeln!(
+                    out,
+                    "{:>8} {:>10} {:<10} {:<24} {:>14} {:>14} {:>14} {:>14} {:>6}",
+                    text(line.seq.map(|s| s.to_string())),
+                    text(line.tx.map(|t| t.to_string())),
+                    text(line.tx_type.map(|t| t.to_string())),
+                    line.result,
+                    text(line.change.map(|c| c.to_string())),
+                    line.available.to_string(),
+                    line.held.to_string(),
+                    line.total.to_string(),
+                    line.locked,
+                )
+            }
+        }
+    }
+}
+
+impl<W> Subscriber for Statement<W>
+    where W: Write + Send
+{
+    fn event(&mut self, event: &Event) -> io::Result<()> {
+        if event.client_id != self.client_id {
+            return Ok(());
+        }
+        self.last = Line {
+            seq: Some(event.seq),
+            tx: Some(event.tx_id),
+            tx_type: Some(event.tx_type),
+            result: "accepted",
+            change: Some(event.total_delta),
+            available: event.available,
+            held: event.held,
+            total: event.total,
+            locked: event.locked,
+        };
+        self.write()
+    }
+
+    fn rejected(&mut self, seq: u64, tx: &Tx, error: &ProcessError) -> io::Result<()> {
+        if tx.client_id != self.client_id {
+            return Ok(());
+        }
+        self.last = Line {
+            seq: Some(seq),
+            tx: Some(tx.tx_id),
+            tx_type: Some(tx.tx_type),
+            result: error.code(),
+            change: None,
+            ..self.last
+        };
+        self.write()
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.out {
+            Output::Records(writer) => writer.flush(),
+            Output::Text(out) => out.flush(),
+        }
+    }
+}
+
+//------------------------------------------------------------------------------
+//------------------------------------------------------------------------------
+// TESTS
+//------------------------------------------------------------------------------
+//------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use crate::engine::Engine;
+    use crate::format::Format;
+    use crate::output;
+
+    /// An output the test can still read once the engine owns the statement.
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn tx(tx_type: TxType, client_id: u16, tx_id: u32, amount: Option<&str>, dest: Option<u16>) -> Tx {
+        Tx{ tx_type, client_id, tx_id, amount: amount.map(|a| a.parse().unwrap()), dest, reason: None, timestamp: None }
+    }
+
+    #[test]
+    fn statement() {
+        let mut engine = Engine::new();
+        engine.process_tx(tx(TxType::Deposit, 1, 1, Some("3.0"), None)).unwrap();
+
+        let out = Shared::default();
+        let opening = engine.acct_map[&1].clone();
+        let statement = Statement::records(1, &opening, output::writer(Format::Csv, out.clone())).unwrap();
+        engine.subscribe(statement);
+
+        for tx in [
+            tx(TxType::Transfer, 2, 2, Some("1.0"), Some(1)),
+            tx(TxType::Deposit, 2, 3, Some("2.0"), None),
+            tx(TxType::Transfer, 2, 4, Some("1.5"), Some(1)),
+            tx(TxType::Withdrawal, 1, 5, Some("9.0"), None),
+            tx(TxType::Dispute, 1, 1, None, None),
+            tx(TxType::Chargeback, 1, 1, None, None),
+        ] {
+            _ = engine.process_tx(tx);
+        }
+        engine.flush_journal().unwrap();
+
+        let out = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(vec![
+            "seq,tx,type,result,change,available,held,total,locked",
+            ",,,opening,,3.0000,0.0000,3.0000,false",
+            "3,4,transfer,accepted,1.5000,4.5000,0.0000,4.5000,false",
+            "4,5,withdrawal,insufficient_funds,,4.5000,0.0000,4.5000,false",
+            "5,1,dispute,accepted,0.0000,1.5000,3.0000,4.5000,false",
+            "6,1,chargeback,accepted,-3.0000,1.5000,0.0000,1.5000,true",
+        ], out.lines().collect::<Vec<_>>());
+    }
+}
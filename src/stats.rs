@@ -0,0 +1,122 @@
+//! Contains the [`Stats`] struct that summarizes a run of the engine.
+
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+use crate::error::ProcessError;
+use crate::transaction::TxType;
+
+/// How many transactions were accepted and rejected.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Counts {
+    pub accepted: u64,
+    pub rejected: u64,
+}
+
+impl Counts {
+    fn record(&mut self, accepted: bool) {
+        if accepted {
+            self.accepted += 1;
+        } else {
+            self.rejected += 1;
+        }
+    }
+}
+
+/// Counts of what happened while processing transactions.
+#[derive(Debug, Default, PartialEq)]
+pub struct Stats {
+    /// The number of rows that couldn't be parsed into a transaction.
+    pub malformed: u64,
+    /// The transactions of each type.
+    pub by_type: BTreeMap<TxType, Counts>,
+    /// The transactions of each client.
+    pub by_client: BTreeMap<u16, Counts>,
+    /// The number of rejections with each error code.
+    pub rejections: BTreeMap<&'static str, u64>,
+}
+
+impl Stats {
+    /// Records the outcome of processing a transaction.
+    pub fn record(&mut self, tx_type: TxType, client_id: u16, result: &Result<(), ProcessError>) {
+        self.by_type.entry(tx_type).or_default().record(result.is_ok());
+        self.by_client.entry(client_id).or_default().record(result.is_ok());
+        if let Err(e) = result {
+            *self.rejections.entry(e.code()).or_default() += 1;
+        }
+    }
+
+    /// Records a row that couldn't be parsed.
+    pub fn record_malformed(&mut self) {
+        self.malformed += 1;
+    }
+
+    /// Returns the counts across every transaction.
+    pub fn totals(&self) -> Counts {
+        self.by_type.values().fold(Counts::default(), |acc, c| Counts {
+            accepted: acc.accepted + c.accepted,
+            rejected: acc.rejected + c.rejected,
+        })
+    }
+
+    /// Writes a human-readable report of the stats.
+    pub fn write_report<W>(&self, mut out: W) -> io::Result<()>
+        where W: Write
+    {
+        let totals = self.totals();
+        writeln!(out, "rows      : {}", totals.accepted + totals.rejected + self.malformed)?;
+        writeln!(out, "malformed : {}", self.malformed)?;
+        writeln!(out, "accepted  : {}", totals.accepted)?;
+        writeln!(out, "rejected  : {}", totals.rejected)?;
+
+        writeln!(out, "\n{:<12} {:>10} {:>10}", "type", "accepted", "rejected")?;
+        for (tx_type, counts) in &self.by_type {
+            writeln!(out, "{:<12} {:>10} {:>10}", tx_type.to_string(), counts.accepted, counts.rejected)?;
+        }
+
+        writeln!(out, "\n{:<26} {:>10}", "rejection", "count")?;
+        for (code, count) in &self.rejections {
+            writeln!(out, "{code:<26} {count:>10}")?;
+        }
+
+        writeln!(out, "\n{:<12} {:>10} {:>10}", "client", "accepted", "rejected")?;
+        for (client_id, counts) in &self.by_client {
+            writeln!(out, "{:<12} {:>10} {:>10}", client_id, counts.accepted, counts.rejected)?;
+        }
+        Ok(())
+    }
+}
+
+//------------------------------------------------------------------------------
+//------------------------------------------------------------------------------
+// TESTS
+//------------------------------------------------------------------------------
+//------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn record() {
+        let mut stats = Stats::default();
+        stats.record(TxType::Deposit, 1, &Ok(()));
+        stats.record(TxType::Deposit, 2, &Ok(()));
+        stats.record(TxType::Withdrawal, 1, &Err(ProcessError::InsufficientFunds { client_id: 1, tx_id: 3 }));
+        stats.record(TxType::Dispute, 2, &Err(ProcessError::UnknownTx { client_id: 2, tx_id: 9 }));
+        stats.record(TxType::Dispute, 2, &Err(ProcessError::UnknownTx { client_id: 2, tx_id: 8 }));
+        stats.record_malformed();
+
+        assert_eq!(Counts{ accepted: 2, rejected: 3 }, stats.totals());
+        assert_eq!(Counts{ accepted: 2, rejected: 0 }, stats.by_type[&TxType::Deposit]);
+        assert_eq!(Counts{ accepted: 1, rejected: 2 }, stats.by_client[&2]);
+        assert_eq!(2, stats.rejections["unknown_tx"]);
+        assert_eq!(1, stats.rejections["insufficient_funds"]);
+
+        let mut report = Vec::new();
+        stats.write_report(&mut report).unwrap();
+        let report = String::from_utf8(report).unwrap();
+        assert!(report.starts_with("rows      : 6\nmalformed : 1\n"));
+        assert!(report.contains("\nunknown_tx                          2\n"));
+    }
+}
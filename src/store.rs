@@ -0,0 +1,220 @@
+//! Contains the [`TxStore`] and [`AccountStore`] traits the [`Engine`](crate::Engine)
+//! keeps its state in.
+//!
+//! The in-memory `BTreeMap`s are the default implementations, but anything that
+//! implements these traits (e.g., a disk-backed or size-bounded store) can be
+//! used instead.
+
+use std::collections::BTreeMap;
+
+use crate::account::Acct;
+use crate::engine::RecTx;
+
+/// Storage for recorded transactions, keyed by transaction ID.
+///
+/// Transactions are handed in and out by value, so a store doesn't have to keep
+/// them in memory.
+pub trait TxStore {
+    /// Returns the recorded transaction with this ID.
+    fn get(&self, tx_id: u32) -> Option<RecTx>;
+
+    /// Stores a transaction, replacing any transaction with the same ID.
+    fn put(&mut self, tx_id: u32, tx: RecTx);
+
+    /// Changes the recorded transaction with this ID, returning whether there was
+    /// one.
+    fn update<F>(&mut self, tx_id: u32, f: F) -> bool
+        where F: FnOnce(&mut RecTx), Self: Sized
+    {
+        let Some(mut tx) = self.get(tx_id) else {
+            return false;
+        };
+        f(&mut tx);
+        self.put(tx_id, tx);
+        true
+    }
+
+    /// Iterates over every recorded transaction, ordered by transaction ID.
+    fn iter(&self) -> Box<dyn Iterator<Item = (u32, RecTx)> + '_>;
+
+    /// Returns the number of recorded transactions.
+    fn len(&self) -> usize;
+
+    /// Returns whether a transaction with this ID has been recorded, which is what
+    /// stops IDs from being reused. A store that forgets transactions must keep
+    /// returning `true` for their IDs, or they can be recorded again.
+    fn contains(&self, tx_id: u32) -> bool {
+        self.get(tx_id).is_some()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Storage for client accounts, keyed by client ID.
+///
+/// Accounts are handed in and out by value, so a store doesn't have to keep them
+/// in memory.
+pub trait AccountStore {
+    /// Returns the account of this client.
+    fn get(&self, client_id: u16) -> Option<Acct>;
+
+    /// Stores an account, replacing any account the client already had.
+    fn put(&mut self, client_id: u16, acct: Acct);
+
+    /// Changes the account of this client, creating a default account first if the
+    /// client doesn't have one yet.
+    fn update<F>(&mut self, client_id: u16, f: F)
+        where F: FnOnce(&mut Acct), Self: Sized
+    {
+        let mut acct = self.get(client_id).unwrap_or_default();
+        f(&mut acct);
+        self.put(client_id, acct);
+    }
+
+    /// Iterates over every account, ordered by client ID.
+    fn iter(&self) -> Box<dyn Iterator<Item = (u16, Acct)> + '_>;
+
+    /// Returns the number of accounts.
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl TxStore for BTreeMap<u32, RecTx> {
+    fn get(&self, tx_id: u32) -> Option<RecTx> {
+        BTreeMap::get(self, &tx_id).cloned()
+    }
+
+    fn put(&mut self, tx_id: u32, tx: RecTx) {
+        BTreeMap::insert(self, tx_id, tx);
+    }
+
+    fn update<F>(&mut self, tx_id: u32, f: F) -> bool
+        where F: FnOnce(&mut RecTx)
+    {
+        BTreeMap::get_mut(self, &tx_id).map(f).is_some()
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (u32, RecTx)> + '_> {
+        Box::new(BTreeMap::iter(self).map(|(k, v)| (*k, v.clone())))
+    }
+
+    fn len(&self) -> usize {
+        BTreeMap::len(self)
+    }
+
+    fn contains(&self, tx_id: u32) -> bool {
+        self.contains_key(&tx_id)
+    }
+}
+
+impl AccountStore for BTreeMap<u16, Acct> {
+    fn get(&self, client_id: u16) -> Option<Acct> {
+        BTreeMap::get(self, &client_id).cloned()
+    }
+
+    fn put(&mut self, client_id: u16, acct: Acct) {
+        BTreeMap::insert(self, client_id, acct);
+    }
+
+    fn update<F>(&mut self, client_id: u16, f: F)
+        where F: FnOnce(&mut Acct)
+    {
+        f(self.entry(client_id).or_default());
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (u16, Acct)> + '_> {
+        Box::new(BTreeMap::iter(self).map(|(k, v)| (*k, v.clone())))
+    }
+
+    fn len(&self) -> usize {
+        BTreeMap::len(self)
+    }
+}
+
+//------------------------------------------------------------------------------
+//------------------------------------------------------------------------------
+// TESTS
+//------------------------------------------------------------------------------
+//------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeSet;
+
+    use super::*;
+    use crate::engine::Engine;
+    use crate::error::ProcessError;
+    use crate::transaction::{Tx, TxType};
+
+    /// A store that refuses to keep more than a fixed number of transactions,
+    /// forgetting the oldest ones first (but not their IDs).
+    #[derive(Default)]
+    struct BoundedTxStore {
+        capacity: usize,
+        txs: BTreeMap<u32, RecTx>,
+        order: Vec<u32>,
+        seen: BTreeSet<u32>,
+    }
+
+    impl TxStore for BoundedTxStore {
+        fn get(&self, tx_id: u32) -> Option<RecTx> {
+            self.txs.get(&tx_id).cloned()
+        }
+
+        fn put(&mut self, tx_id: u32, tx: RecTx) {
+            if !self.txs.contains_key(&tx_id) {
+                if self.order.len() == self.capacity {
+                    let oldest = self.order.remove(0);
+                    self.txs.remove(&oldest);
+                }
+                self.order.push(tx_id);
+                self.seen.insert(tx_id);
+            }
+            self.txs.insert(tx_id, tx);
+        }
+
+        fn iter(&self) -> Box<dyn Iterator<Item = (u32, RecTx)> + '_> {
+            Box::new(self.txs.iter().map(|(k, v)| (*k, v.clone())))
+        }
+
+        fn len(&self) -> usize {
+            self.txs.len()
+        }
+
+        fn contains(&self, tx_id: u32) -> bool {
+            self.seen.contains(&tx_id)
+        }
+    }
+
+    fn tx(tx_type: TxType, tx_id: u32, amount: Option<&str>) -> Tx {
+        Tx{ tx_type, client_id: 1, tx_id, amount: amount.map(|a| a.parse().unwrap()), dest: None, reason: None, timestamp: None }
+    }
+
+    #[test]
+    fn custom_store() {
+        let store = BoundedTxStore{ capacity: 2, ..Default::default() };
+        let mut engine = Engine::with_stores(store, BTreeMap::new());
+
+        assert!(engine.process_tx(tx(TxType::Deposit, 1, Some("1.0"))).is_ok());
+        assert!(engine.process_tx(tx(TxType::Deposit, 2, Some("2.0"))).is_ok());
+        assert!(engine.process_tx(tx(TxType::Deposit, 3, Some("3.0"))).is_ok());
+        assert_eq!(2, engine.tx_map.len());
+
+        // the first transaction has been forgotten, so it can no longer be disputed
+        assert!(engine.process_tx(tx(TxType::Dispute, 1, None)).is_err());
+        assert!(engine.process_tx(tx(TxType::Dispute, 3, None)).is_ok());
+
+        // but its ID still can't be reused
+        let reused = engine.process_tx(tx(TxType::Deposit, 1, Some("1.0")));
+        assert!(matches!(reused, Err(ProcessError::DuplicateTxId { tx_id: 1, .. })));
+
+        let acct = AccountStore::get(&engine.acct_map, 1).unwrap();
+        assert_eq!(Ok(acct.available), "3.0".parse());
+        assert_eq!(Ok(acct.held), "3.0".parse());
+    }
+}
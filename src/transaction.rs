@@ -1,17 +1,146 @@
-use serde::Deserialize;
+//! Contains the [`Tx`] struct representing a row of input.
 
-#[derive(Debug, Deserialize)]
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{de, Deserialize, Deserializer, Serialize};
+
+use crate::amount::Amount;
+
+/// The longest reason a transaction can have, in bytes.
+pub const MAX_REASON_LEN: usize = u16::MAX as usize;
+
+/// The action of a transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
 #[serde(rename_all = "lowercase")]
 pub enum TxType {
+    /// Adds funds to a client's account.
     Deposit,
+    /// Removes funds from a client's account.
     Withdrawal,
+    /// Holds the funds of the referenced transaction.
     Dispute,
+    /// Releases the funds of a disputed transaction.
     Resolve,
+    /// Reverses the funds of a disputed transaction and locks the account.
     Chargeback,
+    /// Moves funds from a client's account to another client's account.
+    Transfer,
+    /// Charges a client's account, paying the funds into the house account.
+    Fee,
+    /// Credits a client's account, paying the funds out of the house account.
+    Interest,
+    /// Locks a client's account until it's unfrozen.
+    Freeze,
+    /// Unlocks a frozen (or charged back) account.
+    Unfreeze,
+    /// Locks a client's account for good.
+    Close,
+}
+
+impl TxType {
+    /// Returns whether transactions of this type are recorded (i.e., they have
+    /// their own amount rather than referencing another transaction).
+    pub fn is_recorded(self) -> bool {
+        !matches!(self, TxType::Dispute | TxType::Resolve | TxType::Chargeback) && !self.is_admin()
+    }
+
+    /// Returns whether this is an administrative action on an account (rather than
+    /// a movement of funds).
+    pub fn is_admin(self) -> bool {
+        matches!(self, TxType::Freeze | TxType::Unfreeze | TxType::Close)
+    }
+
+    /// Returns the byte this type is stored as in the write-ahead log and snapshots.
+    pub(crate) fn to_byte(self) -> u8 {
+        match self {
+            TxType::Deposit => 0,
+            TxType::Withdrawal => 1,
+            TxType::Dispute => 2,
+            TxType::Resolve => 3,
+            TxType::Chargeback => 4,
+            TxType::Transfer => 5,
+            TxType::Fee => 6,
+            TxType::Interest => 7,
+            TxType::Freeze => 8,
+            TxType::Unfreeze => 9,
+            TxType::Close => 10,
+        }
+    }
+
+    /// The inverse of [`TxType::to_byte`].
+    pub(crate) fn from_byte(b: u8) -> Option<Self> {
+        Some(match b {
+            0 => TxType::Deposit,
+            1 => TxType::Withdrawal,
+            2 => TxType::Dispute,
+            3 => TxType::Resolve,
+            4 => TxType::Chargeback,
+            5 => TxType::Transfer,
+            6 => TxType::Fee,
+            7 => TxType::Interest,
+            8 => TxType::Freeze,
+            9 => TxType::Unfreeze,
+            10 => TxType::Close,
+            _ => return None,
+        })
+    }
+}
+
+impl fmt::Display for TxType {
+    /// Writes the type the way it appears in the input.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            TxType::Deposit => "deposit",
+            TxType::Withdrawal => "withdrawal",
+            TxType::Dispute => "dispute",
+            TxType::Resolve => "resolve",
+            TxType::Chargeback => "chargeback",
+            TxType::Transfer => "transfer",
+            TxType::Fee => "fee",
+            TxType::Interest => "interest",
+            TxType::Freeze => "freeze",
+            TxType::Unfreeze => "unfreeze",
+            TxType::Close => "close",
+        })
+    }
+}
+
+impl FromStr for TxType {
+    type Err = String;
+
+    /// Parses a type the way it appears in the input.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "deposit" => TxType::Deposit,
+            "withdrawal" => TxType::Withdrawal,
+            "dispute" => TxType::Dispute,
+            "resolve" => TxType::Resolve,
+            "chargeback" => TxType::Chargeback,
+            "transfer" => TxType::Transfer,
+            "fee" => TxType::Fee,
+            "interest" => TxType::Interest,
+            "freeze" => TxType::Freeze,
+            "unfreeze" => TxType::Unfreeze,
+            "close" => TxType::Close,
+            _ => return Err(format!("unknown transaction type {s}")),
+        })
+    }
+}
+
+/// Reads a reason, refusing one that's longer than [`MAX_REASON_LEN`].
+fn reason<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+    where D: Deserializer<'de>
+{
+    let reason = Option::<String>::deserialize(deserializer)?;
+    match reason {
+        Some(reason) if reason.len() > MAX_REASON_LEN => Err(de::Error::custom(format!("reason is longer than {MAX_REASON_LEN} bytes"))),
+        reason => Ok(reason),
+    }
 }
 
 /// This type represents a row in the input CSV.
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize)]
 pub struct Tx {
     /// The type of this transaction.
     #[serde(rename = "type")]
@@ -25,6 +154,19 @@ pub struct Tx {
     #[serde(rename = "tx")]
     pub tx_id: u32,
 
-    /// The amount of this transaction - required for deposits and withdraws.
-    pub amount: Option<f64>,
+    /// The amount of this transaction - required for all but the dispute-related
+    /// and administrative transactions.
+    pub amount: Option<Amount>,
+
+    /// The client ID funds are moved to - required for transfers.
+    pub dest: Option<u16>,
+
+    /// Why an administrative action was taken - required for administrative
+    /// transactions (at most [`MAX_REASON_LEN`] bytes long).
+    #[serde(default, deserialize_with = "reason")]
+    pub reason: Option<String>,
+
+    /// When this transaction was made (e.g., a Unix timestamp) - optional, but
+    /// never earlier than the client's previous timestamped transaction.
+    pub timestamp: Option<u64>,
 }
@@ -0,0 +1,404 @@
+//! Contains the [`Wal`] write-ahead log that makes an engine's state durable.
+//!
+//! The log starts with a small header (a magic string and a format version)
+//! followed by one record per accepted transaction:
+//!
+//! ```text
+//! | length (u32) | CRC-32 of payload (u32) | payload (length bytes) |
+//! ```
+//!
+//! The payload is the engine's sequence number for the transaction followed by
+//! the transaction itself (where the optional amount, destination, reason, and
+//! timestamp are each preceded by a flag byte, and the reason by its length). All
+//! integers are little-endian.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::amount::Amount;
+use crate::checksum::crc32;
+use crate::engine::Engine;
+use crate::store::{AccountStore, TxStore};
+use crate::transaction::{Tx, TxType, MAX_REASON_LEN};
+
+const MAGIC: &[u8; 6] = b"TPEWAL";
+const VERSION: u16 = 4;
+const HEADER_LEN: u64 = 8;
+/// The longest payload a record can have: a transaction with every optional
+/// field and the longest reason.
+const MAX_PAYLOAD_LEN: u32 = 8 + 1 + 2 + 4 + (1 + 8) + (1 + 2) + (1 + 2 + MAX_REASON_LEN as u32) + (1 + 8);
+
+/// An append-only log of every transaction an engine has accepted.
+#[derive(Debug)]
+pub struct Wal {
+    file: File,
+}
+
+impl Wal {
+    /// Creates a new, empty log, replacing any existing file at the path.
+    pub fn create<P>(path: P) -> io::Result<Self>
+        where P: AsRef<Path>
+    {
+        let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(path)?;
+        write_header(&mut file)?;
+        Ok(Wal { file })
+    }
+
+    /// Opens an existing log and replays every record in it through the engine,
+    /// returning the log ready to be appended to.
+    ///
+    /// A torn trailing record (from a crash in the middle of an append) fails its
+    /// checksum, is cut short, or claims to be longer than any record can be, so
+    /// it and everything after it is truncated. The engine must be configured the
+    /// same way it was when the log was written, and an error is returned if a
+    /// record is rejected during the replay.
+    pub fn recover<P, T, A>(path: P, engine: &mut Engine<T, A>) -> io::Result<Self>
+        where P: AsRef<Path>, T: TxStore, A: AccountStore
+    {
+        let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+
+        // a crash while creating the log can leave a partial header behind
+        if file.metadata()?.len() < HEADER_LEN {
+            file.set_len(0)?;
+            write_header(&mut file)?;
+            return Ok(Wal { file });
+        }
+
+        let end = replay_log(BufReader::new(&mut file), engine)?;
+        file.set_len(end)?;
+        file.seek(SeekFrom::End(0))?;
+        file.sync_all()?;
+        Ok(Wal { file })
+    }
+
+    /// Replays every record in an existing log through the engine without
+    /// touching the log itself (so a torn trailing record is simply ignored).
+    pub fn replay<P, T, A>(path: P, engine: &mut Engine<T, A>) -> io::Result<()>
+        where P: AsRef<Path>, T: TxStore, A: AccountStore
+    {
+        let file = File::open(path)?;
+        if file.metadata()?.len() >= HEADER_LEN {
+            replay_log(BufReader::new(file), engine)?;
+        }
+        Ok(())
+    }
+
+    /// Appends a record for a transaction and waits for it to reach the disk.
+    ///
+    /// A transaction with a reason longer than [`MAX_REASON_LEN`] can't be logged.
+    pub fn append(&mut self, seq: u64, tx: &Tx) -> io::Result<()> {
+        if tx.reason.as_ref().is_some_and(|reason| reason.len() > MAX_REASON_LEN) {
+            return Err(io::Error::new(ErrorKind::InvalidInput, format!("reason of transaction {} is longer than {MAX_REASON_LEN} bytes", tx.tx_id)));
+        }
+        let payload = encode(seq, tx);
+        let mut record = Vec::with_capacity(8 + payload.len());
+        record.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        record.extend_from_slice(&crc32(&payload).to_le_bytes());
+        record.extend_from_slice(&payload);
+
+        self.file.write_all(&record)?;
+        self.file.sync_data()
+    }
+
+    /// Drops every record from the log, leaving it as if it was just created.
+    pub fn truncate(&mut self) -> io::Result<()> {
+        self.file.set_len(HEADER_LEN)?;
+        self.file.seek(SeekFrom::End(0))?;
+        self.file.sync_all()
+    }
+}
+
+/// Replays every intact record of a log through the engine, returning where the
+/// last intact record ends.
+fn replay_log<R, T, A>(mut reader: R, engine: &mut Engine<T, A>) -> io::Result<u64>
+    where R: Read, T: TxStore, A: AccountStore
+{
+    let mut header = [0; HEADER_LEN as usize];
+    reader.read_exact(&mut header)?;
+    if &header[..6] != MAGIC {
+        return Err(io::Error::new(ErrorKind::InvalidData, "not a write-ahead log"));
+    }
+    let version = u16::from_le_bytes([header[6], header[7]]);
+    if version != VERSION {
+        return Err(io::Error::new(ErrorKind::InvalidData, format!("unsupported write-ahead log version {version}")));
+    }
+
+    let mut end = HEADER_LEN;
+    while let Some((seq, tx, len)) = read_record(&mut reader)? {
+        engine.replay(seq, tx).map_err(|e| io::Error::new(ErrorKind::InvalidData, format!("unable to replay record {seq} of write-ahead log: {e}")))?;
+        end += len;
+    }
+    Ok(end)
+}
+
+fn write_header(file: &mut File) -> io::Result<()> {
+    file.write_all(MAGIC)?;
+    file.write_all(&VERSION.to_le_bytes())?;
+    file.sync_all()
+}
+
+/// Reads the next record, returning `None` at the end of the log or at a torn
+/// record. The length returned is the number of bytes the record took up.
+fn read_record<R>(reader: &mut R) -> io::Result<Option<(u64, Tx, u64)>>
+    where R: Read
+{
+    let mut header = [0; 8];
+    if !read_all(reader, &mut header)? {
+        return Ok(None);
+    }
+    let len = u32::from_le_bytes(header[..4].try_into().unwrap());
+    let crc = u32::from_le_bytes(header[4..].try_into().unwrap());
+    // no record is that long, so the length itself was torn
+    if len > MAX_PAYLOAD_LEN {
+        return Ok(None);
+    }
+
+    let mut payload = vec![0; len as usize];
+    if !read_all(reader, &mut payload)? || crc32(&payload) != crc {
+        return Ok(None);
+    }
+    Ok(decode(&payload).map(|(seq, tx)| (seq, tx, 8 + u64::from(len))))
+}
+
+/// Fills the buffer, returning `false` if the end of the data came first.
+fn read_all<R>(reader: &mut R, buf: &mut [u8]) -> io::Result<bool>
+    where R: Read
+{
+    match reader.read_exact(buf) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+fn encode(seq: u64, tx: &Tx) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(27);
+    buf.extend_from_slice(&seq.to_le_bytes());
+    buf.push(tx.tx_type.to_byte());
+    buf.extend_from_slice(&tx.client_id.to_le_bytes());
+    buf.extend_from_slice(&tx.tx_id.to_le_bytes());
+    match tx.amount {
+        Some(amt) => {
+            buf.push(1);
+            buf.extend_from_slice(&amt.raw().to_le_bytes());
+        }
+        None => buf.push(0),
+    }
+    match tx.dest {
+        Some(dest) => {
+            buf.push(1);
+            buf.extend_from_slice(&dest.to_le_bytes());
+        }
+        None => buf.push(0),
+    }
+    match &tx.reason {
+        Some(reason) => {
+            buf.push(1);
+            buf.extend_from_slice(&(reason.len() as u16).to_le_bytes());
+            buf.extend_from_slice(reason.as_bytes());
+        }
+        None => buf.push(0),
+    }
+    match tx.timestamp {
+        Some(timestamp) => {
+            buf.push(1);
+            buf.extend_from_slice(&timestamp.to_le_bytes());
+        }
+        None => buf.push(0),
+    }
+    buf
+}
+
+fn decode(buf: &[u8]) -> Option<(u64, Tx)> {
+    let seq = u64::from_le_bytes(buf.get(..8)?.try_into().ok()?);
+    let tx_type = TxType::from_byte(*buf.get(8)?)?;
+    let client_id = u16::from_le_bytes(buf.get(9..11)?.try_into().ok()?);
+    let tx_id = u32::from_le_bytes(buf.get(11..15)?.try_into().ok()?);
+    let (amount, rest) = match buf.get(15)? {
+        0 => (None, buf.get(16..)?),
+        _ => (Some(Amount::from_raw(i64::from_le_bytes(buf.get(16..24)?.try_into().ok()?))), buf.get(24..)?),
+    };
+    let (dest, rest) = match rest.first()? {
+        0 => (None, rest.get(1..)?),
+        _ => (Some(u16::from_le_bytes(rest.get(1..3)?.try_into().ok()?)), rest.get(3..)?),
+    };
+    let (reason, rest) = match rest.first()? {
+        0 => (None, rest.get(1..)?),
+        _ => {
+            let len = u16::from_le_bytes(rest.get(1..3)?.try_into().ok()?) as usize;
+            (Some(String::from_utf8(rest.get(3..3 + len)?.to_vec()).ok()?), rest.get(3 + len..)?)
+        }
+    };
+    let timestamp = match rest.first()? {
+        0 => None,
+        _ => Some(u64::from_le_bytes(rest.get(1..9)?.try_into().ok()?)),
+    };
+    Some((seq, Tx { tx_type, client_id, tx_id, amount, dest, reason, timestamp }))
+}
+
+//------------------------------------------------------------------------------
+//------------------------------------------------------------------------------
+// TESTS
+//------------------------------------------------------------------------------
+//------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("toy_payments_engine-{}-{name}", std::process::id()))
+    }
+
+    fn tx(tx_type: TxType, client_id: u16, tx_id: u32, amount: Option<&str>) -> Tx {
+        Tx{ tx_type, client_id, tx_id, amount: amount.map(|a| a.parse().unwrap()), dest: None, reason: None, timestamp: None }
+    }
+
+    #[test]
+    fn encoding() {
+        let tx = tx(TxType::Withdrawal, 7, 42, Some("1.2345"));
+        let (seq, decoded) = decode(&encode(9, &tx)).unwrap();
+        assert_eq!(9, seq);
+        assert_eq!(tx, decoded);
+
+        let tx = Tx{ tx_type: TxType::Dispute, client_id: 7, tx_id: 42, amount: None, dest: None, reason: None, timestamp: Some(1_700_000_000) };
+        assert_eq!(Some((0, tx.clone())), decode(&encode(0, &tx)));
+
+        let tx = Tx{ tx_type: TxType::Transfer, client_id: 7, tx_id: 43, amount: Some(Amount::from_raw(5)), dest: Some(8), reason: None, timestamp: None };
+        assert_eq!(Some((1, tx.clone())), decode(&encode(1, &tx)));
+
+        let tx = Tx{ tx_type: TxType::Freeze, client_id: 7, tx_id: 44, amount: None, dest: None, reason: Some("fraud review".into()), timestamp: Some(9) };
+        assert_eq!(Some((2, tx.clone())), decode(&encode(2, &tx)));
+
+        // the longest reason is kept whole
+        let tx = Tx{ reason: Some("é".repeat(MAX_REASON_LEN / 2) + "!"), ..tx };
+        assert_eq!(Some((3, tx.clone())), decode(&encode(3, &tx)));
+
+        // and one that isn't valid UTF-8 is a corrupt record
+        let mut buf = encode(4, &Tx{ reason: Some("é".into()), ..tx });
+        let at = buf.len() - 11;
+        buf[at] = 0xff;
+        assert_eq!(None, decode(&buf));
+    }
+
+    #[test]
+    fn recover() {
+        let path = temp_path("wal-recover");
+
+        let mut engine = Engine::new();
+        engine.set_wal(Wal::create(&path).unwrap());
+        assert!(engine.process_tx(tx(TxType::Deposit, 1, 1, Some("2.0"))).is_ok());
+        assert!(engine.process_tx(tx(TxType::Withdrawal, 1, 2, Some("5.0"))).is_err());
+        assert!(engine.process_tx(tx(TxType::Withdrawal, 1, 3, Some("0.5"))).is_ok());
+        assert!(engine.process_tx(tx(TxType::Dispute, 1, 1, None)).is_ok());
+        drop(engine);
+
+        // simulate a crash in the middle of appending a record
+        let len = fs::metadata(&path).unwrap().len();
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&[20, 0, 0, 0, 1, 2, 3]).unwrap();
+        drop(file);
+
+        let mut recovered = Engine::new();
+        let wal = Wal::recover(&path, &mut recovered).unwrap();
+        assert_eq!(len, fs::metadata(&path).unwrap().len());
+        assert_eq!(4, recovered.processed());
+        assert_eq!(2, recovered.tx_map.len());
+
+        let acct = &recovered.acct_map[&1];
+        assert_eq!(Ok(acct.available), "-0.5".parse());
+        assert_eq!(Ok(acct.held), "2.0".parse());
+
+        // the recovered log can be appended to and recovered again
+        recovered.set_wal(wal);
+        assert!(recovered.process_tx(tx(TxType::Resolve, 1, 1, None)).is_ok());
+        drop(recovered);
+
+        let mut again = Engine::new();
+        Wal::recover(&path, &mut again).unwrap();
+        assert_eq!(5, again.processed());
+        assert_eq!(Ok(again.acct_map[&1].available), "1.5".parse());
+
+        // a reason too long to log is refused rather than cut short
+        let mut wal = Wal::recover(&path, &mut Engine::new()).unwrap();
+        let len = fs::metadata(&path).unwrap().len();
+        let freeze = Tx{ tx_type: TxType::Freeze, client_id: 1, tx_id: 6, amount: None, dest: None, reason: Some("a".repeat(MAX_REASON_LEN + 1)), timestamp: None };
+        assert_eq!(ErrorKind::InvalidInput, wal.append(5, &freeze).unwrap_err().kind());
+        assert_eq!(len, fs::metadata(&path).unwrap().len());
+        drop(wal);
+
+        // replaying leaves a torn trailing record in place
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&[20, 0]).unwrap();
+        drop(file);
+        let len = fs::metadata(&path).unwrap().len();
+
+        let mut replayed = Engine::new();
+        Wal::replay(&path, &mut replayed).unwrap();
+        assert_eq!(again.acct_map, replayed.acct_map);
+        assert_eq!(len, fs::metadata(&path).unwrap().len());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn truncate() {
+        let path = temp_path("wal-truncate");
+
+        let mut engine = Engine::new();
+        engine.set_wal(Wal::create(&path).unwrap());
+        assert!(engine.process_tx(tx(TxType::Deposit, 1, 1, Some("1.0"))).is_ok());
+        engine.truncate_wal().unwrap();
+        assert_eq!(HEADER_LEN, fs::metadata(&path).unwrap().len());
+
+        // what's appended afterwards is all that's recovered
+        assert!(engine.process_tx(tx(TxType::Deposit, 1, 2, Some("2.0"))).is_ok());
+        drop(engine);
+
+        let mut recovered = Engine::new();
+        Wal::recover(&path, &mut recovered).unwrap();
+        assert_eq!(vec![2], recovered.tx_map.keys().copied().collect::<Vec<_>>());
+        assert_eq!(Ok(recovered.acct_map[&1].available), "2.0".parse());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn corrupt_record() {
+        let path = temp_path("wal-corrupt");
+
+        let mut engine = Engine::new();
+        engine.set_wal(Wal::create(&path).unwrap());
+        assert!(engine.process_tx(tx(TxType::Deposit, 1, 1, Some("1.0"))).is_ok());
+        assert!(engine.process_tx(tx(TxType::Deposit, 1, 2, Some("1.0"))).is_ok());
+        drop(engine);
+
+        // flip a bit in the amount of the last record
+        let mut data = fs::read(&path).unwrap();
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        fs::write(&path, &data).unwrap();
+
+        let mut recovered = Engine::new();
+        Wal::recover(&path, &mut recovered).unwrap();
+        assert_eq!(1, recovered.tx_map.len());
+        assert_eq!(1, recovered.processed());
+
+        // a record claiming to be longer than any record can be is torn as well
+        let len = fs::metadata(&path).unwrap().len();
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&[0xff, 0xff, 0xff, 0xff, 0, 0, 0, 0]).unwrap();
+        drop(file);
+
+        let mut recovered = Engine::new();
+        Wal::recover(&path, &mut recovered).unwrap();
+        assert_eq!(1, recovered.processed());
+        assert_eq!(len, fs::metadata(&path).unwrap().len());
+
+        fs::remove_file(&path).unwrap();
+    }
+}